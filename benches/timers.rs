@@ -0,0 +1,93 @@
+//! Benchmarks the timers of the shim, which back [`n0_future::time`] in browsers.
+//!
+//! Natively, the shim's timers run on the `LocalRuntime` of [`block_on`]. Run with
+//! `cargo bench --features shim --bench timers`.
+//!
+//! The shim keeps the timers of a thread ordered, and only schedules the earliest one
+//! with the browser's `setTimeout`, or with the executor natively. The `shim_timers`
+//! group measures these shared timers, and the `shim_timers_baseline` group the same
+//! benchmarks with [`PerSleepRuntime`], which schedules every sleep on the executor on its
+//! own, like the shim did before. As the executor keeps its timers ordered as well, this
+//! only compares the shim's own bookkeeping natively, and not the `setTimeout` and
+//! `clearTimeout` calls and callback allocations that sharing the timer saves in browsers.
+
+use std::pin::pin;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use n0_future::{
+    future::{poll_once, Boxed},
+    runtime,
+    shim::{
+        block_on,
+        time::{self, Duration, Instant},
+        PerSleepRuntime,
+    },
+};
+
+/// The number of timers per iteration.
+const TIMERS: u32 = 1000;
+
+fn timers(c: &mut Criterion) {
+    bench_timers(c, "shim_timers", block_on);
+    bench_timers(c, "shim_timers_baseline", |fut| {
+        block_on(runtime::with_runtime(PerSleepRuntime, fut))
+    });
+}
+
+/// Runs the benchmarks, running each iteration with `run`.
+fn bench_timers(c: &mut Criterion, name: &str, run: fn(Boxed<()>)) {
+    let mut group = c.benchmark_group(name);
+
+    group.bench_function("schedule_and_drop", |b| {
+        b.iter(|| {
+            run(Box::pin(async {
+                let mut sleeps = (0..TIMERS)
+                    .map(|i| Box::pin(time::sleep(Duration::from_secs(60 + u64::from(i)))))
+                    .collect::<Vec<_>>();
+                for sleep in &mut sleeps {
+                    // Schedules the timer
+                    assert!(poll_once(sleep.as_mut()).await.is_none());
+                }
+            }))
+        })
+    });
+
+    group.bench_function("reset", |b| {
+        b.iter(|| {
+            run(Box::pin(async {
+                let mut sleep = pin!(time::sleep(Duration::from_secs(60)));
+                for i in 0..TIMERS {
+                    let deadline = Instant::now() + Duration::from_secs(60 + u64::from(i));
+                    sleep.as_mut().reset(deadline);
+                    assert!(poll_once(sleep.as_mut()).await.is_none());
+                }
+            }))
+        })
+    });
+
+    group.bench_function("fire", |b| {
+        b.iter(|| {
+            run(Box::pin(async {
+                let sleeps = (0..TIMERS).map(|_| time::sleep(Duration::from_micros(200)));
+                n0_future::join_all(sleeps).await;
+            }))
+        })
+    });
+
+    group.bench_function("interval_ticks", |b| {
+        b.iter(|| {
+            run(Box::pin(async {
+                // Every tick is due already, and resets the interval's timer
+                let mut interval = time::interval(Duration::from_nanos(1));
+                for _ in 0..TIMERS {
+                    interval.tick().await;
+                }
+            }))
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, timers);
+criterion_main!(benches);
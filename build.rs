@@ -5,5 +5,9 @@ fn main() {
     cfg_aliases! {
         // Convenience aliases
         wasm_browser: { all(target_family = "wasm", target_os = "unknown") },
+        // The browser shims for `task` and `time` are compiled
+        shim: { any(wasm_browser, feature = "shim") },
+        // Tasks and timers are backed by async-executor and async-io instead of tokio
+        smol: { all(feature = "smol", not(wasm_browser)) },
     }
 }
@@ -8,14 +8,35 @@
 //!
 //! ## Feature flags
 //!
-//! * `serde`: Enables serde support for the [`time::SystemTime`] type when building for WebAssembly.
+//! * `serde`: Enables serde support for the [`time::SystemTime`] type when building for WebAssembly,
+//!   and serializing the `task::TaskDump` snapshots of the `registry` feature.
+//! * `tracing`: Enables tokio's `tracing` feature, so that tasks spawned using [`task::Builder`]
+//!   are named in tokio as well, when building with `--cfg tokio_unstable`. Also logs the slow
+//!   polls found by a `task::SlowPollDetector` without a handler as warnings.
+//! * `registry`: Keeps track of all tasks spawned through the [`task`] module, so that they
+//!   can be inspected using `task::dump`.
+//! * `metrics`: Records how often and for how long tasks spawned through the [`task`] module
+//!   are polled, available as `task::TaskMetrics` from their handles and `JoinSet`s.
+//! * `trace`: Records async stack traces of the tasks spawned through the [`task`] module,
+//!   which can be printed using `task::trace::dump`.
+//! * `shim`: Compiles the browser implementations of [`task`] and [`time`] natively, as the
+//!   `shim` module, so that their behavior can be tested without a browser.
+//! * `smol`: Adds `runtime::SmolRuntime`, which backs the [`task`] and [`time`] modules with
+//!   `async-executor`, `async-io` and `blocking` instead of tokio's runtime once installed.
+//!   Enabling it doesn't change any types or the default runtime. tokio remains a
+//!   dependency, since the native types wrap tokio's, but no tokio runtime needs to run.
 
 #![deny(missing_docs, rustdoc::broken_intra_doc_links)]
 #![cfg_attr(not(test), deny(clippy::unwrap_used))]
 #![cfg_attr(n0_future_docsrs, feature(doc_auto_cfg))]
 
 mod maybe_future;
+#[cfg(all(feature = "shim", not(wasm_browser)))]
+pub mod shim;
+#[cfg(wasm_browser)]
+mod shim;
 
+pub mod runtime;
 pub mod task;
 pub mod time;
 
@@ -0,0 +1,481 @@
+//! Pluggable runtimes for spawning tasks and waiting on timers.
+//!
+//! The [`task`] and [`time`] modules don't depend on a particular executor. Once a
+//! [`Runtime`], such as a game loop or a test scheduler, is installed globally using
+//! [`set_global`] or for a single task using [`with_runtime`], they spawn their tasks
+//! and schedule their timers on it. Their types stay the same, whichever runtime is
+//! installed.
+//!
+//! Without an installed runtime, [`task`] and [`time`] are backed by tokio directly
+//! natively, and by `WasmRuntime` in browsers. With the `smol` feature, `SmolRuntime`
+//! backs them with `async-executor` and `async-io` instead of tokio once installed.
+//!
+//! [`task`]: crate::task
+//! [`time`]: crate::time
+
+#[cfg(not(wasm_browser))]
+use std::time::Instant;
+use std::{
+    cell::RefCell,
+    fmt,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, LazyLock, OnceLock},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use futures_lite::future::BoxedLocal;
+#[cfg(wasm_browser)]
+use web_time::Instant;
+
+use crate::boxed::BoxFuture;
+
+#[cfg(smol)]
+mod smol;
+
+#[cfg(smol)]
+pub use smol::SmolRuntime;
+
+/// An executor and timer, that tasks can be spawned on.
+///
+/// Futures passed to [`Runtime::spawn`] are `Send` natively, and not `Send` in browsers,
+/// see [`BoxFuture`].
+pub trait Runtime: fmt::Debug + Send + Sync + 'static {
+    /// Spawns a future onto this runtime, driving it to completion in the background.
+    fn spawn(&self, fut: BoxFuture<()>);
+
+    /// Spawns a `!Send` future onto this runtime, driving it to completion on the current
+    /// thread.
+    fn spawn_local(&self, fut: BoxedLocal<()>);
+
+    /// Returns a future that completes once `duration` has passed.
+    ///
+    /// Dropping the future before it completes cancels the timer. If it completes early,
+    /// e.g. because the runtime's timers don't support waiting as long, the [`time`] shim
+    /// schedules another timer for the rest of the duration.
+    ///
+    /// [`time`]: crate::time
+    fn sleep(&self, duration: Duration) -> BoxFuture<()>;
+
+    /// Returns a timer that completes once `duration` has passed, and that can be moved
+    /// to another deadline using [`Timer::reset`].
+    ///
+    /// The [`time`] shim resets its timers instead of creating new ones, e.g. for every
+    /// tick of an interval. This defaults to a timer that replaces its [`Runtime::sleep`]
+    /// future with a new one when it's reset.
+    ///
+    /// [`time`]: crate::time
+    fn timer(&self, duration: Duration) -> BoxTimer {
+        Box::pin(SleepTimer {
+            sleep: Some(self.sleep(duration)),
+        })
+    }
+
+    /// Returns the current time of this runtime's clock.
+    fn now(&self) -> Instant;
+
+    /// Runs a blocking closure on a thread where blocking is acceptable.
+    ///
+    /// This backs `JoinSet::spawn_blocking`, and defaults to running the closure on a
+    /// new thread.
+    #[cfg(not(wasm_browser))]
+    fn spawn_blocking(&self, f: Box<dyn FnOnce() + Send>) {
+        std::thread::spawn(f);
+    }
+}
+
+impl<R: Runtime> Runtime for Arc<R> {
+    fn spawn(&self, fut: BoxFuture<()>) {
+        (**self).spawn(fut)
+    }
+
+    fn spawn_local(&self, fut: BoxedLocal<()>) {
+        (**self).spawn_local(fut)
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<()> {
+        (**self).sleep(duration)
+    }
+
+    fn timer(&self, duration: Duration) -> BoxTimer {
+        (**self).timer(duration)
+    }
+
+    fn now(&self) -> Instant {
+        (**self).now()
+    }
+
+    #[cfg(not(wasm_browser))]
+    fn spawn_blocking(&self, f: Box<dyn FnOnce() + Send>) {
+        (**self).spawn_blocking(f)
+    }
+}
+
+/// A timer of a [`Runtime`], which can be moved to another deadline.
+pub trait Timer: Future<Output = ()> {
+    /// Resets the timer to complete once `duration` has passed, whether or not it
+    /// completed already.
+    fn reset(self: Pin<&mut Self>, duration: Duration);
+}
+
+/// A boxed [`Timer`], which is `Send` natively and not `Send` in browsers, like
+/// [`BoxFuture`].
+#[cfg(not(wasm_browser))]
+pub type BoxTimer = Pin<Box<dyn Timer + Send + 'static>>;
+/// A boxed [`Timer`], which is `Send` natively and not `Send` in browsers, like
+/// [`BoxFuture`].
+#[cfg(wasm_browser)]
+pub type BoxTimer = Pin<Box<dyn Timer + 'static>>;
+
+/// The default [`Runtime::timer`], which waits on a [`Runtime::sleep`].
+struct SleepTimer {
+    /// The sleep, until it completes.
+    sleep: Option<BoxFuture<()>>,
+}
+
+impl Future for SleepTimer {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // The sleep might not support being polled again once it completed
+        if let Some(sleep) = &mut self.sleep {
+            futures_lite::ready!(sleep.as_mut().poll(cx));
+            self.sleep = None;
+        }
+        Poll::Ready(())
+    }
+}
+
+impl Timer for SleepTimer {
+    fn reset(mut self: Pin<&mut Self>, duration: Duration) {
+        self.sleep = Some(current().sleep(duration));
+    }
+}
+
+static GLOBAL: OnceLock<Arc<dyn Runtime>> = OnceLock::new();
+
+thread_local! {
+    /// The runtime installed for the task that's currently being polled.
+    static CURRENT: RefCell<Option<Arc<dyn Runtime>>> = const { RefCell::new(None) };
+}
+
+/// The runtime that's used when none is installed.
+static DEFAULT: LazyLock<Arc<dyn Runtime>> = LazyLock::new(|| {
+    #[cfg(not(wasm_browser))]
+    return Arc::new(TokioRuntime);
+    #[cfg(wasm_browser)]
+    Arc::new(WasmRuntime)
+});
+
+/// Returns the runtime that's installed for the current task.
+///
+/// This is the runtime installed using [`with_runtime`] for the task that's currently
+/// running, or otherwise the runtime installed using [`set_global`]. If neither is
+/// installed, this is `TokioRuntime` natively and `WasmRuntime` in browsers.
+pub fn current() -> Arc<dyn Runtime> {
+    installed().unwrap_or_else(|| DEFAULT.clone())
+}
+
+/// Returns the runtime installed using [`with_runtime`] or [`set_global`], if any.
+pub(crate) fn installed() -> Option<Arc<dyn Runtime>> {
+    CURRENT
+        .with(|current| current.borrow().clone())
+        .or_else(|| GLOBAL.get().cloned())
+}
+
+/// Installs `runtime` as the runtime for all tasks that don't run [`with_runtime`].
+///
+/// This can only be done once. Tasks and timers that were created before keep running
+/// on the runtime they were created on.
+pub fn set_global(runtime: impl Runtime) -> Result<(), GlobalRuntimeSet> {
+    GLOBAL
+        .set(Arc::new(runtime))
+        .map_err(|_| GlobalRuntimeSet(()))
+}
+
+/// Error returned by [`set_global`] if a global runtime is installed already.
+#[derive(Debug, PartialEq, Eq, derive_more::Display)]
+#[display("a global runtime is installed already")]
+pub struct GlobalRuntimeSet(());
+
+impl std::error::Error for GlobalRuntimeSet {}
+
+/// Runs `fut` with `runtime` installed as the [`current`] runtime.
+///
+/// Tasks spawned through [`crate::task`] inherit the runtime, so this installs the
+/// runtime for all tasks spawned from within `fut`, too.
+///
+/// # Example
+///
+/// ```ignore-wasm32-unknown-unknown
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// use std::sync::{
+///     atomic::{AtomicUsize, Ordering},
+///     Arc,
+/// };
+///
+/// use n0_future::{
+///     boxed::BoxFuture,
+///     future::BoxedLocal,
+///     runtime::{self, Runtime, TokioRuntime},
+///     time::Duration,
+/// };
+///
+/// /// Counts the tasks spawned on tokio.
+/// #[derive(Debug, Default)]
+/// struct Counting(AtomicUsize);
+///
+/// impl Runtime for Counting {
+///     fn spawn(&self, fut: BoxFuture<()>) {
+///         self.0.fetch_add(1, Ordering::Relaxed);
+///         TokioRuntime.spawn(fut)
+///     }
+///     fn spawn_local(&self, fut: BoxedLocal<()>) {
+///         self.0.fetch_add(1, Ordering::Relaxed);
+///         TokioRuntime.spawn_local(fut)
+///     }
+///     fn sleep(&self, duration: Duration) -> BoxFuture<()> {
+///         TokioRuntime.sleep(duration)
+///     }
+///     fn now(&self) -> std::time::Instant {
+///         TokioRuntime.now()
+///     }
+/// }
+///
+/// let counting = Arc::new(Counting::default());
+/// runtime::with_runtime(counting.clone(), async {
+///     runtime::current().spawn(Box::pin(async {}));
+/// })
+/// .await;
+/// assert_eq!(counting.0.load(Ordering::Relaxed), 1);
+/// # }
+/// ```
+pub fn with_runtime<F: Future>(runtime: impl Runtime, fut: F) -> WithRuntime<F> {
+    WithRuntime::new(Arc::new(runtime), fut)
+}
+
+/// Future returned by [`with_runtime`].
+#[derive(derive_more::Debug)]
+#[pin_project::pin_project]
+pub struct WithRuntime<F> {
+    runtime: Arc<dyn Runtime>,
+    #[debug(skip)]
+    #[pin]
+    fut: F,
+}
+
+impl<F> WithRuntime<F> {
+    pub(crate) fn new(runtime: Arc<dyn Runtime>, fut: F) -> Self {
+        Self { runtime, fut }
+    }
+}
+
+impl<F: Future> Future for WithRuntime<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let _guard = Restore(CURRENT.with(|current| current.replace(Some(this.runtime.clone()))));
+        this.fut.poll(cx)
+    }
+}
+
+/// Restores the previously installed runtime once the future was polled, even if it panics.
+struct Restore(Option<Arc<dyn Runtime>>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+/// Spawns `fut` on the [`current`] runtime, which is installed for the task as well.
+#[cfg(shim)]
+pub(crate) fn spawn_local_inherit(fut: impl Future<Output = ()> + 'static) {
+    let runtime = current();
+    runtime.spawn_local(Box::pin(WithRuntime::new(runtime.clone(), fut)));
+}
+
+/// Returns the current time of the [`current`] runtime's clock.
+pub(crate) fn now() -> Instant {
+    current().now()
+}
+
+/// The runtime backed by tokio.
+///
+/// This requires being called from within a tokio runtime, and [`Runtime::spawn_local`]
+/// requires being called from within a tokio `LocalSet`.
+#[cfg(not(wasm_browser))]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TokioRuntime;
+
+#[cfg(not(wasm_browser))]
+impl Timer for tokio::time::Sleep {
+    fn reset(self: Pin<&mut Self>, duration: Duration) {
+        self.reset(tokio::time::Instant::now() + duration);
+    }
+}
+
+#[cfg(not(wasm_browser))]
+impl Runtime for TokioRuntime {
+    fn spawn(&self, fut: BoxFuture<()>) {
+        tokio::spawn(fut);
+    }
+
+    fn spawn_local(&self, fut: BoxedLocal<()>) {
+        tokio::task::spawn_local(fut);
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+
+    fn timer(&self, duration: Duration) -> BoxTimer {
+        Box::pin(tokio::time::sleep(duration))
+    }
+
+    fn now(&self) -> Instant {
+        tokio::time::Instant::now().into_std()
+    }
+
+    fn spawn_blocking(&self, f: Box<dyn FnOnce() + Send>) {
+        tokio::task::spawn_blocking(f);
+    }
+}
+
+/// The runtime backed by `wasm_bindgen_futures` and the JavaScript `setTimeout` function.
+#[cfg(wasm_browser)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct WasmRuntime;
+
+#[cfg(wasm_browser)]
+impl Runtime for WasmRuntime {
+    fn spawn(&self, fut: BoxFuture<()>) {
+        wasm_bindgen_futures::spawn_local(fut);
+    }
+
+    fn spawn_local(&self, fut: BoxedLocal<()>) {
+        wasm_bindgen_futures::spawn_local(fut);
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<()> {
+        Box::pin(crate::shim::sleep(duration))
+    }
+
+    fn timer(&self, duration: Duration) -> BoxTimer {
+        Box::pin(crate::shim::sleep(duration))
+    }
+
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[cfg(not(wasm_browser))]
+    use tokio::test;
+    #[cfg(wasm_browser)]
+    use wasm_bindgen_test::wasm_bindgen_test as test;
+
+    use super::*;
+
+    /// Counts the tasks spawned and timers scheduled on the wrapped runtime.
+    #[derive(Debug, Clone)]
+    pub(crate) struct Counting<R> {
+        pub(crate) inner: R,
+        pub(crate) count: Arc<AtomicUsize>,
+    }
+
+    impl<R> Counting<R> {
+        pub(crate) fn new(inner: R) -> Self {
+            Self {
+                inner,
+                count: Default::default(),
+            }
+        }
+
+        pub(crate) fn count(&self) -> usize {
+            self.count.load(Ordering::SeqCst)
+        }
+    }
+
+    impl<R: Runtime> Runtime for Counting<R> {
+        fn spawn(&self, fut: BoxFuture<()>) {
+            self.count.fetch_add(1, Ordering::SeqCst);
+            self.inner.spawn(fut)
+        }
+
+        fn spawn_local(&self, fut: BoxedLocal<()>) {
+            self.count.fetch_add(1, Ordering::SeqCst);
+            self.inner.spawn_local(fut)
+        }
+
+        fn sleep(&self, duration: Duration) -> BoxFuture<()> {
+            self.count.fetch_add(1, Ordering::SeqCst);
+            self.inner.sleep(duration)
+        }
+
+        fn now(&self) -> Instant {
+            self.inner.now()
+        }
+
+        #[cfg(not(wasm_browser))]
+        fn spawn_blocking(&self, f: Box<dyn FnOnce() + Send>) {
+            self.count.fetch_add(1, Ordering::SeqCst);
+            self.inner.spawn_blocking(f)
+        }
+    }
+
+    #[cfg(not(wasm_browser))]
+    fn counting() -> Counting<TokioRuntime> {
+        Counting::new(TokioRuntime)
+    }
+
+    #[cfg(wasm_browser)]
+    fn counting() -> Counting<WasmRuntime> {
+        Counting::new(WasmRuntime)
+    }
+
+    #[test]
+    async fn with_runtime_installs_runtime() {
+        let counting = counting();
+        with_runtime(counting.clone(), async {
+            current().sleep(Duration::from_millis(1)).await;
+            let (tx, rx) = std::sync::mpsc::channel();
+            current().spawn(Box::pin(async move {
+                tx.send(()).unwrap();
+            }));
+            while rx.try_recv().is_err() {
+                crate::future::yield_now().await;
+            }
+        })
+        .await;
+        assert_eq!(counting.count(), 2);
+
+        // Outside of `with_runtime`, the runtime isn't installed anymore
+        current().sleep(Duration::from_millis(1)).await;
+        assert_eq!(counting.count(), 2);
+    }
+
+    /// The crate's tasks and timers are dispatched through the installed runtime.
+    #[test]
+    async fn task_and_time_dispatch_through_runtime() {
+        let counting = counting();
+        with_runtime(counting.clone(), async {
+            let handle = crate::task::spawn(async {
+                crate::time::sleep(Duration::from_millis(1)).await;
+                crate::task::spawn(async {}).await.unwrap();
+            });
+            handle.await.unwrap();
+        })
+        .await;
+        // Two spawns and one sleep, with the nested spawn inheriting the runtime
+        assert_eq!(counting.count(), 3);
+    }
+}
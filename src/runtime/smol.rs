@@ -0,0 +1,184 @@
+//! Implements the [`Runtime`] backed by `async-executor` and `async-io`, see
+//! [`SmolRuntime`].
+
+use std::{
+    future::Future,
+    num::NonZeroUsize,
+    pin::Pin,
+    sync::LazyLock,
+    task::{Context, Poll},
+    thread,
+    time::{Duration, Instant},
+};
+
+use async_executor::{Executor, LocalExecutor};
+use futures_lite::future::BoxedLocal;
+
+use super::{with_runtime, BoxTimer, Runtime, Timer};
+use crate::boxed::BoxFuture;
+
+/// The executor all `Send` tasks are spawned on.
+///
+/// Runs on one background thread per available CPU, which are started once the first
+/// task is spawned.
+static EXECUTOR: LazyLock<Executor<'static>> = LazyLock::new(|| {
+    let threads = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    for n in 0..threads {
+        thread::Builder::new()
+            .name(format!("n0-future-{n}"))
+            .spawn(|| async_io::block_on(EXECUTOR.run(std::future::pending::<()>())))
+            .expect("failed to spawn executor thread");
+    }
+    Executor::new()
+});
+
+thread_local! {
+    /// The executor of the `!Send` tasks of the current thread, which runs inside of
+    /// [`SmolRuntime::block_on`].
+    static LOCAL: LocalExecutor<'static> = const { LocalExecutor::new() };
+}
+
+/// The runtime backed by `async-executor` and `async-io`, for applications that don't
+/// run tokio's runtime.
+///
+/// Tasks are spawned onto a global executor, which runs on a background thread per
+/// available CPU. `!Send` tasks are spawned onto an executor local to the current thread,
+/// which only makes progress inside of [`SmolRuntime::block_on`]. Blocking tasks run on
+/// the thread pool of the `blocking` crate.
+///
+/// The runtime is installed using [`set_global`], [`with_runtime`], or for the duration of
+/// [`SmolRuntime::block_on`].
+///
+/// # Example
+///
+/// ```
+/// use n0_future::{runtime::SmolRuntime, task, time};
+///
+/// let out = SmolRuntime::block_on(async {
+///     let handle = task::spawn(async {
+///         time::sleep(time::Duration::from_millis(1)).await;
+///         21
+///     });
+///     let local = task::spawn_local(async { 2 });
+///     handle.await.unwrap() * local.await.unwrap()
+/// });
+/// assert_eq!(out, 42);
+/// ```
+///
+/// [`set_global`]: super::set_global
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SmolRuntime;
+
+impl SmolRuntime {
+    /// Runs `fut` to completion on the current thread with this runtime installed,
+    /// driving the `!Send` tasks spawned on the current thread in the meantime.
+    pub fn block_on<F: Future>(fut: F) -> F::Output {
+        LOCAL.with(|local| async_io::block_on(local.run(with_runtime(Self, fut))))
+    }
+}
+
+impl Runtime for SmolRuntime {
+    fn spawn(&self, fut: BoxFuture<()>) {
+        EXECUTOR.spawn(fut).detach();
+    }
+
+    fn spawn_local(&self, fut: BoxedLocal<()>) {
+        LOCAL.with(|local| local.spawn(fut).detach());
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<()> {
+        Box::pin(async move {
+            async_io::Timer::after(duration).await;
+        })
+    }
+
+    fn timer(&self, duration: Duration) -> BoxTimer {
+        Box::pin(SmolTimer(async_io::Timer::after(duration)))
+    }
+
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn spawn_blocking(&self, f: Box<dyn FnOnce() + Send>) {
+        blocking::unblock(f).detach();
+    }
+}
+
+/// The [`Runtime::timer`] of [`SmolRuntime`], which moves its `async-io` timer when reset.
+#[derive(Debug)]
+struct SmolTimer(async_io::Timer);
+
+impl Future for SmolTimer {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.0).poll(cx).map(drop)
+    }
+}
+
+impl Timer for SmolTimer {
+    fn reset(mut self: Pin<&mut Self>, duration: Duration) {
+        self.0.set_after(duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{task, time};
+
+    #[test]
+    fn runs_without_tokio_runtime() {
+        let out = SmolRuntime::block_on(async {
+            let handle = task::spawn(async {
+                time::sleep(Duration::from_millis(1)).await;
+                21
+            });
+            let doubled = task::spawn_local(async { 2 }).await.unwrap();
+            handle.await.unwrap() * doubled
+        });
+        assert_eq!(out, 42);
+    }
+
+    #[test]
+    fn abort_cancels_task() {
+        SmolRuntime::block_on(async {
+            let handle = task::spawn(std::future::pending::<()>());
+            handle.abort();
+            assert!(handle.await.unwrap_err().is_cancelled());
+        });
+    }
+
+    #[test]
+    fn spawn_blocking_runs_on_thread_pool() {
+        let out = SmolRuntime::block_on(async {
+            let mut set = task::JoinSet::new();
+            set.spawn_blocking(|| thread::current().id());
+            set.join_next().await.unwrap().unwrap()
+        });
+        assert_ne!(out, thread::current().id());
+    }
+
+    #[test]
+    fn join_set_abort() {
+        SmolRuntime::block_on(async {
+            let mut set = task::JoinSet::new();
+            let h1 = set.spawn(async { 22 });
+            // The executor threads might complete a ready task before it's aborted
+            let h2 = set.spawn(std::future::pending::<i32>());
+            assert!(h1.id() != h2.id());
+            h2.abort();
+
+            let mut results = Vec::new();
+            while let Some(ret) = set.join_next_with_id().await {
+                results.push(ret.map_err(|err| (err.id(), err.is_cancelled())));
+            }
+            assert_eq!(results.len(), 2);
+            assert!(results.contains(&Ok((h1.id(), 22))));
+            assert!(results.contains(&Err((h2.id(), true))));
+        });
+    }
+}
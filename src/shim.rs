@@ -0,0 +1,122 @@
+//! The runtime-independent implementations of the [`task`] and [`time`] modules.
+//!
+//! In browsers, these back [`crate::task`] and [`crate::time`], driven by
+//! `wasm_bindgen_futures` and the JavaScript `setTimeout` function.
+//!
+//! With the `shim` feature, the same implementations are available natively in here,
+//! driven by [`LocalRuntime`], a single-threaded executor and timer that run inside of
+//! [`block_on`]. Like in browsers, a different [`Runtime`] can be installed using
+//! [`runtime::with_runtime`].
+//! This makes it possible to test the browser behavior with a regular `cargo test`:
+//!
+//! ```
+//! use n0_future::shim::{block_on, task, time};
+//!
+//! let out = block_on(async {
+//!     let handle = task::spawn(async {
+//!         time::sleep(time::Duration::from_millis(1)).await;
+//!         42
+//!     });
+//!     handle.await.unwrap()
+//! });
+//! assert_eq!(out, 42);
+//! ```
+//!
+//! Tasks and timers are local to the thread that created them, and only make progress
+//! while [`block_on`] runs on that thread.
+//!
+//! [`Runtime`]: crate::runtime::Runtime
+//! [`runtime::with_runtime`]: crate::runtime::with_runtime
+
+#[cfg(not(wasm_browser))]
+#[doc(hidden)]
+pub use executor::PerSleepRuntime;
+#[cfg(not(wasm_browser))]
+pub use executor::{block_on, LocalRuntime};
+pub(crate) use timers::sleep;
+
+#[cfg(not(wasm_browser))]
+mod executor;
+mod timers;
+
+/// The shim implementation of [`crate::task`].
+#[cfg(not(wasm_browser))]
+pub mod task {
+    pub use crate::task::shim::{
+        spawn, spawn_local, AbortHandle, Id, JoinError, JoinHandle, JoinSet, LocalSet,
+    };
+}
+
+/// The shim implementation of [`crate::time`].
+#[cfg(not(wasm_browser))]
+pub mod time {
+    pub use crate::time::{
+        shim::{
+            advance, interval, interval_at, pause, resume, sleep, sleep_until, timeout, timeout_at,
+            Duration, Instant, Interval, MissedTickBehavior, Sleep, SystemTime, Timeout,
+        },
+        Elapsed,
+    };
+}
+
+#[cfg(wasm_browser)]
+mod js {
+    use wasm_bindgen::{prelude::wasm_bindgen, JsCast, JsValue};
+
+    use crate::time::Duration;
+
+    /// A timer scheduled with `setTimeout`, which is cleared when dropped.
+    #[derive(Debug)]
+    pub(super) struct Timer(JsValue);
+
+    /// The longest timeout `setTimeout` supports, about 24.8 days.
+    const MAX_TIMEOUT_MS: u128 = i32::MAX as u128;
+
+    /// Calls `callback` once `duration` has passed.
+    ///
+    /// Durations longer than [`MAX_TIMEOUT_MS`] call `callback` early, after
+    /// [`MAX_TIMEOUT_MS`], so callers need to schedule another timer for the rest.
+    pub(super) fn set_timeout(duration: Duration, callback: &js_sys::Function) -> Timer {
+        // Rounds up, to not fire before the duration has passed
+        let millis = duration.as_nanos().div_ceil(1_000_000).min(MAX_TIMEOUT_MS);
+        let timeout_id = set_timeout_js(callback, millis as i32)
+            .expect("missing setTimeout function on globalThis");
+        Timer(timeout_id)
+    }
+
+    impl Drop for Timer {
+        fn drop(&mut self) {
+            clear_timeout_js(self.0.clone()).ok();
+        }
+    }
+
+    #[wasm_bindgen]
+    extern "C" {
+        type GlobalScope;
+
+        #[wasm_bindgen(catch, method, js_name = "setTimeout")]
+        fn set_timeout_with_callback_and_timeout_and_arguments_0(
+            this: &GlobalScope,
+            handler: &js_sys::Function,
+            timeout: i32,
+        ) -> Result<JsValue, JsValue>;
+
+        #[wasm_bindgen(catch, method, js_name = "clearTimeout")]
+        fn clear_timeout_with_handle(
+            this: &GlobalScope,
+            timeout_id: JsValue,
+        ) -> Result<(), JsValue>;
+    }
+
+    fn set_timeout_js(handler: &js_sys::Function, timeout: i32) -> Result<JsValue, JsValue> {
+        let global_this = js_sys::global();
+        let global_scope = global_this.unchecked_ref::<GlobalScope>();
+        global_scope.set_timeout_with_callback_and_timeout_and_arguments_0(handler, timeout)
+    }
+
+    fn clear_timeout_js(timeout_id: JsValue) -> Result<(), JsValue> {
+        let global_this = js_sys::global();
+        let global_scope = global_this.unchecked_ref::<GlobalScope>();
+        global_scope.clear_timeout_with_handle(timeout_id)
+    }
+}
@@ -0,0 +1,388 @@
+//! A single-threaded executor and timer, standing in for the browser's event loop
+//! when the shim is compiled natively.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, HashMap, VecDeque},
+    future::Future,
+    pin::pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+    time::{Duration, Instant},
+};
+
+use futures_lite::future::BoxedLocal;
+use futures_util::task::AtomicWaker;
+
+use crate::{
+    boxed::BoxFuture,
+    runtime::{BoxTimer, Runtime, WithRuntime},
+};
+
+type LocalFuture = BoxedLocal<()>;
+type Callback = Box<dyn FnOnce()>;
+
+thread_local! {
+    static EXECUTOR: Executor = Executor::new();
+}
+
+/// The tasks and timers of a single thread.
+struct Executor {
+    tasks: RefCell<HashMap<u64, (LocalFuture, Arc<TaskWaker>)>>,
+    next_task: Cell<u64>,
+    queue: Arc<Queue>,
+    timers: RefCell<BTreeMap<(Instant, u64), Callback>>,
+    next_timer: Cell<u64>,
+}
+
+/// The tasks that were woken up, shared with their wakers.
+#[derive(Debug)]
+struct Queue {
+    ready: Mutex<VecDeque<u64>>,
+    thread: Thread,
+}
+
+impl Queue {
+    fn push(&self, id: u64) {
+        self.ready.lock().expect("poisoned").push_back(id);
+        self.thread.unpark();
+    }
+
+    fn is_empty(&self) -> bool {
+        self.ready.lock().expect("poisoned").is_empty()
+    }
+}
+
+/// Wakes up a task by putting it onto the queue, unless it's already queued.
+#[derive(Debug)]
+struct TaskWaker {
+    id: u64,
+    queued: AtomicBool,
+    queue: Arc<Queue>,
+}
+
+impl Wake for TaskWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if !self.queued.swap(true, Ordering::SeqCst) {
+            self.queue.push(self.id);
+        }
+    }
+}
+
+/// Wakes up the future passed to [`block_on`].
+#[derive(Debug)]
+struct MainWaker {
+    woken: AtomicBool,
+    thread: Thread,
+}
+
+impl Wake for MainWaker {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::SeqCst);
+        self.thread.unpark();
+    }
+}
+
+impl Executor {
+    fn new() -> Self {
+        Self {
+            tasks: Default::default(),
+            next_task: Cell::new(0),
+            queue: Arc::new(Queue {
+                ready: Default::default(),
+                thread: thread::current(),
+            }),
+            timers: Default::default(),
+            next_timer: Cell::new(0),
+        }
+    }
+
+    /// Polls all tasks that were woken up since the last call.
+    ///
+    /// Returns whether any task was polled.
+    fn run_ready(&self) -> bool {
+        let ready = std::mem::take(&mut *self.queue.ready.lock().expect("poisoned"));
+        let polled = !ready.is_empty();
+        for id in ready {
+            // The task is removed while it's polled, so it can spawn other tasks
+            let Some((mut fut, waker)) = self.tasks.borrow_mut().remove(&id) else {
+                continue;
+            };
+            waker.queued.store(false, Ordering::SeqCst);
+            let cx_waker = Waker::from(waker.clone());
+            let mut cx = Context::from_waker(&cx_waker);
+            if fut.as_mut().poll(&mut cx).is_pending() {
+                self.tasks.borrow_mut().insert(id, (fut, waker));
+            }
+        }
+        polled
+    }
+
+    /// Calls the callbacks of all timers that expired.
+    ///
+    /// Returns the deadline of the next timer that's still pending.
+    fn fire_timers(&self) -> Option<Instant> {
+        loop {
+            let now = Instant::now();
+            let callback = {
+                let mut timers = self.timers.borrow_mut();
+                let entry = timers.first_entry()?;
+                if entry.key().0 > now {
+                    return Some(entry.key().0);
+                }
+                entry.remove()
+            };
+            callback();
+        }
+    }
+}
+
+/// The runtime driven by [`block_on`], which runs all tasks and timers on the thread
+/// that spawned or scheduled them.
+///
+/// This is installed as the current runtime for the future passed to [`block_on`].
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LocalRuntime;
+
+impl Runtime for LocalRuntime {
+    fn spawn(&self, fut: BoxFuture<()>) {
+        spawn_local(fut);
+    }
+
+    fn spawn_local(&self, fut: BoxedLocal<()>) {
+        spawn_local(fut);
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<()> {
+        Box::pin(super::sleep(duration))
+    }
+
+    fn timer(&self, duration: Duration) -> BoxTimer {
+        Box::pin(super::sleep(duration))
+    }
+
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A [`LocalRuntime`] that schedules every sleep on the executor on its own, like the
+/// shim did before its sleeps shared a single scheduled timer.
+///
+/// This is only meant as the baseline of the timer benchmarks.
+#[doc(hidden)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PerSleepRuntime;
+
+impl Runtime for PerSleepRuntime {
+    fn spawn(&self, fut: BoxFuture<()>) {
+        spawn_local(fut);
+    }
+
+    fn spawn_local(&self, fut: BoxedLocal<()>) {
+        spawn_local(fut);
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<()> {
+        let fired = Arc::new((AtomicBool::new(false), AtomicWaker::new()));
+        let timer = set_timeout(duration, {
+            let fired = fired.clone();
+            Box::new(move || {
+                fired.0.store(true, Ordering::Relaxed);
+                fired.1.wake();
+            })
+        });
+        Box::pin(std::future::poll_fn(move |cx| {
+            // Dropping the future cancels the timer
+            let _timer = &timer;
+            fired.1.register(cx.waker());
+            if fired.0.load(Ordering::Relaxed) {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        }))
+    }
+
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// Spawns a future onto the executor of the current thread.
+fn spawn_local(fut: LocalFuture) {
+    EXECUTOR.with(|executor| {
+        let id = executor.next_task.get();
+        executor.next_task.set(id + 1);
+        let waker = Arc::new(TaskWaker {
+            id,
+            queued: AtomicBool::new(true),
+            queue: executor.queue.clone(),
+        });
+        executor.tasks.borrow_mut().insert(id, (fut, waker));
+        executor.queue.push(id);
+    });
+}
+
+/// A timer scheduled on the current thread, which is cancelled when dropped.
+#[derive(Debug)]
+pub(super) struct Timer(Option<(Instant, u64)>);
+
+/// Calls `callback` on the current thread once `duration` has passed.
+pub(super) fn set_timeout(duration: Duration, callback: Callback) -> Timer {
+    // A deadline that isn't representable is never reached
+    let Some(deadline) = Instant::now().checked_add(duration) else {
+        return Timer(None);
+    };
+    EXECUTOR.with(|executor| {
+        let key = (deadline, executor.next_timer.get());
+        executor.next_timer.set(key.1 + 1);
+        executor.timers.borrow_mut().insert(key, callback);
+        Timer(Some(key))
+    })
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        if let Some(key) = self.0 {
+            // The executor is already gone when the timer is dropped during thread teardown
+            EXECUTOR
+                .try_with(|executor| executor.timers.borrow_mut().remove(&key))
+                .ok();
+        }
+    }
+}
+
+/// Runs a future to completion on the current thread, while driving all tasks and timers
+/// of the shim on this thread.
+///
+/// The future runs with [`LocalRuntime`] installed as the current runtime.
+///
+/// Tasks that are still running when the future completes are suspended until
+/// `block_on` is called again on the same thread.
+///
+/// # Panics
+///
+/// Panics when called from within a task or future that's driven by `block_on`.
+pub fn block_on<F: Future>(fut: F) -> F::Output {
+    thread_local! {
+        static RUNNING: Cell<bool> = const { Cell::new(false) };
+    }
+    struct Running;
+    impl Drop for Running {
+        fn drop(&mut self) {
+            RUNNING.with(|running| running.set(false));
+        }
+    }
+    assert!(
+        !RUNNING.with(|running| running.replace(true)),
+        "block_on can't be called from within block_on"
+    );
+    let _running = Running;
+
+    let mut fut = pin!(WithRuntime::new(Arc::new(LocalRuntime), fut));
+    let main = Arc::new(MainWaker {
+        woken: AtomicBool::new(true),
+        thread: thread::current(),
+    });
+    let waker = Waker::from(main.clone());
+    let mut cx = Context::from_waker(&waker);
+
+    EXECUTOR.with(|executor| loop {
+        if main.woken.swap(false, Ordering::SeqCst) {
+            if let Poll::Ready(out) = fut.as_mut().poll(&mut cx) {
+                return out;
+            }
+        }
+        let polled = executor.run_ready();
+        let next_timer = executor.fire_timers();
+        if polled || main.woken.load(Ordering::SeqCst) || !executor.queue.is_empty() {
+            continue;
+        }
+        // Wakers unpark this thread, which makes parking return immediately if a
+        // wakeup happened in the meantime.
+        match next_timer {
+            Some(deadline) => {
+                thread::park_timeout(deadline.saturating_duration_since(Instant::now()))
+            }
+            None => thread::park(),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use futures_util::task::AtomicWaker;
+
+    use super::*;
+    use crate::{
+        runtime::{tests::Counting, with_runtime},
+        shim,
+    };
+
+    #[test]
+    fn block_on_woken_from_other_thread() {
+        let shared = Arc::new((AtomicWaker::new(), AtomicBool::new(false)));
+        thread::spawn({
+            let shared = shared.clone();
+            move || {
+                thread::sleep(Duration::from_millis(5));
+                shared.1.store(true, Ordering::SeqCst);
+                shared.0.wake();
+            }
+        });
+        block_on(std::future::poll_fn(|cx| {
+            shared.0.register(cx.waker());
+            if shared.1.load(Ordering::SeqCst) {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        }));
+    }
+
+    #[test]
+    fn timer_cancelled_on_drop() {
+        let fired = Rc::new(Cell::new(0));
+        let cancelled = set_timeout(Duration::ZERO, {
+            let fired = fired.clone();
+            Box::new(move || fired.set(fired.get() + 1))
+        });
+        let _timer = set_timeout(Duration::ZERO, {
+            let fired = fired.clone();
+            Box::new(move || fired.set(fired.get() + 10))
+        });
+        drop(cancelled);
+
+        EXECUTOR.with(|executor| assert_eq!(executor.fire_timers(), None));
+        assert_eq!(fired.get(), 10);
+    }
+
+    #[test]
+    fn shim_dispatches_through_runtime() {
+        let counting = Counting::new(LocalRuntime);
+        block_on(with_runtime(counting.clone(), async {
+            let handle = shim::task::spawn(async {
+                shim::time::sleep(Duration::from_millis(1)).await;
+                shim::task::spawn(async {}).await.unwrap();
+            });
+            handle.await.unwrap();
+        }));
+        // Two spawns and one sleep, with the nested spawn inheriting the runtime
+        assert_eq!(counting.count(), 3);
+    }
+}
@@ -0,0 +1,228 @@
+//! The timers of the shim, which share a single timer of the browser or of the
+//! [`LocalRuntime`].
+//!
+//! The pending timers of a thread are ordered by their deadline, and only the earliest one
+//! is scheduled with `setTimeout` in browsers, or on the executor natively. Creating,
+//! resetting and dropping a timer thus usually only updates the ordered timers, without
+//! allocating a callback or calling into JavaScript.
+//!
+//! [`LocalRuntime`]: super::LocalRuntime
+
+#[cfg(not(wasm_browser))]
+use std::time::Instant;
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+#[cfg(wasm_browser)]
+use wasm_bindgen::{closure::Closure, JsCast};
+#[cfg(wasm_browser)]
+use web_time::Instant;
+
+use crate::runtime::Timer;
+
+thread_local! {
+    static DRIVER: RefCell<Driver> = RefCell::new(Driver::new());
+}
+
+/// The timer scheduled for the earliest deadline.
+#[cfg(wasm_browser)]
+type Scheduled = super::js::Timer;
+#[cfg(not(wasm_browser))]
+type Scheduled = super::executor::Timer;
+
+/// The pending timers of a thread.
+#[derive(Debug)]
+struct Driver {
+    /// The wakers of the pending timers by deadline and id, which are `None` until the
+    /// timer is polled. Timers are removed once they fire.
+    timers: BTreeMap<(Instant, u64), Option<Waker>>,
+    next_id: u64,
+    /// The timer that's scheduled, along with its deadline.
+    scheduled: Option<(Instant, Scheduled)>,
+    /// The callback passed to `setTimeout`, which is shared by all timers.
+    #[cfg(wasm_browser)]
+    callback: Closure<dyn FnMut()>,
+}
+
+impl Driver {
+    fn new() -> Self {
+        Self {
+            timers: BTreeMap::new(),
+            next_id: 0,
+            scheduled: None,
+            #[cfg(wasm_browser)]
+            callback: Closure::new(fire),
+        }
+    }
+
+    fn insert(&mut self, deadline: Instant, waker: Option<Waker>) -> (Instant, u64) {
+        let key = (deadline, self.next_id);
+        self.next_id += 1;
+        self.timers.insert(key, waker);
+        if self.scheduled.as_ref().is_none_or(|(at, _)| deadline < *at) {
+            self.schedule(deadline);
+        }
+        key
+    }
+
+    /// Schedules the timer for `deadline`, cancelling the previously scheduled one.
+    fn schedule(&mut self, deadline: Instant) {
+        let duration = deadline.saturating_duration_since(Instant::now());
+        #[cfg(wasm_browser)]
+        let timer = super::js::set_timeout(duration, self.callback.as_ref().unchecked_ref());
+        #[cfg(not(wasm_browser))]
+        let timer = super::executor::set_timeout(duration, Box::new(fire));
+        self.scheduled = Some((deadline, timer));
+    }
+}
+
+/// Fires the timers that are due, and schedules the timer for the next one.
+fn fire() {
+    let due = DRIVER.with_borrow_mut(|driver| {
+        driver.scheduled = None;
+        let now = Instant::now();
+        let mut due = Vec::new();
+        while let Some(entry) = driver.timers.first_entry() {
+            if entry.key().0 > now {
+                // `setTimeout` fires early for long durations, so this can reschedule
+                // the same deadline
+                let next = entry.key().0;
+                driver.schedule(next);
+                break;
+            }
+            due.extend(entry.remove());
+        }
+        due
+    });
+    // Wakes outside of the borrow, in case that the waker polls the timer right away
+    due.into_iter().for_each(Waker::wake);
+}
+
+/// Returns a future that completes once `duration` has passed, using the timers of the
+/// browser or of the [`LocalRuntime`].
+///
+/// [`LocalRuntime`]: super::LocalRuntime
+pub(crate) fn sleep(duration: Duration) -> TimerFuture {
+    // A deadline that isn't representable is never reached
+    let key = Instant::now()
+        .checked_add(duration)
+        .map(|deadline| DRIVER.with_borrow_mut(|driver| driver.insert(deadline, None)));
+    TimerFuture { key }
+}
+
+/// Future returned by [`sleep`], which cancels its timer when dropped.
+#[derive(Debug)]
+pub(crate) struct TimerFuture {
+    key: Option<(Instant, u64)>,
+}
+
+impl Future for TimerFuture {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let Some(key) = self.key else {
+            return Poll::Pending;
+        };
+        DRIVER.with_borrow_mut(|driver| match driver.timers.get_mut(&key) {
+            Some(Some(waker)) => {
+                waker.clone_from(cx.waker());
+                Poll::Pending
+            }
+            Some(waker) => {
+                *waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            // The timer fired
+            None => Poll::Ready(()),
+        })
+    }
+}
+
+impl Timer for TimerFuture {
+    fn reset(mut self: Pin<&mut Self>, duration: Duration) {
+        let deadline = Instant::now().checked_add(duration);
+        DRIVER.with_borrow_mut(|driver| {
+            // Moves the timer's entry to the new deadline, keeping its waker
+            let waker = self
+                .key
+                .and_then(|key| driver.timers.remove(&key))
+                .flatten();
+            self.key = deadline.map(|deadline| driver.insert(deadline, waker));
+        })
+    }
+}
+
+impl Drop for TimerFuture {
+    fn drop(&mut self) {
+        // The scheduled timer is left as is if this was the earliest, as it's cheaper to
+        // have it fire for nothing than to reschedule it.
+        if let Some(key) = self.key {
+            // The driver is already gone when the timer is dropped during thread teardown
+            DRIVER
+                .try_with(|driver| driver.borrow_mut().timers.remove(&key))
+                .ok();
+        }
+    }
+}
+
+#[cfg(all(test, not(wasm_browser)))]
+mod tests {
+    use std::pin::pin;
+
+    use super::*;
+    use crate::shim::block_on;
+
+    fn pending() -> usize {
+        DRIVER.with_borrow(|driver| driver.timers.len())
+    }
+
+    fn scheduled() -> Option<Instant> {
+        DRIVER.with_borrow(|driver| driver.scheduled.as_ref().map(|(at, _)| *at))
+    }
+
+    #[test]
+    fn timers_share_the_scheduled_timer() {
+        let later = sleep(Duration::from_secs(60));
+        let first = scheduled();
+        assert!(first.is_some());
+
+        // Only an earlier timer replaces the scheduled one
+        let sooner = sleep(Duration::from_millis(5));
+        let second = scheduled();
+        assert!(second < first);
+        let _latest = sleep(Duration::from_secs(120));
+        assert_eq!(scheduled(), second);
+        assert_eq!(pending(), 3);
+
+        drop(later);
+        assert_eq!(pending(), 2);
+        block_on(sooner);
+        assert_eq!(pending(), 1);
+        // The next timer is scheduled once the earliest fired
+        assert!(scheduled() > second);
+    }
+
+    #[test]
+    fn reset_moves_the_timer() {
+        let mut timer = pin!(sleep(Duration::from_secs(60)));
+        let later = scheduled();
+        timer.as_mut().reset(Duration::from_millis(5));
+        // The timer's entry moved, and the earlier deadline is scheduled
+        assert_eq!(pending(), 1);
+        assert!(scheduled() < later);
+        block_on(timer.as_mut());
+        assert_eq!(pending(), 0);
+
+        // Timers that fired already can be reset as well
+        timer.as_mut().reset(Duration::from_millis(5));
+        assert_eq!(pending(), 1);
+        block_on(timer);
+        assert_eq!(pending(), 0);
+    }
+}
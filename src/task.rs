@@ -1,625 +1,403 @@
 //! Async rust task spawning and utilities that work natively (using tokio) and in browsers
 //! (using wasm-bindgen-futures).
+//!
+//! Natively, the types in here are thin wrappers around their tokio counterparts, which
+//! additionally keep track of task metadata such as the task's name.
 
+#[cfg(wasm_browser)]
+use std::future::IntoFuture;
+use std::{
+    future::Future,
+    panic::Location,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+pub use bounded::BoundedJoinSet;
+pub use cancel::{
+    spawn_cancellable, CancellationToken, DropGuard, DropGuardRef, WaitForCancellationFuture,
+    WaitForCancellationFutureOwned,
+};
+pub use join_all::TryJoinError;
+pub use join_map::JoinMap;
+#[cfg(feature = "metrics")]
+pub use metrics::TaskMetrics;
 #[cfg(not(wasm_browser))]
-pub use tokio::spawn;
-#[cfg(not(wasm_browser))]
-pub use tokio::task::{AbortHandle, Id, JoinError, JoinHandle, JoinSet};
+use native as backend;
 #[cfg(not(wasm_browser))]
-pub use tokio_util::task::AbortOnDropHandle;
-#[cfg(wasm_browser)]
-pub use wasm::*;
-
+pub use native::*;
+use panic::panic_message;
+pub use panic::{panic_policy, set_panic_policy, PanicPolicy};
+#[cfg(feature = "registry")]
+pub use registry::{dump, TaskDump, TaskInfo, TaskState};
+pub use scope::{scope, Scope, ScopeBuilder, ScopeError};
 #[cfg(wasm_browser)]
-mod wasm {
-    use std::{
-        cell::RefCell,
-        fmt::{self, Debug},
-        future::{Future, IntoFuture},
-        pin::Pin,
-        rc::Rc,
-        sync::Mutex,
-        task::{Context, Poll, Waker},
-    };
-
-    use futures_lite::{stream::StreamExt, FutureExt};
-    use send_wrapper::SendWrapper;
-
-    static TASK_ID_COUNTER: Mutex<u64> = Mutex::new(0);
+pub use shim::*;
+pub use shutdown::ShutdownOutcome;
+pub use slow_poll::{
+    DetectSlowPolls, SlowPoll, SlowPollDetector, SlowPollDetectorSet, SlowPollExt,
+};
+pub use supervisor::{RestartPolicy, RestartStrategy, Supervisor, SupervisorEvent};
+
+mod bounded;
+mod cancel;
+mod join_all;
+mod join_map;
+#[cfg(feature = "metrics")]
+mod metrics;
+#[cfg(not(wasm_browser))]
+mod native;
+mod panic;
+#[cfg(feature = "registry")]
+mod registry;
+mod scope;
+#[cfg(shim)]
+#[cfg_attr(not(wasm_browser), allow(dead_code))]
+pub(crate) mod shim;
+mod shutdown;
+mod slow_poll;
+mod supervisor;
+#[cfg(feature = "trace")]
+pub mod trace;
+
+/// Factory which is used to configure the properties of a new task.
+///
+/// Mirrors `tokio::task::Builder`, but is available without `tokio_unstable` and in browsers.
+/// When building with `--cfg tokio_unstable` and the `tracing` feature enabled, tasks
+/// are named using tokio's builder, too.
+///
+/// # Example
+///
+/// ```ignore-wasm32-unknown-unknown
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// use n0_future::task;
+///
+/// let handle = task::Builder::new()
+///     .name("my-task")
+///     .spawn(async { 42 });
+/// assert_eq!(handle.name(), Some("my-task"));
+/// assert_eq!(handle.await.unwrap(), 42);
+/// # }
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct Builder<'a> {
+    name: Option<&'a str>,
+}
 
-    fn next_task_id() -> u64 {
-        let mut counter = TASK_ID_COUNTER.lock().unwrap();
-        *counter += 1;
-        *counter
+impl<'a> Builder<'a> {
+    /// Creates a new task builder.
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    /// An opaque ID that uniquely identifies a task relative to all other currently running tasks.
-    #[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, derive_more::Display)]
-    pub struct Id(u64);
-
-    /// Wasm shim for tokio's `JoinSet`.
-    ///
-    /// Uses a [`futures_buffered::FuturesUnordered`] queue of
-    /// [`JoinHandle`]s inside.
-    pub struct JoinSet<T> {
-        handles: futures_buffered::FuturesUnordered<JoinHandleWithId<T>>,
-        // We need to keep a second list of JoinHandles so we can access them for cancellation
-        to_cancel: Vec<JoinHandle<T>>,
+    /// Assigns a name to the task which will be spawned.
+    pub fn name(self, name: &'a str) -> Self {
+        Self { name: Some(name) }
     }
 
-    impl<T> Debug for JoinSet<T> {
-        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-            f.debug_struct("JoinSet").field("len", &self.len()).finish()
-        }
+    /// Spawns a task with this builder's settings, returning a [`JoinHandle`] for it.
+    ///
+    /// See [`spawn`].
+    #[cfg(not(wasm_browser))]
+    #[track_caller]
+    pub fn spawn<F>(self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        backend::spawn_with_meta(TaskMeta::new(self.name), future)
     }
 
-    impl<T> Default for JoinSet<T> {
-        fn default() -> Self {
-            Self::new()
-        }
+    /// Spawns a task with this builder's settings, returning a [`JoinHandle`] for it.
+    ///
+    /// See [`spawn`].
+    #[cfg(wasm_browser)]
+    #[track_caller]
+    pub fn spawn<T: 'static>(self, fut: impl IntoFuture<Output = T> + 'static) -> JoinHandle<T> {
+        shim::spawn_with_meta(TaskMeta::new(self.name), fut)
     }
 
-    impl<T> JoinSet<T> {
-        /// Creates a new, empty `JoinSet`
-        pub fn new() -> Self {
-            Self {
-                handles: futures_buffered::FuturesUnordered::new(),
-                to_cancel: Vec::new(),
-            }
-        }
-
-        /// Spawns a task into this `JoinSet`.
-        pub fn spawn(&mut self, fut: impl IntoFuture<Output = T> + 'static) -> AbortHandle
-        where
-            T: 'static,
-        {
-            let handle = JoinHandle::new();
-            let state = handle.task.state.clone();
-            let handle_for_spawn = JoinHandle {
-                task: handle.task.clone(),
-            };
-            let handle_for_cancel = JoinHandle {
-                task: handle.task.clone(),
-            };
-
-            wasm_bindgen_futures::spawn_local(SpawnFuture {
-                handle: handle_for_spawn,
-                fut: fut.into_future(),
-            });
-
-            self.handles.push(JoinHandleWithId(handle));
-            self.to_cancel.push(handle_for_cancel);
-            AbortHandle { state }
-        }
-
-        /// Alias to [`Self::spawn`].
-        ///
-        /// Mirrors [`tokio::JoinSet::spawn_local](https://docs.rs/tokio/latest/tokio/task/struct.JoinSet.html#method.spawn_local).
-        /// Because all tasks in WebAssembly are local, this is a simple alias to [`Self::spawn`].
-        pub fn spawn_local(&mut self, fut: impl IntoFuture<Output = T> + 'static) -> AbortHandle
-        where
-            T: 'static,
-        {
-            self.spawn(fut)
-        }
-
-        /// Aborts all tasks inside this `JoinSet`
-        pub fn abort_all(&self) {
-            self.to_cancel.iter().for_each(JoinHandle::abort);
-        }
-
-        /// Awaits the next `JoinSet`'s completion.
-        ///
-        /// If you `.spawn` a new task onto this `JoinSet` while the future
-        /// returned from this is currently pending, then this future will
-        /// continue to be pending, even if the newly spawned future is already
-        /// finished.
-        ///
-        /// TODO(matheus23): Fix this limitation.
-        ///
-        /// Current work around is to recreate the `join_next` future when
-        /// you newly spawned a task onto it. This seems to be the usual way
-        /// the `JoinSet` is used *most of the time* in the iroh codebase anyways.
-        pub async fn join_next(&mut self) -> Option<Result<T, JoinError>> {
-            self.join_next_with_id()
-                .await
-                .map(|ret| ret.map(|(_id, out)| out))
-        }
-
-        /// Waits until one of the tasks in the set completes and returns its
-        /// output, along with the [task ID] of the completed task.
-        ///
-        /// Returns `None` if the set is empty.
-        ///
-        /// When this method returns an error, then the id of the task that failed can be accessed
-        /// using the [`JoinError::id`] method.
-        ///
-        /// [task ID]: crate::task::Id
-        /// [`JoinError::id`]: fn@crate::task::JoinError::id
-        pub async fn join_next_with_id(&mut self) -> Option<Result<(Id, T), JoinError>> {
-            futures_lite::future::poll_fn(|cx| self.poll_join_next_with_id(cx)).await
-        }
-
-        /// Polls for one of the tasks in the set to complete.
-        ///
-        /// If this returns `Poll::Ready(Some(_))`, then the task that completed is removed from the set.
-        ///
-        /// When the method returns `Poll::Pending`, the `Waker` in the provided `Context` is scheduled
-        /// to receive a wakeup when a task in the `JoinSet` completes. Note that on multiple calls to
-        /// `poll_join_next`, only the `Waker` from the `Context` passed to the most recent call is
-        /// scheduled to receive a wakeup.
-        ///
-        /// # Returns
-        ///
-        /// This function returns:
-        ///
-        ///  * `Poll::Pending` if the `JoinSet` is not empty but there is no task whose output is
-        ///    available right now.
-        ///  * `Poll::Ready(Some(Ok(value)))` if one of the tasks in this `JoinSet` has completed.
-        ///    The `value` is the return value of one of the tasks that completed.
-        ///  * `Poll::Ready(Some(Err(err)))` if one of the tasks in this `JoinSet` has panicked or been
-        ///    aborted. The `err` is the `JoinError` from the panicked/aborted task.
-        ///  * `Poll::Ready(None)` if the `JoinSet` is empty.
-        pub fn poll_join_next(
-            &mut self,
-            cx: &mut Context<'_>,
-        ) -> Poll<Option<Result<T, JoinError>>> {
-            match self.poll_join_next_with_id(cx) {
-                Poll::Ready(Some(Ok((_, ret)))) => Poll::Ready(Some(Ok(ret))),
-                Poll::Ready(Some(Err(e))) => Poll::Ready(Some(Err(e))),
-                Poll::Ready(None) => Poll::Ready(None),
-                Poll::Pending => Poll::Pending,
-            }
-        }
-
-        /// Polls for one of the tasks in the set to complete.
-        ///
-        /// If this returns `Poll::Ready(Some(_))`, then the task that completed is removed from the set.
-        ///
-        /// When the method returns `Poll::Pending`, the `Waker` in the provided `Context` is scheduled
-        /// to receive a wakeup when a task in the `JoinSet` completes. Note that on multiple calls to
-        /// `poll_join_next`, only the `Waker` from the `Context` passed to the most recent call is
-        /// scheduled to receive a wakeup.
-        ///
-        /// # Returns
-        ///
-        /// This function returns:
-        ///
-        ///  * `Poll::Pending` if the `JoinSet` is not empty but there is no task whose output is
-        ///    available right now.
-        ///  * `Poll::Ready(Some(Ok((id, value))))` if one of the tasks in this `JoinSet` has completed.
-        ///    The `value` is the return value of one of the tasks that completed, and
-        ///    `id` is the [task ID] of that task.
-        ///  * `Poll::Ready(Some(Err(err)))` if one of the tasks in this `JoinSet` has panicked or been
-        ///    aborted. The `err` is the `JoinError` from the panicked/aborted task.
-        ///  * `Poll::Ready(None)` if the `JoinSet` is empty.
-        ///
-        /// [task ID]: crate::task::Id
-        pub fn poll_join_next_with_id(
-            &mut self,
-            cx: &mut Context<'_>,
-        ) -> Poll<Option<Result<(Id, T), JoinError>>> {
-            let ret = self.handles.poll_next(cx);
-            // clean up handles that are either cancelled or have finished
-            self.to_cancel.retain(JoinHandle::is_running);
-            ret
-        }
-
-        /// Returns whether there's any tasks that are either still running or
-        /// have pending results in this `JoinSet`.
-        pub fn is_empty(&self) -> bool {
-            self.handles.is_empty()
-        }
-
-        /// Returns the amount of tasks that are either still running or have
-        /// pending results in this `JoinSet`.
-        pub fn len(&self) -> usize {
-            self.handles.len()
-        }
-
-        /// Waits for all tasks to finish. If any of them returns a JoinError,
-        /// this will panic.
-        pub async fn join_all(mut self) -> Vec<T> {
-            let mut output = Vec::new();
-            while let Some(res) = self.join_next().await {
-                match res {
-                    Ok(t) => output.push(t),
-                    Err(err) => panic!("{err}"),
-                }
-            }
-            output
-        }
-
-        /// Aborts all tasks and then waits for them to finish, ignoring panics.
-        pub async fn shutdown(&mut self) {
-            self.abort_all();
-            while let Some(_res) = self.join_next().await {}
-        }
+    /// Spawns a `!Send` task on the current [`LocalSet`] with this builder's settings,
+    /// returning a [`JoinHandle`] for it.
+    ///
+    /// See [`spawn_local`].
+    #[cfg(not(wasm_browser))]
+    #[track_caller]
+    pub fn spawn_local<F>(self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        backend::spawn_local_with_meta(TaskMeta::new(self.name), future)
     }
 
-    impl<T> Drop for JoinSet<T> {
-        fn drop(&mut self) {
-            self.abort_all()
-        }
+    /// Spawns a `!Send` task on the current [`LocalSet`] with this builder's settings,
+    /// returning a [`JoinHandle`] for it.
+    ///
+    /// See [`spawn_local`].
+    #[cfg(wasm_browser)]
+    #[track_caller]
+    pub fn spawn_local<T: 'static>(
+        self,
+        fut: impl IntoFuture<Output = T> + 'static,
+    ) -> JoinHandle<T> {
+        shim::spawn_with_meta(TaskMeta::new(self.name), fut)
     }
+}
 
-    /// A handle to a spawned task.
-    pub struct JoinHandle<T> {
-        task: Task<T>,
-    }
+/// Metadata recorded for every spawned task.
+#[derive(Clone)]
+struct TaskMeta {
+    name: Option<Arc<str>>,
+    location: &'static Location<'static>,
+    /// The deadline the task inherits from the future that spawned it.
+    deadline: Option<crate::time::Instant>,
+    #[cfg(feature = "registry")]
+    entry: Arc<registry::Entry>,
+    #[cfg(feature = "metrics")]
+    metrics: Arc<metrics::Recorder>,
+    #[cfg(feature = "trace")]
+    trace: Arc<trace::Task>,
+}
 
-    struct Task<T> {
-        // Using SendWrapper here is safe as long as you keep all of your
-        // work on the main UI worker in the browser.
-        // The only exception to that being the case would be if our user
-        // would use multiple Wasm instances with a single SharedArrayBuffer,
-        // put the instances on different Web Workers and finally shared
-        // the JoinHandle across the Web Worker boundary.
-        // In that case, using the JoinHandle would panic.
-        state: SendWrapper<Rc<RefCell<State>>>,
-        result: SendWrapper<Rc<RefCell<Option<T>>>>,
+impl TaskMeta {
+    /// Creates the metadata for a task spawned at the caller's location.
+    #[track_caller]
+    fn new(name: Option<&str>) -> Self {
+        Self::at(name, Location::caller())
     }
 
-    impl<T> Clone for Task<T> {
-        fn clone(&self) -> Self {
-            Self {
-                state: self.state.clone(),
-                result: self.result.clone(),
-            }
+    /// Creates the metadata for a task spawned at `location`.
+    ///
+    /// This is for utilities that can't be `#[track_caller]` all the way down to the spawn,
+    /// such as async functions and stored closures.
+    fn at(name: Option<&str>, location: &'static Location<'static>) -> Self {
+        let name = name.map(Arc::from);
+        Self {
+            #[cfg(feature = "registry")]
+            entry: registry::Entry::register(name.clone(), location),
+            #[cfg(feature = "metrics")]
+            metrics: Default::default(),
+            #[cfg(feature = "trace")]
+            trace: trace::Task::register(name.clone(), location),
+            name,
+            location,
+            deadline: crate::time::current_deadline().map(|deadline| deadline.instant()),
         }
     }
 
-    #[derive(Debug)]
-    struct State {
-        id: Id,
-        cancelled: bool,
-        completed: bool,
-        waker_handler: Option<Waker>,
-        waker_spawn_fn: Option<Waker>,
-    }
-
-    impl State {
-        fn cancel(&mut self) {
-            if !self.cancelled {
-                self.cancelled = true;
-                self.wake();
-            }
-        }
-
-        fn complete(&mut self) {
-            self.completed = true;
-            self.wake();
-        }
-
-        fn is_complete(&self) -> bool {
-            self.completed || self.cancelled
-        }
-
-        fn wake(&mut self) {
-            if let Some(waker) = self.waker_handler.take() {
-                waker.wake();
-            }
-            if let Some(waker) = self.waker_spawn_fn.take() {
-                waker.wake();
-            }
-        }
-
-        fn register_handler(&mut self, cx: &mut Context<'_>) {
-            match self.waker_handler {
-                // clone_from can be marginally faster in some cases
-                Some(ref mut waker) => waker.clone_from(cx.waker()),
-                None => self.waker_handler = Some(cx.waker().clone()),
-            }
-        }
-
-        fn register_spawn_fn(&mut self, cx: &mut Context<'_>) {
-            match self.waker_spawn_fn {
-                // clone_from can be marginally faster in some cases
-                Some(ref mut waker) => waker.clone_from(cx.waker()),
-                None => self.waker_spawn_fn = Some(cx.waker().clone()),
-            }
+    /// Creates the metadata for a task that wasn't spawned through this module, which
+    /// isn't added to the task registry or the traced tasks.
+    #[cfg(not(wasm_browser))]
+    #[track_caller]
+    fn untracked() -> Self {
+        let location = Location::caller();
+        Self {
+            #[cfg(feature = "registry")]
+            entry: registry::Entry::untracked(None, location),
+            #[cfg(feature = "metrics")]
+            metrics: Default::default(),
+            #[cfg(feature = "trace")]
+            trace: trace::Task::untracked(None, location),
+            name: None,
+            location,
+            deadline: None,
         }
     }
 
-    impl<T> Debug for JoinHandle<T> {
-        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-            if self.task.state.valid() {
-                let state = self.task.state.borrow();
-                f.debug_struct("JoinHandle")
-                    .field("id", &state.id)
-                    .field("cancelled", &state.cancelled)
-                    .field("completed", &state.completed)
-                    .finish()
-            } else {
-                f.debug_tuple("JoinHandle")
-                    .field(&format_args!("<other thread>"))
-                    .finish()
-            }
-        }
+    /// Has the metrics of the task count towards the metrics of a `JoinSet`.
+    #[cfg(feature = "metrics")]
+    fn in_set(mut self, set: &Arc<metrics::Counters>) -> Self {
+        self.metrics = Arc::new(metrics::Recorder::in_set(set));
+        self
     }
 
-    impl<T> JoinHandle<T> {
-        fn new() -> Self {
-            Self {
-                task: Task {
-                    state: SendWrapper::new(Rc::new(RefCell::new(State {
-                        cancelled: false,
-                        completed: false,
-                        waker_handler: None,
-                        waker_spawn_fn: None,
-                        id: Id(next_task_id()),
-                    }))),
-                    result: SendWrapper::new(Rc::new(RefCell::new(None))),
-                },
-            }
-        }
-
-        /// Aborts this task.
-        pub fn abort(&self) {
-            self.task.state.borrow_mut().cancel();
-        }
-
-        /// Returns a new [`AbortHandle`] that can be used to remotely abort this task.
-        ///
-        /// Awaiting a task cancelled by the [`AbortHandle`] might complete as usual if the task was
-        /// already completed at the time it was cancelled, but most likely it
-        /// will fail with a [cancelled] `JoinError`.
-        ///
-        /// [cancelled]: JoinError::is_cancelled
-        pub fn abort_handle(&self) -> AbortHandle {
-            AbortHandle {
-                state: self.task.state.clone(),
-            }
-        }
-
-        /// Returns a [task ID] that uniquely identifies this task relative to other
-        /// currently spawned tasks.
-        ///
-        /// [task ID]: crate::task::Id
-        pub fn id(&self) -> Id {
-            let state = self.task.state.borrow();
-            state.id
-        }
-
-        /// Checks if the task associated with this `JoinHandle` has finished.
-        pub fn is_finished(&self) -> bool {
-            let state = self.task.state.borrow();
-            state.is_complete()
-        }
-
-        fn is_running(&self) -> bool {
-            !self.is_finished()
-        }
+    fn name(&self) -> Option<&str> {
+        self.name.as_deref()
     }
 
-    /// An error that can occur when waiting for the completion of a task.
-    #[derive(derive_more::Display, Debug, Clone, Copy)]
-    #[display("{cause}")]
-    pub struct JoinError {
-        cause: JoinErrorCause,
-        id: Id,
+    /// Records the id of the task in the task registry and its trace.
+    ///
+    /// Natively, this is also called with the ids of the tasks spawned on the shim.
+    #[cfg_attr(not(feature = "registry"), allow(unused_variables))]
+    fn set_id(&self, id: impl Into<Id>) {
+        let id = id.into();
+        #[cfg(feature = "registry")]
+        self.entry.set_id(id);
+        #[cfg(feature = "trace")]
+        self.trace.set_id(id);
     }
 
-    #[derive(derive_more::Display, Debug, Clone, Copy)]
-    enum JoinErrorCause {
-        /// The error that's returned when the task that's being waited on
-        /// has been cancelled.
-        #[display("task was cancelled")]
-        Cancelled,
+    /// Returns a snapshot of the poll metrics of the task.
+    #[cfg(feature = "metrics")]
+    fn metrics(&self) -> TaskMetrics {
+        self.metrics.snapshot()
     }
 
-    impl std::error::Error for JoinError {}
-
-    impl JoinError {
-        /// Returns whether this join error is due to cancellation.
-        ///
-        /// Always true in this Wasm implementation, because we don't
-        /// unwind panics in tasks.
-        /// All panics just happen on the main thread anyways.
-        pub fn is_cancelled(&self) -> bool {
-            matches!(self.cause, JoinErrorCause::Cancelled)
-        }
-
-        /// Returns whether this is a panic. Always `false` in Wasm,
-        /// because when a task panics, it's not unwound, instead it
-        /// panics directly to the main thread.
-        pub fn is_panic(&self) -> bool {
-            false
-        }
+    /// Wraps the task's future to install its inherited deadline, to report its slow polls,
+    /// to keep track of its state in the task registry, to record its poll metrics and to
+    /// trace its frames.
+    fn track<F>(&self, fut: F) -> Tracked<F> {
+        let fut = crate::time::WithDeadline::task(self.deadline, fut);
+        #[cfg(feature = "trace")]
+        let fut = trace::Root::new(fut, self.trace.clone());
+        let fut = slow_poll::DetectSlowPolls::spawned(fut, self.name.clone(), self.location);
+        #[cfg(feature = "metrics")]
+        let fut = metrics::Instrumented::new(fut, self.metrics.clone());
+        #[cfg(feature = "registry")]
+        let fut = registry::Tracked::new(fut, self.entry.clone());
+        fut
+    }
+}
 
-        /// Returns the panic, if the task has panicked.
-        ///
-        /// Always returns `Err(self)`, in Wasm, because when a task panics, it's not unwound,
-        /// instead it panics directly to the main thread.
-        pub fn try_into_panic(self) -> Result<Box<dyn std::any::Any + Send + 'static>, JoinError> {
-            Err(self)
+impl TaskMeta {
+    /// Wraps a blocking task's closure to install its inherited deadline, and to keep
+    /// track of its state in the task registry and its trace.
+    ///
+    /// Blocking tasks aren't polled, so they don't have poll metrics, slow polls or frames.
+    #[cfg(not(wasm_browser))]
+    fn track_blocking<F, R>(&self, f: F) -> impl FnOnce() -> R + Send + 'static
+    where
+        F: FnOnce() -> R + Send + 'static,
+    {
+        let deadline = self.deadline;
+        #[cfg(feature = "registry")]
+        let entry = self.entry.clone();
+        #[cfg(feature = "trace")]
+        let trace = self.trace.clone();
+        move || {
+            let _deadline = crate::time::deadline::enter(deadline);
+            #[cfg(feature = "trace")]
+            let f = || trace.run_blocking(f);
+            #[cfg(feature = "registry")]
+            let f = || entry.run_blocking(f);
+            f()
         }
+    }
+}
 
-        /// Returns a task ID that identifies the task which errored relative to other currently spawned tasks.
-        pub fn id(&self) -> Id {
-            self.id
+#[cfg(feature = "registry")]
+type Registered<F> = registry::Tracked<F>;
+#[cfg(not(feature = "registry"))]
+type Registered<F> = F;
+#[cfg(feature = "metrics")]
+type Instrumented<F> = metrics::Instrumented<F>;
+#[cfg(not(feature = "metrics"))]
+type Instrumented<F> = F;
+#[cfg(feature = "trace")]
+type Traced<F> = trace::Root<F>;
+#[cfg(not(feature = "trace"))]
+type Traced<F> = F;
+type Tracked<F> =
+    Registered<Instrumented<slow_poll::DetectSlowPolls<Traced<crate::time::WithDeadline<F>>>>>;
+
+/// Similar to a `JoinHandle`, except it automatically aborts
+/// the task when it's dropped.
+#[derive(derive_more::Debug)]
+#[debug("AbortOnDropHandle")]
+#[must_use = "Dropping the handle aborts the task immediately"]
+pub struct AbortOnDropHandle<T>(
+    /// Only `None` once the handle was detached.
+    Option<JoinHandle<T>>,
+);
+
+impl<T> Drop for AbortOnDropHandle<T> {
+    fn drop(&mut self) {
+        if let Some(handle) = &self.0 {
+            handle.abort();
         }
     }
+}
 
-    impl<T> Future for JoinHandle<T> {
-        type Output = Result<T, JoinError>;
-
-        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-            let mut state = self.task.state.borrow_mut();
-            if state.cancelled {
-                return Poll::Ready(Err(JoinError {
-                    cause: JoinErrorCause::Cancelled,
-                    id: state.id,
-                }));
-            }
-
-            let mut result = self.task.result.borrow_mut();
-            if let Some(result) = result.take() {
-                return Poll::Ready(Ok(result));
-            }
+impl<T> Future for AbortOnDropHandle<T> {
+    type Output = <JoinHandle<T> as Future>::Output;
 
-            state.register_handler(cx);
-            Poll::Pending
-        }
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut **self).poll(cx)
     }
+}
 
-    struct JoinHandleWithId<T>(JoinHandle<T>);
-
-    impl<T> Future for JoinHandleWithId<T> {
-        type Output = Result<(Id, T), JoinError>;
+impl<T> std::ops::Deref for AbortOnDropHandle<T> {
+    type Target = JoinHandle<T>;
 
-        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-            match self.0.poll(cx) {
-                Poll::Ready(out) => Poll::Ready(out.map(|out| (self.0.id(), out))),
-                Poll::Pending => Poll::Pending,
-            }
-        }
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref().expect("not detached")
     }
+}
 
-    #[pin_project::pin_project]
-    struct SpawnFuture<Fut: Future<Output = T>, T> {
-        handle: JoinHandle<T>,
-        #[pin]
-        fut: Fut,
+impl<T> std::ops::DerefMut for AbortOnDropHandle<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.0.as_mut().expect("not detached")
     }
+}
 
-    impl<Fut: Future<Output = T>, T> Future for SpawnFuture<Fut, T> {
-        type Output = ();
-
-        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-            let this = self.project();
-            let mut state = this.handle.task.state.borrow_mut();
-
-            if state.cancelled {
-                return Poll::Ready(());
-            }
-
-            match this.fut.poll(cx) {
-                Poll::Ready(value) => {
-                    let _ = this.handle.task.result.borrow_mut().insert(value);
-                    state.complete();
-                    Poll::Ready(())
-                }
-                Poll::Pending => {
-                    state.register_spawn_fn(cx);
-                    Poll::Pending
-                }
-            }
-        }
+impl<T> AsRef<JoinHandle<T>> for AbortOnDropHandle<T> {
+    fn as_ref(&self) -> &JoinHandle<T> {
+        self
     }
+}
 
-    /// An owned permission to abort a spawned task, without awaiting its completion.
-    #[derive(Clone)]
-    pub struct AbortHandle {
-        state: SendWrapper<Rc<RefCell<State>>>,
-    }
-
-    impl Debug for AbortHandle {
-        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-            if self.state.valid() {
-                let state = self.state.borrow();
-                f.debug_struct("AbortHandle")
-                    .field("id", &state.id)
-                    .field("cancelled", &state.cancelled)
-                    .field("completed", &state.completed)
-                    .finish()
-            } else {
-                f.debug_tuple("AbortHandle")
-                    .field(&format_args!("<other thread>"))
-                    .finish()
-            }
-        }
+impl<T> AbortOnDropHandle<T> {
+    /// Converts a `JoinHandle` into one that aborts on drop.
+    pub fn new(task: JoinHandle<T>) -> Self {
+        Self(Some(task))
     }
 
-    impl AbortHandle {
-        /// Abort the task associated with the handle.
-        pub fn abort(&self) {
-            self.state.borrow_mut().cancel();
-        }
-
-        /// Returns a [task ID] that uniquely identifies this task relative to other
-        /// currently spawned tasks.
-        ///
-        /// [task ID]: crate::task::Id
-        pub fn id(&self) -> Id {
-            self.state.borrow().id
-        }
-
-        /// Checks if the task associated with this `AbortHandle` has finished.
-        pub fn is_finished(&self) -> bool {
-            let state = self.state.borrow();
-            state.cancelled && state.completed
-        }
+    /// Returns a new [`AbortHandle`] that can be used to remotely abort this task,
+    /// equivalent to [`JoinHandle::abort_handle`].
+    pub fn abort_handle(&self) -> AbortHandle {
+        (**self).abort_handle()
     }
 
-    /// Similar to a `JoinHandle`, except it automatically aborts
-    /// the task when it's dropped.
-    #[pin_project::pin_project(PinnedDrop)]
-    #[derive(derive_more::Debug, derive_more::Deref)]
-    #[debug("AbortOnDropHandle")]
-    #[must_use = "Dropping the handle aborts the task immediately"]
-    pub struct AbortOnDropHandle<T>(#[pin] JoinHandle<T>);
-
-    #[pin_project::pinned_drop]
-    impl<T> PinnedDrop for AbortOnDropHandle<T> {
-        fn drop(self: Pin<&mut Self>) {
-            self.0.abort();
-        }
+    /// Abort the task associated with this handle,
+    /// equivalent to [`JoinHandle::abort`].
+    pub fn abort(&self) {
+        (**self).abort()
     }
 
-    impl<T> Future for AbortOnDropHandle<T> {
-        type Output = <JoinHandle<T> as Future>::Output;
-
-        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-            self.project().0.poll(cx)
-        }
+    /// Checks if the task associated with this handle is finished,
+    /// equivalent to [`JoinHandle::is_finished`].
+    pub fn is_finished(&self) -> bool {
+        (**self).is_finished()
     }
 
-    impl<T> AbortOnDropHandle<T> {
-        /// Converts a `JoinHandle` into one that aborts on drop.
-        pub fn new(task: JoinHandle<T>) -> Self {
-            Self(task)
-        }
-
-        /// Returns a new [`AbortHandle`] that can be used to remotely abort this task,
-        /// equivalent to [`JoinHandle::abort_handle`].
-        pub fn abort_handle(&self) -> AbortHandle {
-            self.0.abort_handle()
-        }
-
-        /// Abort the task associated with this handle,
-        /// equivalent to [`JoinHandle::abort`].
-        pub fn abort(&self) {
-            self.0.abort()
-        }
-
-        /// Checks if the task associated with this handle is finished,
-        /// equivalent to [`JoinHandle::is_finished`].
-        pub fn is_finished(&self) -> bool {
-            self.0.is_finished()
-        }
+    /// Returns the underlying `JoinHandle`, so that the task is no longer aborted
+    /// when the handle is dropped.
+    pub fn detach(mut self) -> JoinHandle<T> {
+        self.0.take().expect("not detached")
     }
+}
 
-    /// Spawns a future as a task in the browser runtime.
-    ///
-    /// This is powered by `wasm_bidngen_futures`.
-    pub fn spawn<T: 'static>(fut: impl IntoFuture<Output = T> + 'static) -> JoinHandle<T> {
-        let handle = JoinHandle::new();
-
-        wasm_bindgen_futures::spawn_local(SpawnFuture {
-            handle: JoinHandle {
-                task: handle.task.clone(),
-            },
-            fut: fut.into_future(),
-        });
+/// Unwraps the underlying tokio handle, keeping the task aborted on drop.
+///
+/// Fails for tasks that were spawned on an installed [`Runtime`] instead of tokio.
+///
+/// [`Runtime`]: crate::runtime::Runtime
+#[cfg(not(wasm_browser))]
+impl<T> TryFrom<AbortOnDropHandle<T>> for tokio_util::task::AbortOnDropHandle<T> {
+    type Error = AbortOnDropHandle<T>;
 
-        handle
+    fn try_from(handle: AbortOnDropHandle<T>) -> Result<Self, Self::Error> {
+        tokio::task::JoinHandle::try_from(handle.detach())
+            .map(Self::new)
+            .map_err(AbortOnDropHandle::new)
     }
 }
 
 #[cfg(test)]
 mod test {
-    use std::time::Duration;
+    use std::{
+        cell::Cell,
+        rc::Rc,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        task::{Context, Poll, Wake, Waker},
+        time::Duration,
+    };
 
     #[cfg(not(wasm_browser))]
     use tokio::test;
@@ -643,6 +421,75 @@ mod test {
         assert!(h2.await.is_ok());
     }
 
+    #[cfg(panic = "unwind")]
+    #[test]
+    async fn task_panic() {
+        let handle = task::spawn(async {
+            panic!("boom");
+        });
+
+        let err = handle.await.unwrap_err();
+        assert!(err.is_panic());
+        assert!(!err.is_cancelled());
+        assert_eq!(*err.into_panic().downcast::<&str>().unwrap(), "boom");
+    }
+
+    #[test]
+    async fn builder_name() {
+        let handle = task::Builder::new()
+            .name("named")
+            .spawn(std::future::pending::<()>());
+        assert_eq!(handle.name(), Some("named"));
+        assert_eq!(handle.abort_handle().name(), Some("named"));
+
+        handle.abort();
+        let err = handle.await.unwrap_err();
+        assert!(err.is_cancelled());
+        assert_eq!(err.name(), Some("named"));
+
+        let handle = task::spawn(async {});
+        assert_eq!(handle.name(), None);
+        handle.await.unwrap();
+    }
+
+    #[test]
+    async fn spawn_local() {
+        let local = task::LocalSet::new();
+        let counter = Rc::new(Cell::new(0));
+
+        let out = local
+            .run_until({
+                let counter = counter.clone();
+                async move {
+                    let handle = task::spawn_local({
+                        let counter = counter.clone();
+                        async move {
+                            counter.set(counter.get() + 1);
+                            Rc::new(counter.get())
+                        }
+                    });
+                    let mut set = task::JoinSet::new();
+                    set.spawn_local(async { Rc::new(10) });
+                    let from_set = set.join_next().await.unwrap().unwrap();
+                    *handle.await.unwrap() + *from_set
+                }
+            })
+            .await;
+        assert_eq!(out, 11);
+
+        let handle = local.spawn_local({
+            let counter = counter.clone();
+            async move {
+                crate::time::sleep(Duration::from_millis(1)).await;
+                counter.set(counter.get() + 1);
+                counter.clone()
+            }
+        });
+        local.await;
+        assert_eq!(counter.get(), 2);
+        assert_eq!(handle.await.unwrap().get(), 2);
+    }
+
     #[test]
     async fn join_set_abort() {
         let fut = || async { 22 };
@@ -678,4 +525,148 @@ mod test {
         assert!(has_err);
         assert!(has_ok);
     }
+
+    #[test]
+    async fn join_set_observes_spawn_while_pending() {
+        let mut set = task::JoinSet::new();
+        set.spawn(std::future::pending::<u32>());
+
+        let mut spawned = false;
+        let out = crate::time::timeout(
+            Duration::from_secs(1),
+            std::future::poll_fn(|cx| {
+                if let Poll::Ready(out) = set.poll_join_next(cx) {
+                    return Poll::Ready(out);
+                }
+                if !spawned {
+                    spawned = true;
+                    set.spawn(async { 42 });
+                }
+                Poll::Pending
+            }),
+        )
+        .await
+        .expect("join_next didn't observe the newly spawned task");
+
+        assert_eq!(out.unwrap().unwrap(), 42);
+        assert_eq!(set.len(), 1);
+        set.shutdown().await;
+    }
+
+    #[test]
+    async fn join_set_spawn_wakes_pending_poll() {
+        struct Flag(AtomicBool);
+
+        impl Wake for Flag {
+            fn wake(self: Arc<Self>) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let mut set = task::JoinSet::new();
+        set.spawn(std::future::pending::<u32>());
+
+        let flag = Arc::new(Flag(AtomicBool::new(false)));
+        let waker = Waker::from(flag.clone());
+        let mut cx = Context::from_waker(&waker);
+        assert!(set.poll_join_next_with_id(&mut cx).is_pending());
+
+        let handle = set.spawn(async { 42 });
+        crate::time::timeout(Duration::from_secs(1), async {
+            while !flag.0.load(Ordering::SeqCst) {
+                crate::future::yield_now().await;
+            }
+        })
+        .await
+        .expect("spawn didn't wake the pending poll");
+
+        // Multi-threaded backends might still be running the task
+        let (id, out) = crate::time::timeout(Duration::from_secs(1), set.join_next_with_id())
+            .await
+            .expect("the newly spawned task didn't finish")
+            .expect("the set isn't empty")
+            .expect("the task didn't fail");
+        assert_eq!(id, handle.id());
+        assert_eq!(out, 42);
+        set.shutdown().await;
+    }
+
+    #[test]
+    async fn abort_on_drop_detach() {
+        let handle = task::AbortOnDropHandle::new(task::spawn(async {
+            crate::time::sleep(Duration::from_millis(10)).await;
+            42
+        }));
+        let abort_handle = handle.abort_handle();
+        let handle = handle.detach();
+        assert!(!abort_handle.is_finished());
+        assert_eq!(handle.await.unwrap(), 42);
+    }
+
+    #[cfg(not(wasm_browser))]
+    #[test]
+    async fn join_set_tokio_api() {
+        let mut set: task::JoinSet<u32> = (0..3).map(|i| async move { i }).collect();
+        set.extend([async { 3 }]);
+        set.spawn_blocking(|| 4);
+        set.spawn_on(async { 5 }, &tokio::runtime::Handle::current());
+        assert_eq!(set.len(), 6);
+
+        let mut outs = Vec::new();
+        while outs.len() < 6 {
+            match set.try_join_next() {
+                Some(out) => outs.push(out.unwrap()),
+                None => crate::future::yield_now().await,
+            }
+        }
+        outs.sort();
+        assert_eq!(outs, [0, 1, 2, 3, 4, 5]);
+        assert!(set.try_join_next().is_none());
+
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        set.spawn(async move {
+            tx.send(()).unwrap();
+            0
+        });
+        set.detach_all();
+        assert!(set.is_empty());
+        // The detached task still runs
+        rx.await.unwrap();
+    }
+
+    #[cfg(not(wasm_browser))]
+    #[test]
+    async fn tokio_handle_conversions() {
+        let handle: task::JoinHandle<_> = tokio::spawn(async { 42 }).into();
+        let handle = tokio::task::JoinHandle::try_from(handle).unwrap();
+        assert_eq!(handle.await.unwrap(), 42);
+
+        let handle = task::AbortOnDropHandle::new(task::spawn(std::future::pending::<()>()));
+        let abort_handle = tokio::task::AbortHandle::try_from(handle.abort_handle()).unwrap();
+        let handle = tokio_util::task::AbortOnDropHandle::try_from(handle).unwrap();
+        drop(handle);
+        tokio::task::yield_now().await;
+        assert!(abort_handle.is_finished());
+
+        // Tasks on an installed runtime aren't tokio's
+        crate::runtime::with_runtime(crate::runtime::TokioRuntime, async {
+            let handle = task::spawn(async { 42 });
+            let handle = tokio::task::JoinHandle::try_from(handle).unwrap_err();
+            assert_eq!(handle.await.unwrap(), 42);
+        })
+        .await;
+    }
+
+    #[cfg(not(wasm_browser))]
+    #[test]
+    async fn spawn_blocking_on_custom_runtime() {
+        let counting = crate::runtime::tests::Counting::new(crate::runtime::TokioRuntime);
+        crate::runtime::with_runtime(counting.clone(), async {
+            let mut set = task::JoinSet::new();
+            set.spawn_blocking(|| 42);
+            assert_eq!(set.join_next().await.unwrap().unwrap(), 42);
+        })
+        .await;
+        assert_eq!(counting.count(), 1);
+    }
 }
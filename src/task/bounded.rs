@@ -0,0 +1,280 @@
+//! Implements the [`BoundedJoinSet`] utility.
+
+#[cfg(wasm_browser)]
+use std::future::IntoFuture;
+use std::{
+    fmt,
+    future::Future,
+    panic::Location,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use super::{AbortHandle, Id, JoinError, JoinSet, PanicPolicy};
+
+/// A [`JoinSet`] that limits how many of its tasks run at the same time.
+///
+/// [`BoundedJoinSet::spawn`] waits for a running task to complete once the limit is
+/// reached, and [`BoundedJoinSet::try_spawn`] returns the task back instead.
+/// Only running tasks count towards the limit: tasks that completed, but whose results
+/// haven't been joined yet, don't.
+///
+/// # Example
+///
+/// ```ignore-wasm32-unknown-unknown
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// use n0_future::task::BoundedJoinSet;
+///
+/// let mut set = BoundedJoinSet::new(2);
+/// for i in 0..10 {
+///     // Waits while two tasks are running
+///     set.spawn(async move { i * 2 }).await;
+/// }
+///
+/// let mut sum = 0;
+/// while let Some(res) = set.join_next().await {
+///     sum += res.unwrap();
+/// }
+/// assert_eq!(sum, 90);
+/// # }
+/// ```
+pub struct BoundedJoinSet<T> {
+    tasks: JoinSet<T>,
+    permits: Arc<Semaphore>,
+    limit: usize,
+}
+
+impl<T> fmt::Debug for BoundedJoinSet<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BoundedJoinSet")
+            .field("len", &self.len())
+            .field("running", &self.running())
+            .field("limit", &self.limit)
+            .finish()
+    }
+}
+
+impl<T> BoundedJoinSet<T> {
+    /// Creates a new, empty `BoundedJoinSet` that runs at most `limit` tasks at once.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `limit` is zero, or larger than [`Semaphore::MAX_PERMITS`].
+    ///
+    /// [`Semaphore::MAX_PERMITS`]: tokio::sync::Semaphore::MAX_PERMITS
+    pub fn new(limit: usize) -> Self {
+        assert!(limit > 0, "limit must be greater than zero");
+        let mut tasks = JoinSet::new();
+        // The global policy is applied to the joined results instead
+        tasks.set_panic_policy(PanicPolicy::ReturnError);
+        Self {
+            tasks,
+            permits: Arc::new(Semaphore::new(limit)),
+            limit,
+        }
+    }
+
+    /// Returns the maximum number of tasks that run at once.
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// Returns the number of tasks that are currently running.
+    pub fn running(&self) -> usize {
+        self.limit - self.permits.available_permits()
+    }
+
+    /// Returns the number of tasks that are either still running or have pending
+    /// results in this `BoundedJoinSet`.
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Returns whether there's any tasks that are either still running or have
+    /// pending results in this `BoundedJoinSet`.
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+}
+
+impl<T: 'static> BoundedJoinSet<T> {
+    /// Spawns a task into this `BoundedJoinSet`, once fewer than [`limit`] tasks are
+    /// running.
+    ///
+    /// [`limit`]: Self::limit
+    // Not an `async fn`, so that the task is reported as spawned at the caller's location.
+    #[cfg(not(wasm_browser))]
+    #[track_caller]
+    pub fn spawn<F>(&mut self, task: F) -> impl Future<Output = AbortHandle> + '_
+    where
+        F: Future<Output = T> + Send + 'static,
+        T: Send,
+    {
+        let location = Location::caller();
+        async move {
+            let permit = self.acquire().await;
+            self.tasks.spawn_at(location, with_permit(permit, task))
+        }
+    }
+
+    /// Spawns a task into this `BoundedJoinSet`, once fewer than [`limit`] tasks are
+    /// running.
+    ///
+    /// [`limit`]: Self::limit
+    // Not an `async fn`, so that the task is reported as spawned at the caller's location.
+    #[cfg(wasm_browser)]
+    #[track_caller]
+    pub fn spawn<F>(&mut self, task: F) -> impl Future<Output = AbortHandle> + '_
+    where
+        F: IntoFuture<Output = T> + 'static,
+    {
+        let location = Location::caller();
+        async move {
+            let permit = self.acquire().await;
+            self.tasks.spawn_at(location, with_permit(permit, task))
+        }
+    }
+
+    /// Spawns a task into this `BoundedJoinSet` if fewer than [`limit`] tasks are
+    /// running, or returns it back otherwise.
+    ///
+    /// [`limit`]: Self::limit
+    #[cfg(not(wasm_browser))]
+    #[track_caller]
+    pub fn try_spawn<F>(&mut self, task: F) -> Result<AbortHandle, F>
+    where
+        F: Future<Output = T> + Send + 'static,
+        T: Send,
+    {
+        match self.permits.clone().try_acquire_owned() {
+            Ok(permit) => Ok(self.tasks.spawn(with_permit(permit, task))),
+            Err(_) => Err(task),
+        }
+    }
+
+    /// Spawns a task into this `BoundedJoinSet` if fewer than [`limit`] tasks are
+    /// running, or returns it back otherwise.
+    ///
+    /// [`limit`]: Self::limit
+    #[cfg(wasm_browser)]
+    #[track_caller]
+    pub fn try_spawn<F>(&mut self, task: F) -> Result<AbortHandle, F>
+    where
+        F: IntoFuture<Output = T> + 'static,
+    {
+        match self.permits.clone().try_acquire_owned() {
+            Ok(permit) => Ok(self.tasks.spawn(with_permit(permit, task))),
+            Err(_) => Err(task),
+        }
+    }
+
+    async fn acquire(&self) -> OwnedSemaphorePermit {
+        self.permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("semaphore is never closed")
+    }
+
+    /// Aborts all tasks inside this `BoundedJoinSet`.
+    pub fn abort_all(&mut self) {
+        self.tasks.abort_all();
+    }
+
+    /// Awaits the completion of the next task in this `BoundedJoinSet`.
+    ///
+    /// Returns `None` if the set is empty.
+    pub async fn join_next(&mut self) -> Option<Result<T, JoinError>> {
+        std::future::poll_fn(|cx| self.poll_join_next(cx)).await
+    }
+
+    /// Awaits the completion of the next task in this `BoundedJoinSet`, and returns its
+    /// output along with its [task ID].
+    ///
+    /// Returns `None` if the set is empty.
+    ///
+    /// [task ID]: crate::task::Id
+    pub async fn join_next_with_id(&mut self) -> Option<Result<(Id, T), JoinError>> {
+        let res = self.tasks.join_next_with_id().await?;
+        Some(PanicPolicy::apply(None, res, || self.tasks.abort_all()))
+    }
+
+    /// Polls for one of the tasks in the set to complete.
+    ///
+    /// See [`JoinSet::poll_join_next`].
+    pub fn poll_join_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<T, JoinError>>> {
+        let res = self.tasks.poll_join_next(cx);
+        res.map(|res| res.map(|res| PanicPolicy::apply(None, res, || self.tasks.abort_all())))
+    }
+
+    /// Aborts all tasks and then waits for them to finish, ignoring panics.
+    pub async fn shutdown(&mut self) {
+        self.tasks.shutdown().await;
+    }
+}
+
+/// Holds on to `permit` until `task` completes, or is dropped when it's aborted.
+#[cfg(not(wasm_browser))]
+async fn with_permit<F: Future>(permit: OwnedSemaphorePermit, task: F) -> F::Output {
+    let _permit = permit;
+    task.await
+}
+
+/// Holds on to `permit` until `task` completes, or is dropped when it's aborted.
+#[cfg(wasm_browser)]
+async fn with_permit<F: IntoFuture>(permit: OwnedSemaphorePermit, task: F) -> F::Output {
+    let _permit = permit;
+    task.await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    #[cfg(not(wasm_browser))]
+    use tokio::test;
+    #[cfg(wasm_browser)]
+    use wasm_bindgen_test::wasm_bindgen_test as test;
+
+    use super::*;
+    use crate::time;
+
+    #[test]
+    async fn bounded_join_set_limits_running_tasks() {
+        let mut set = BoundedJoinSet::new(2);
+        set.spawn(time::sleep(Duration::from_millis(10))).await;
+        set.spawn(std::future::pending()).await;
+        assert_eq!(set.running(), 2);
+
+        let rejected = set.try_spawn(std::future::ready(()));
+        assert!(rejected.is_err());
+
+        // Completes once the sleep is done, without joining it first
+        set.spawn(std::future::ready(())).await;
+        assert_eq!(set.len(), 3);
+        assert!(set.running() <= 2);
+
+        set.abort_all();
+        while !set.is_empty() {
+            set.join_next().await;
+        }
+        assert_eq!(set.running(), 0);
+        assert!(set.try_spawn(std::future::ready(())).is_ok());
+    }
+
+    #[cfg(feature = "registry")]
+    #[test]
+    async fn bounded_join_set_reports_caller_location() {
+        let mut set = BoundedJoinSet::new(1);
+        let (handle, line) = (set.spawn(std::future::pending::<()>()).await, line!());
+        let info = crate::task::dump()
+            .into_iter()
+            .find(|info| info.id() == handle.id())
+            .expect("task is registered");
+        assert_eq!(info.location().line(), line);
+        set.shutdown().await;
+    }
+}
@@ -0,0 +1,185 @@
+//! Implements spawning tasks that are aborted once a [`CancellationToken`] is cancelled.
+
+use std::future::Future;
+#[cfg(wasm_browser)]
+use std::future::IntoFuture;
+
+use tokio::sync::oneshot;
+pub use tokio_util::sync::{
+    CancellationToken, DropGuard, DropGuardRef, WaitForCancellationFuture,
+    WaitForCancellationFutureOwned,
+};
+
+use super::{AbortHandle, JoinHandle, JoinSet};
+
+/// Spawns a new task, which is aborted once `token` is cancelled.
+///
+/// The task's [`JoinHandle`] returns a [cancelled] `JoinError` when the token was
+/// cancelled before the task completed. Use [`CancellationToken::run_until_cancelled`]
+/// instead, if the task should just stop at its next await point without being aborted.
+///
+/// # Example
+///
+/// ```ignore-wasm32-unknown-unknown
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// use n0_future::task::{self, CancellationToken};
+///
+/// let token = CancellationToken::new();
+/// let handle = task::spawn_cancellable(token.clone(), std::future::pending::<()>());
+/// token.cancel();
+/// assert!(handle.await.unwrap_err().is_cancelled());
+/// # }
+/// ```
+///
+/// [cancelled]: super::JoinError::is_cancelled
+#[cfg(not(wasm_browser))]
+#[track_caller]
+pub fn spawn_cancellable<F>(token: CancellationToken, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let (tx, rx) = oneshot::channel();
+    let handle = super::spawn(abort_on_cancel(token, rx, future));
+    tx.send(handle.abort_handle()).ok();
+    handle
+}
+
+/// Spawns a new task, which is aborted once `token` is cancelled.
+///
+/// The task's [`JoinHandle`] returns a [cancelled] `JoinError` when the token was
+/// cancelled before the task completed. Use [`CancellationToken::run_until_cancelled`]
+/// instead, if the task should just stop at its next await point without being aborted.
+///
+/// [cancelled]: super::JoinError::is_cancelled
+#[cfg(wasm_browser)]
+#[track_caller]
+pub fn spawn_cancellable<T: 'static>(
+    token: CancellationToken,
+    fut: impl IntoFuture<Output = T> + 'static,
+) -> JoinHandle<T> {
+    let (tx, rx) = oneshot::channel();
+    let handle = super::spawn(abort_on_cancel(token, rx, fut.into_future()));
+    tx.send(handle.abort_handle()).ok();
+    handle
+}
+
+impl<T: 'static> JoinSet<T> {
+    /// Spawns a task into this `JoinSet`, which is aborted once `token` is cancelled.
+    ///
+    /// See [`spawn_cancellable`].
+    #[cfg(not(wasm_browser))]
+    #[track_caller]
+    pub fn spawn_cancellable<F>(&mut self, token: CancellationToken, task: F) -> AbortHandle
+    where
+        F: Future<Output = T> + Send + 'static,
+        T: Send,
+    {
+        let (tx, rx) = oneshot::channel();
+        let handle = self.spawn(abort_on_cancel(token, rx, task));
+        tx.send(handle.clone()).ok();
+        handle
+    }
+
+    /// Spawns a task into this `JoinSet`, which is aborted once `token` is cancelled.
+    ///
+    /// See [`spawn_cancellable`].
+    #[cfg(wasm_browser)]
+    #[track_caller]
+    pub fn spawn_cancellable(
+        &mut self,
+        token: CancellationToken,
+        task: impl IntoFuture<Output = T> + 'static,
+    ) -> AbortHandle {
+        let (tx, rx) = oneshot::channel();
+        let handle = self.spawn(abort_on_cancel(token, rx, task.into_future()));
+        tx.send(handle.clone()).ok();
+        handle
+    }
+}
+
+/// Runs `future` until `token` is cancelled, and then aborts the task it runs in using
+/// the [`AbortHandle`] received from `handle`.
+///
+/// The task's abort handle is sent right after spawning it. Aborting the task itself,
+/// instead of just returning, has its `JoinHandle` return a [cancelled] `JoinError`.
+///
+/// [cancelled]: super::JoinError::is_cancelled
+async fn abort_on_cancel<F: Future>(
+    token: CancellationToken,
+    handle: oneshot::Receiver<AbortHandle>,
+    future: F,
+) -> F::Output {
+    if let Some(out) = token.run_until_cancelled(future).await {
+        return out;
+    }
+    if let Ok(handle) = handle.await {
+        handle.abort();
+    }
+    // The task is cancelled once it yields
+    std::future::pending().await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    #[cfg(not(wasm_browser))]
+    use tokio::test;
+    #[cfg(wasm_browser)]
+    use wasm_bindgen_test::wasm_bindgen_test as test;
+
+    use super::*;
+    use crate::task;
+
+    #[test]
+    async fn spawn_cancellable_aborts() {
+        let token = CancellationToken::new();
+        let pending = task::spawn_cancellable(token.child_token(), std::future::pending::<()>());
+        let done = task::spawn_cancellable(token.clone(), async { 42 });
+        assert_eq!(done.await.unwrap(), 42);
+
+        crate::time::sleep(Duration::from_millis(1)).await;
+        assert!(!pending.is_finished());
+        token.cancel();
+        assert!(pending.await.unwrap_err().is_cancelled());
+    }
+
+    #[test]
+    async fn join_set_spawn_cancellable() {
+        let token = CancellationToken::new();
+        let mut set = JoinSet::new();
+        set.spawn_cancellable(token.clone(), std::future::pending::<u32>());
+        set.spawn_cancellable(token.clone(), std::future::pending::<u32>());
+
+        let cancelled = token.run_until_cancelled(std::future::pending::<()>());
+        token.cancel();
+        assert_eq!(cancelled.await, None);
+        while let Some(res) = set.join_next().await {
+            assert!(res.unwrap_err().is_cancelled());
+        }
+    }
+
+    #[cfg(not(wasm_browser))]
+    #[test]
+    async fn spawn_cancellable_spawns_single_task() {
+        let counting = crate::runtime::tests::Counting::new(crate::runtime::TokioRuntime);
+        crate::runtime::with_runtime(counting.clone(), async {
+            let token = CancellationToken::new();
+            let handle = task::spawn_cancellable(token.clone(), std::future::pending::<()>());
+            let mut set = JoinSet::new();
+            set.spawn_cancellable(token.clone(), std::future::pending::<()>());
+            token.cancel();
+            assert!(handle.await.unwrap_err().is_cancelled());
+            assert!(set.join_next().await.unwrap().unwrap_err().is_cancelled());
+
+            // Tokens that are cancelled already abort the task right away
+            let handle = task::spawn_cancellable(token, async { 42 });
+            assert!(handle.await.unwrap_err().is_cancelled());
+        })
+        .await;
+        // No tasks watch the tokens next to the cancellable tasks themselves
+        assert_eq!(counting.count(), 3);
+    }
+}
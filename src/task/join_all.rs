@@ -0,0 +1,202 @@
+//! Implements collecting the results of all tasks in a [`JoinSet`] without panicking,
+//! see [`JoinSet::join_all_results`] and [`JoinSet::try_join_all`].
+
+use std::{fmt, future::poll_fn};
+
+use super::{Id, JoinError, JoinSet};
+
+/// Error returned by [`JoinSet::try_join_all`] for the first task that failed.
+#[derive(Debug)]
+pub enum TryJoinError<E> {
+    /// The task completed with an error.
+    Task {
+        /// The id of the task.
+        id: Id,
+        /// The error the task returned.
+        error: E,
+    },
+    /// The task panicked or was aborted.
+    Join(JoinError),
+}
+
+impl<E> TryJoinError<E> {
+    /// Returns the id of the task that failed.
+    pub fn id(&self) -> Id {
+        match self {
+            Self::Task { id, .. } => *id,
+            Self::Join(err) => err.id(),
+        }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for TryJoinError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Task { id, error } => write!(f, "task {id} failed: {error}"),
+            Self::Join(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for TryJoinError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Task { error, .. } => Some(error),
+            Self::Join(err) => Some(err),
+        }
+    }
+}
+
+impl<T: 'static> JoinSet<T> {
+    /// Awaits the completion of all tasks in this `JoinSet`, returning a vector of their
+    /// results.
+    ///
+    /// Unlike [`JoinSet::join_all`], this doesn't panic if a task panicked or was aborted,
+    /// but returns its [`JoinError`], regardless of the set's [`PanicPolicy`]. The results
+    /// are in the order the tasks completed in.
+    ///
+    /// [`PanicPolicy`]: super::PanicPolicy
+    pub async fn join_all_results(mut self) -> Vec<Result<T, JoinError>> {
+        let mut output = Vec::with_capacity(self.len());
+        while let Some(res) = poll_fn(|cx| self.poll_join_next_unchecked(cx)).await {
+            output.push(res.map(|(_id, out)| out));
+        }
+        output
+    }
+}
+
+impl<T: 'static, E: 'static> JoinSet<Result<T, E>> {
+    /// Awaits the completion of all tasks in this `JoinSet`, returning their outputs, or
+    /// the first error.
+    ///
+    /// Once a task returns an error, panics or is aborted, the remaining tasks are aborted
+    /// and the error is returned along with the id of the task. Panics are returned as
+    /// [`JoinError`]s regardless of the set's [`PanicPolicy`]. The outputs are in the
+    /// order the tasks completed in.
+    ///
+    /// # Example
+    ///
+    /// ```ignore-wasm32-unknown-unknown
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// use n0_future::task::{JoinSet, TryJoinError};
+    ///
+    /// let mut set = JoinSet::new();
+    /// set.spawn(std::future::pending());
+    /// let failing = set.spawn(async { Err::<(), _>("failed") });
+    ///
+    /// match set.try_join_all().await {
+    ///     Err(TryJoinError::Task { id, error }) => {
+    ///         assert_eq!(id, failing.id());
+    ///         assert_eq!(error, "failed");
+    ///     }
+    ///     res => panic!("unexpected result: {res:?}"),
+    /// }
+    /// # }
+    /// ```
+    ///
+    /// [`PanicPolicy`]: super::PanicPolicy
+    pub async fn try_join_all(mut self) -> Result<Vec<T>, TryJoinError<E>> {
+        let mut output = Vec::with_capacity(self.len());
+        while let Some(res) = poll_fn(|cx| self.poll_join_next_unchecked(cx)).await {
+            let err = match res {
+                Ok((_, Ok(out))) => {
+                    output.push(out);
+                    continue;
+                }
+                Ok((id, Err(error))) => TryJoinError::Task { id, error },
+                Err(err) => TryJoinError::Join(err),
+            };
+            self.shutdown().await;
+            return Err(err);
+        }
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(not(wasm_browser))]
+    use tokio::test;
+    #[cfg(wasm_browser)]
+    use wasm_bindgen_test::wasm_bindgen_test as test;
+
+    use super::*;
+    use crate::task::PanicPolicy;
+
+    #[test]
+    async fn join_all_results_returns_errors() {
+        let mut set = JoinSet::new();
+        set.spawn(async { 1 });
+        let aborted = set.spawn(std::future::pending());
+        aborted.abort();
+
+        let mut results = set.join_all_results().await;
+        results.sort_by_key(|res| res.is_err());
+        assert_eq!(*results[0].as_ref().unwrap(), 1);
+        let err = results[1].as_ref().unwrap_err();
+        assert!(err.is_cancelled());
+        assert_eq!(err.id(), aborted.id());
+    }
+
+    #[test]
+    async fn join_all_results_ignores_panic_policy() {
+        let mut set = JoinSet::new();
+        set.set_panic_policy(PanicPolicy::Resume);
+        set.spawn(async { panic!("fatal") });
+
+        let results = set.join_all_results().await;
+        let err = results[0].as_ref().unwrap_err();
+        assert_eq!(err.panic_message(), Some("fatal"));
+    }
+
+    #[test]
+    async fn try_join_all_collects_outputs() {
+        let mut set = JoinSet::new();
+        for i in 0..3 {
+            set.spawn(async move { Ok::<_, String>(i) });
+        }
+        let mut outputs = set.try_join_all().await.unwrap();
+        outputs.sort();
+        assert_eq!(outputs, vec![0, 1, 2]);
+    }
+
+    #[test]
+    async fn try_join_all_aborts_on_error() {
+        let (tx, rx) = std::sync::mpsc::channel::<()>();
+        let mut set = JoinSet::new();
+        set.spawn(async move {
+            let _tx = tx;
+            std::future::pending::<Result<(), &str>>().await
+        });
+        let failing = set.spawn(async { Err("failed") });
+
+        let err = set.try_join_all().await.unwrap_err();
+        assert_eq!(err.id(), failing.id());
+        assert_eq!(
+            err.to_string(),
+            format!("task {} failed: failed", failing.id())
+        );
+        // The pending task was aborted, which dropped the sender
+        assert!(rx.recv().is_err());
+    }
+
+    #[test]
+    async fn try_join_all_returns_join_errors() {
+        let mut set = JoinSet::new();
+        set.set_panic_policy(PanicPolicy::Resume);
+        set.spawn(std::future::pending::<Result<(), &str>>());
+        let panicking = set.spawn(async { panic!("fatal") });
+
+        let err = set.try_join_all().await.unwrap_err();
+        assert_eq!(err.id(), panicking.id());
+        let TryJoinError::Join(ref join_err) = err else {
+            panic!("unexpected error: {err:?}");
+        };
+        assert_eq!(join_err.panic_message(), Some("fatal"));
+        assert_eq!(
+            err.to_string(),
+            format!("task {} panicked with message \"fatal\"", panicking.id())
+        );
+    }
+}
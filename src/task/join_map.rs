@@ -0,0 +1,305 @@
+//! Implements the [`JoinMap`] utility.
+
+#[cfg(not(wasm_browser))]
+use std::future::Future;
+#[cfg(wasm_browser)]
+use std::future::IntoFuture;
+use std::{
+    borrow::Borrow,
+    collections::HashMap,
+    fmt,
+    hash::Hash,
+    task::{Context, Poll},
+};
+
+use super::{AbortHandle, Id, JoinError, JoinSet, PanicPolicy};
+
+/// A collection of tasks spawned on the runtime, which are associated with keys.
+///
+/// This is the equivalent of `tokio_util::task::JoinMap`, but works in browsers, too.
+/// It's built on top of a [`JoinSet`] and keeps track of each task's [`AbortHandle`],
+/// so tasks can be looked up and aborted by their key.
+///
+/// When a task is spawned with a key that's already in use, the previous task is
+/// aborted and removed from the map. Its result won't be returned from [`join_next`].
+///
+/// [`join_next`]: JoinMap::join_next
+///
+/// # Example
+///
+/// ```ignore-wasm32-unknown-unknown
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// use n0_future::task::JoinMap;
+///
+/// let mut map = JoinMap::new();
+/// map.spawn("one", async { 1 });
+/// map.spawn("two", async { 2 });
+/// assert!(map.contains_key("one"));
+///
+/// let mut results = Vec::new();
+/// while let Some((key, res)) = map.join_next().await {
+///     results.push((key, res.unwrap()));
+/// }
+/// results.sort();
+/// assert_eq!(results, vec![("one", 1), ("two", 2)]);
+/// # }
+/// ```
+pub struct JoinMap<K, V> {
+    tasks: JoinSet<V>,
+    keys: HashMap<Id, K>,
+    handles: HashMap<K, AbortHandle>,
+}
+
+impl<K, V> fmt::Debug for JoinMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JoinMap").field("len", &self.len()).finish()
+    }
+}
+
+impl<K, V> Default for JoinMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K, V> JoinMap<K, V> {
+    /// Creates a new, empty `JoinMap`.
+    pub fn new() -> Self {
+        let mut tasks = JoinSet::new();
+        // The global policy is applied in `poll_join_next` instead, once the task was
+        // removed from the map
+        tasks.set_panic_policy(PanicPolicy::ReturnError);
+        Self {
+            tasks,
+            keys: HashMap::new(),
+            handles: HashMap::new(),
+        }
+    }
+
+    /// Returns the number of tasks currently in the `JoinMap`.
+    ///
+    /// This includes tasks that have finished, but whose results haven't been
+    /// returned from [`JoinMap::join_next`] yet.
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    /// Returns whether the `JoinMap` is empty.
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+
+    /// Returns an iterator over the keys of the tasks in this `JoinMap`.
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.handles.keys()
+    }
+}
+
+impl<K, V> JoinMap<K, V>
+where
+    K: Hash + Eq + Clone + 'static,
+    V: 'static,
+{
+    /// Spawns the provided task and stores it in this `JoinMap` with the provided key.
+    ///
+    /// If a task previously existed in the `JoinMap` for this key, that task is
+    /// aborted and replaced with the new one.
+    #[cfg(not(wasm_browser))]
+    #[track_caller]
+    pub fn spawn<F>(&mut self, key: K, task: F)
+    where
+        F: Future<Output = V> + Send + 'static,
+        V: Send,
+    {
+        let handle = self.tasks.spawn(task);
+        self.insert(key, handle);
+    }
+
+    /// Spawns the provided task and stores it in this `JoinMap` with the provided key.
+    ///
+    /// If a task previously existed in the `JoinMap` for this key, that task is
+    /// aborted and replaced with the new one.
+    #[cfg(wasm_browser)]
+    #[track_caller]
+    pub fn spawn(&mut self, key: K, task: impl IntoFuture<Output = V> + 'static) {
+        let handle = self.tasks.spawn(task);
+        self.insert(key, handle);
+    }
+
+    /// Spawns the provided `!Send` task on the current [`LocalSet`] and stores it in
+    /// this `JoinMap` with the provided key.
+    ///
+    /// If a task previously existed in the `JoinMap` for this key, that task is
+    /// aborted and replaced with the new one.
+    ///
+    /// [`LocalSet`]: crate::task::LocalSet
+    #[cfg(not(wasm_browser))]
+    #[track_caller]
+    pub fn spawn_local<F>(&mut self, key: K, task: F)
+    where
+        F: Future<Output = V> + 'static,
+    {
+        let handle = self.tasks.spawn_local(task);
+        self.insert(key, handle);
+    }
+
+    /// Alias to [`Self::spawn`].
+    ///
+    /// Because all tasks in WebAssembly are local, this is a simple alias to [`Self::spawn`].
+    #[cfg(wasm_browser)]
+    #[track_caller]
+    pub fn spawn_local(&mut self, key: K, task: impl IntoFuture<Output = V> + 'static) {
+        self.spawn(key, task)
+    }
+
+    fn insert(&mut self, key: K, handle: AbortHandle) {
+        self.keys.insert(handle.id(), key.clone());
+        if let Some(prev) = self.handles.insert(key, handle) {
+            prev.abort();
+            self.keys.remove(&prev.id());
+        }
+    }
+
+    /// Aborts the task corresponding to the provided key.
+    ///
+    /// Returns `true` if a task was found and aborted. The aborted task is still
+    /// returned from [`JoinMap::join_next`] with a [cancelled] `JoinError`, unless it
+    /// completed before it was aborted.
+    ///
+    /// [cancelled]: JoinError::is_cancelled
+    pub fn abort<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        match self.handles.get(key) {
+            Some(handle) => {
+                handle.abort();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Aborts all tasks inside this `JoinMap`.
+    pub fn abort_all(&mut self) {
+        self.tasks.abort_all();
+    }
+
+    /// Returns `true` if this `JoinMap` contains a task for the provided key.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.handles.contains_key(key)
+    }
+
+    /// Waits until one of the tasks in the map completes and returns its output,
+    /// along with the key of the completed task.
+    ///
+    /// Returns `None` if the map is empty.
+    pub async fn join_next(&mut self) -> Option<(K, Result<V, JoinError>)> {
+        std::future::poll_fn(|cx| self.poll_join_next(cx)).await
+    }
+
+    /// Polls for one of the tasks in the map to complete.
+    ///
+    /// If this returns `Poll::Ready(Some(_))`, then the task that completed is removed
+    /// from the map.
+    ///
+    /// When the method returns `Poll::Pending`, the `Waker` in the provided `Context` is
+    /// scheduled to receive a wakeup when a task in the `JoinMap` completes.
+    pub fn poll_join_next(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<(K, Result<V, JoinError>)>> {
+        loop {
+            let (id, res) = match self.tasks.poll_join_next_with_id(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Ready(Some(Ok((id, out)))) => (id, Ok(out)),
+                Poll::Ready(Some(Err(err))) => (err.id(), Err(err)),
+            };
+            // Tasks that were replaced by a task with the same key aren't tracked anymore.
+            if let Some(key) = self.keys.remove(&id) {
+                self.handles.remove(&key);
+                let res = PanicPolicy::apply(None, res, || self.tasks.abort_all());
+                return Poll::Ready(Some((key, res)));
+            }
+        }
+    }
+
+    /// Aborts all tasks and then waits for them to finish, ignoring panics.
+    pub async fn shutdown(&mut self) {
+        self.tasks.shutdown().await;
+        self.keys.clear();
+        self.handles.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    #[cfg(not(wasm_browser))]
+    use tokio::test;
+    #[cfg(wasm_browser)]
+    use wasm_bindgen_test::wasm_bindgen_test as test;
+
+    use super::*;
+
+    #[test]
+    async fn join_map_keys() {
+        let mut map = JoinMap::new();
+        map.spawn(1, async { "one" });
+        map.spawn(2, async { "two" });
+        assert_eq!(map.len(), 2);
+        assert!(map.contains_key(&1));
+        assert!(!map.contains_key(&3));
+
+        let mut results = Vec::new();
+        while let Some((key, res)) = map.join_next().await {
+            results.push((key, res.unwrap()));
+        }
+        results.sort();
+        assert_eq!(results, vec![(1, "one"), (2, "two")]);
+        assert!(map.is_empty());
+    }
+
+    #[test]
+    async fn join_map_abort() {
+        let mut map = JoinMap::new();
+        map.spawn("pending", std::future::pending::<()>());
+        map.spawn("sleeping", crate::time::sleep(Duration::from_millis(10)));
+        assert!(map.abort("pending"));
+        assert!(!map.abort("missing"));
+
+        let (key, res) = map.join_next().await.unwrap();
+        assert_eq!(key, "pending");
+        assert!(res.unwrap_err().is_cancelled());
+        assert!(!map.contains_key("pending"));
+
+        let (key, res) = map.join_next().await.unwrap();
+        assert_eq!(key, "sleeping");
+        assert!(res.is_ok());
+        assert!(map.join_next().await.is_none());
+    }
+
+    #[test]
+    async fn join_map_replace() {
+        let mut map = JoinMap::new();
+        map.spawn("key", async {
+            std::future::pending::<()>().await;
+            "old"
+        });
+        map.spawn("key", async { "new" });
+        assert_eq!(map.len(), 1);
+
+        let (key, res) = map.join_next().await.unwrap();
+        assert_eq!(key, "key");
+        assert_eq!(res.unwrap(), "new");
+        assert!(map.join_next().await.is_none());
+    }
+}
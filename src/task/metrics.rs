@@ -0,0 +1,306 @@
+//! Implements the opt-in poll metrics of tasks, see [`TaskMetrics`].
+
+#[cfg(not(wasm_browser))]
+use std::time::Instant;
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Wake, Waker},
+    time::Duration,
+};
+
+#[cfg(wasm_browser)]
+use web_time::Instant;
+
+/// Poll metrics of a task, or aggregated over the tasks of a [`JoinSet`].
+///
+/// A task is idle from the moment a poll returns until it's woken up, and scheduled from
+/// the moment it's woken up until it's polled again. A newly spawned task is scheduled
+/// until its first poll.
+///
+/// Obtained from [`JoinHandle::metrics`], [`AbortHandle::metrics`] and
+/// [`JoinSet::metrics`]. Only available with the `metrics` feature.
+///
+/// # Example
+///
+/// ```ignore-wasm32-unknown-unknown
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// use n0_future::task;
+///
+/// let handle = task::spawn(async {
+///     n0_future::future::yield_now().await;
+/// });
+/// while !handle.is_finished() {
+///     n0_future::future::yield_now().await;
+/// }
+/// let metrics = handle.metrics();
+/// assert_eq!(metrics.poll_count(), 2);
+/// assert!(metrics.max_poll_duration() <= metrics.busy_duration());
+/// # }
+/// ```
+///
+/// [`JoinSet`]: super::JoinSet
+/// [`JoinSet::metrics`]: super::JoinSet::metrics
+/// [`JoinHandle::metrics`]: super::JoinHandle::metrics
+/// [`AbortHandle::metrics`]: super::AbortHandle::metrics
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TaskMetrics {
+    poll_count: u64,
+    busy_duration: Duration,
+    max_poll_duration: Duration,
+    idle_duration: Duration,
+    scheduled_duration: Duration,
+}
+
+impl TaskMetrics {
+    /// Returns how often the task was polled.
+    pub fn poll_count(&self) -> u64 {
+        self.poll_count
+    }
+
+    /// Returns the total time spent polling the task.
+    pub fn busy_duration(&self) -> Duration {
+        self.busy_duration
+    }
+
+    /// Returns the time spent in the longest single poll of the task.
+    pub fn max_poll_duration(&self) -> Duration {
+        self.max_poll_duration
+    }
+
+    /// Returns the average time spent in a single poll of the task.
+    pub fn mean_poll_duration(&self) -> Duration {
+        match self.poll_count {
+            0 => Duration::ZERO,
+            count => Duration::from_nanos(as_nanos(self.busy_duration) / count),
+        }
+    }
+
+    /// Returns the total time the task spent waiting to be woken up.
+    pub fn idle_duration(&self) -> Duration {
+        self.idle_duration
+    }
+
+    /// Returns the total time the task spent waiting to be polled after it was woken up.
+    pub fn scheduled_duration(&self) -> Duration {
+        self.scheduled_duration
+    }
+}
+
+/// Counters that are updated while a task runs.
+#[derive(Debug, Default)]
+pub(super) struct Counters {
+    polls: AtomicU64,
+    busy: AtomicU64,
+    max_poll: AtomicU64,
+    idle: AtomicU64,
+    scheduled: AtomicU64,
+}
+
+impl Counters {
+    fn record_wait(&self, idle: Duration, scheduled: Duration) {
+        self.idle.fetch_add(as_nanos(idle), Ordering::Relaxed);
+        self.scheduled
+            .fetch_add(as_nanos(scheduled), Ordering::Relaxed);
+    }
+
+    fn record_poll(&self, duration: Duration) {
+        let nanos = as_nanos(duration);
+        self.polls.fetch_add(1, Ordering::Relaxed);
+        self.busy.fetch_add(nanos, Ordering::Relaxed);
+        self.max_poll.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    /// Returns a snapshot of the counters.
+    pub(super) fn snapshot(&self) -> TaskMetrics {
+        TaskMetrics {
+            poll_count: self.polls.load(Ordering::Relaxed),
+            busy_duration: Duration::from_nanos(self.busy.load(Ordering::Relaxed)),
+            max_poll_duration: Duration::from_nanos(self.max_poll.load(Ordering::Relaxed)),
+            idle_duration: Duration::from_nanos(self.idle.load(Ordering::Relaxed)),
+            scheduled_duration: Duration::from_nanos(self.scheduled.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// The counters of a task, shared by the task and all of its handles.
+#[derive(Debug, Default)]
+pub(super) struct Recorder {
+    task: Counters,
+    /// The counters of the `JoinSet` the task was spawned on.
+    set: Option<Arc<Counters>>,
+}
+
+impl Recorder {
+    /// Creates the counters of a task that also counts towards the counters of a `JoinSet`.
+    pub(super) fn in_set(set: &Arc<Counters>) -> Self {
+        Self {
+            task: Counters::default(),
+            set: Some(set.clone()),
+        }
+    }
+
+    pub(super) fn snapshot(&self) -> TaskMetrics {
+        self.task.snapshot()
+    }
+
+    fn counters(&self) -> impl Iterator<Item = &Counters> {
+        std::iter::once(&self.task).chain(self.set.as_deref())
+    }
+}
+
+/// Wakes up the task, remembering when that happened.
+#[derive(Debug)]
+struct Wakeup {
+    woken_at: Mutex<Option<Instant>>,
+    waker: Mutex<Option<Waker>>,
+}
+
+impl Wake for Wakeup {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        // The task is scheduled from the first wakeup on
+        self.woken_at
+            .lock()
+            .expect("poisoned")
+            .get_or_insert_with(Instant::now);
+        if let Some(waker) = &*self.waker.lock().expect("poisoned") {
+            waker.wake_by_ref();
+        }
+    }
+}
+
+/// Wraps a task's future to record its poll metrics.
+#[pin_project::pin_project]
+pub(super) struct Instrumented<F> {
+    #[pin]
+    fut: F,
+    recorder: Arc<Recorder>,
+    wakeup: Arc<Wakeup>,
+    /// When the task was spawned or its last poll ended.
+    last_poll_end: Instant,
+}
+
+impl<F> Instrumented<F> {
+    pub(super) fn new(fut: F, recorder: Arc<Recorder>) -> Self {
+        let now = Instant::now();
+        Self {
+            fut,
+            recorder,
+            // Counts the time until the first poll as scheduled
+            wakeup: Arc::new(Wakeup {
+                woken_at: Mutex::new(Some(now)),
+                waker: Mutex::new(None),
+            }),
+            last_poll_end: now,
+        }
+    }
+}
+
+impl<F: Future> Future for Instrumented<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let start = Instant::now();
+        // Wakeups during the previous poll count as scheduled right after it ended
+        let woken_at = match this.wakeup.woken_at.lock().expect("poisoned").take() {
+            Some(woken_at) => woken_at.clamp(*this.last_poll_end, start),
+            None => start,
+        };
+        for counters in this.recorder.counters() {
+            counters.record_wait(woken_at - *this.last_poll_end, start - woken_at);
+        }
+
+        {
+            let mut waker = this.wakeup.waker.lock().expect("poisoned");
+            if !waker.as_ref().is_some_and(|w| w.will_wake(cx.waker())) {
+                *waker = Some(cx.waker().clone());
+            }
+        }
+        let waker = Waker::from(this.wakeup.clone());
+        let poll = this.fut.poll(&mut Context::from_waker(&waker));
+
+        let end = Instant::now();
+        for counters in this.recorder.counters() {
+            counters.record_poll(end - start);
+        }
+        *this.last_poll_end = end;
+        poll
+    }
+}
+
+fn as_nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    #[cfg(not(wasm_browser))]
+    use tokio::test;
+    #[cfg(wasm_browser)]
+    use wasm_bindgen_test::wasm_bindgen_test as test;
+
+    use crate::{task, time};
+
+    /// Blocks the thread, to have the task spend time in a poll.
+    fn busy(duration: Duration) {
+        let start = super::Instant::now();
+        while start.elapsed() < duration {
+            std::hint::spin_loop();
+        }
+    }
+
+    #[test]
+    async fn metrics_record_polls() {
+        let handle = task::spawn(async {
+            busy(Duration::from_millis(2));
+            time::sleep(Duration::from_millis(10)).await;
+            busy(Duration::from_millis(5));
+        });
+        let abort_handle = handle.abort_handle();
+        while !handle.is_finished() {
+            time::sleep(Duration::from_millis(1)).await;
+        }
+
+        let metrics = handle.metrics();
+        assert_eq!(metrics, abort_handle.metrics());
+        assert!(metrics.poll_count() >= 2);
+        assert!(metrics.busy_duration() >= Duration::from_millis(7));
+        assert!(metrics.max_poll_duration() >= Duration::from_millis(5));
+        assert!(metrics.max_poll_duration() < metrics.busy_duration());
+        // The sleep is mostly spent idle
+        assert!(metrics.idle_duration() >= Duration::from_millis(5));
+        handle.await.unwrap();
+    }
+
+    #[test]
+    async fn metrics_aggregate_join_set() {
+        let mut set = task::JoinSet::new();
+        let first = set.spawn(async { busy(Duration::from_millis(2)) });
+        let second = set.spawn(async {
+            crate::future::yield_now().await;
+            busy(Duration::from_millis(3));
+        });
+        while set.join_next().await.is_some() {}
+
+        let (first, second) = (first.metrics(), second.metrics());
+        let total = set.metrics();
+        assert_eq!(total.poll_count(), 3);
+        assert_eq!(
+            total.busy_duration(),
+            first.busy_duration() + second.busy_duration()
+        );
+        assert_eq!(total.max_poll_duration(), second.max_poll_duration());
+    }
+}
@@ -0,0 +1,906 @@
+use std::{
+    any::Any,
+    collections::HashMap,
+    fmt::{self, Debug},
+    future::Future,
+    panic::Location,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll, Waker},
+};
+
+use futures_lite::Stream;
+
+use super::{panic_message, PanicPolicy, TaskMeta};
+use crate::runtime;
+
+mod custom;
+
+/// An opaque ID that uniquely identifies a task relative to all other currently running tasks.
+///
+/// Wraps a [`tokio::task::Id`] for tasks that were spawned on tokio.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, derive_more::Display)]
+pub struct Id(IdRepr);
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, derive_more::Display)]
+enum IdRepr {
+    Tokio(tokio::task::Id),
+    Custom(u64),
+    #[cfg(feature = "shim")]
+    Shim(u64),
+}
+
+impl From<tokio::task::Id> for Id {
+    fn from(id: tokio::task::Id) -> Self {
+        Self(IdRepr::Tokio(id))
+    }
+}
+
+#[cfg(feature = "shim")]
+impl From<super::shim::Id> for Id {
+    fn from(id: super::shim::Id) -> Self {
+        Self(IdRepr::Shim(id.0))
+    }
+}
+
+/// A collection of tasks spawned on tokio, or on the installed [`Runtime`], which keeps
+/// track of the metadata of its tasks.
+///
+/// Mirrors the API of [`tokio::task::JoinSet`].
+///
+/// [`Runtime`]: crate::runtime::Runtime
+pub struct JoinSet<T> {
+    handles: futures_buffered::FuturesUnordered<JoinHandleWithId<T>>,
+    // We need to keep a second set of handles so we can access them for cancellation.
+    // They're keyed by task id, so that joined tasks can be removed one by one.
+    to_cancel: HashMap<Id, AbortHandle>,
+    // The waker of the most recent pending `poll_join_next_with_id` call.
+    // Newly spawned tasks aren't polled by `handles` until it is polled again,
+    // so `spawn` needs to wake this to have the new task be observed.
+    waker: Option<Waker>,
+    panic_policy: Option<PanicPolicy>,
+    #[cfg(feature = "metrics")]
+    metrics: Arc<super::metrics::Counters>,
+}
+
+impl<T> Debug for JoinSet<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JoinSet").field("len", &self.len()).finish()
+    }
+}
+
+impl<T> Default for JoinSet<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> JoinSet<T> {
+    /// Creates a new, empty `JoinSet`
+    pub fn new() -> Self {
+        Self {
+            handles: futures_buffered::FuturesUnordered::new(),
+            to_cancel: HashMap::new(),
+            waker: None,
+            panic_policy: None,
+            #[cfg(feature = "metrics")]
+            metrics: Default::default(),
+        }
+    }
+
+    /// Returns whether there's any tasks that are either still running or
+    /// have pending results in this `JoinSet`.
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+
+    /// Returns the amount of tasks that are either still running or have
+    /// pending results in this `JoinSet`.
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    /// Sets what happens when a task in this set panicked, overriding the global
+    /// [`PanicPolicy`] set with [`set_panic_policy`].
+    ///
+    /// [`set_panic_policy`]: super::set_panic_policy
+    pub fn set_panic_policy(&mut self, policy: PanicPolicy) {
+        self.panic_policy = Some(policy);
+    }
+
+    /// Returns the poll metrics aggregated over all tasks that were spawned on this
+    /// `JoinSet`, including the ones that already completed.
+    ///
+    /// Only available with the `metrics` feature.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> super::TaskMetrics {
+        self.metrics.snapshot()
+    }
+}
+
+impl<T: 'static> JoinSet<T> {
+    /// Spawns a task into this `JoinSet`.
+    ///
+    /// See [`tokio::task::JoinSet::spawn`].
+    #[track_caller]
+    pub fn spawn<F>(&mut self, task: F) -> AbortHandle
+    where
+        F: Future<Output = T> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.spawn_at(Location::caller(), task)
+    }
+
+    /// Spawns a task into this `JoinSet`, reporting `location` as where it was spawned.
+    pub(super) fn spawn_at<F>(
+        &mut self,
+        location: &'static Location<'static>,
+        task: F,
+    ) -> AbortHandle
+    where
+        F: Future<Output = T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let meta = TaskMeta::at(None, location);
+        #[cfg(feature = "metrics")]
+        let meta = meta.in_set(&self.metrics);
+        self.insert(spawn_with_meta(meta, task))
+    }
+
+    /// Spawns a `!Send` task into this `JoinSet`.
+    ///
+    /// See [`tokio::task::JoinSet::spawn_local`].
+    #[track_caller]
+    pub fn spawn_local<F>(&mut self, task: F) -> AbortHandle
+    where
+        F: Future<Output = T> + 'static,
+        T: 'static,
+    {
+        let meta = TaskMeta::new(None);
+        #[cfg(feature = "metrics")]
+        let meta = meta.in_set(&self.metrics);
+        self.insert(spawn_local_with_meta(meta, task))
+    }
+
+    /// Spawns a task into this `JoinSet`, on the provided tokio runtime.
+    ///
+    /// See [`tokio::task::JoinSet::spawn_on`].
+    #[track_caller]
+    pub fn spawn_on<F>(&mut self, task: F, handle: &tokio::runtime::Handle) -> AbortHandle
+    where
+        F: Future<Output = T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let meta = TaskMeta::new(None);
+        #[cfg(feature = "metrics")]
+        let meta = meta.in_set(&self.metrics);
+        let handle = handle.spawn(meta.track(task));
+        self.insert(JoinHandle::new(HandleRepr::Tokio(handle), meta))
+    }
+
+    /// Spawns a `!Send` task into this `JoinSet`, on the provided [`LocalSet`].
+    ///
+    /// See [`tokio::task::JoinSet::spawn_local_on`].
+    #[track_caller]
+    pub fn spawn_local_on<F>(&mut self, task: F, local_set: &LocalSet) -> AbortHandle
+    where
+        F: Future<Output = T> + 'static,
+        T: 'static,
+    {
+        let meta = TaskMeta::new(None);
+        #[cfg(feature = "metrics")]
+        let meta = meta.in_set(&self.metrics);
+        let handle = local_set.inner.spawn_local(meta.track(task));
+        self.insert(JoinHandle::new(HandleRepr::Tokio(handle), meta))
+    }
+
+    /// Spawns a blocking closure into this `JoinSet`, on tokio's blocking thread pool,
+    /// or using [`Runtime::spawn_blocking`] if a runtime is installed.
+    ///
+    /// Blocking tasks show up in the task registry and in traces, but don't have poll
+    /// metrics.
+    ///
+    /// See [`tokio::task::JoinSet::spawn_blocking`].
+    ///
+    /// [`Runtime::spawn_blocking`]: crate::runtime::Runtime::spawn_blocking
+    #[track_caller]
+    pub fn spawn_blocking<F>(&mut self, f: F) -> AbortHandle
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send,
+    {
+        let meta = TaskMeta::new(None);
+        let f = meta.track_blocking(f);
+        let repr = match runtime::installed() {
+            Some(runtime) => HandleRepr::Custom(custom::spawn_blocking(&*runtime, f)),
+            None => HandleRepr::Tokio(tokio::task::spawn_blocking(f)),
+        };
+        self.insert(JoinHandle::new(repr, meta))
+    }
+
+    /// Spawns a blocking closure into this `JoinSet`, on the blocking thread pool of the
+    /// provided tokio runtime.
+    ///
+    /// See [`tokio::task::JoinSet::spawn_blocking_on`].
+    #[track_caller]
+    pub fn spawn_blocking_on<F>(&mut self, f: F, handle: &tokio::runtime::Handle) -> AbortHandle
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send,
+    {
+        let meta = TaskMeta::new(None);
+        let handle = handle.spawn_blocking(meta.track_blocking(f));
+        self.insert(JoinHandle::new(HandleRepr::Tokio(handle), meta))
+    }
+
+    pub(super) fn insert(&mut self, handle: JoinHandle<T>) -> AbortHandle {
+        let abort_handle = handle.abort_handle();
+        self.to_cancel
+            .insert(abort_handle.id(), abort_handle.clone());
+        self.handles.push(JoinHandleWithId(handle));
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+        abort_handle
+    }
+
+    /// Aborts all tasks inside this `JoinSet`
+    pub fn abort_all(&mut self) {
+        self.to_cancel.values().for_each(AbortHandle::abort);
+    }
+
+    /// Awaits the next `JoinSet`'s completion.
+    ///
+    /// Returns `None` if the set is empty.
+    pub async fn join_next(&mut self) -> Option<Result<T, JoinError>> {
+        self.join_next_with_id()
+            .await
+            .map(|ret| ret.map(|(_id, out)| out))
+    }
+
+    /// Waits until one of the tasks in the set completes and returns its
+    /// output, along with the [task ID] of the completed task.
+    ///
+    /// Returns `None` if the set is empty.
+    ///
+    /// When this method returns an error, then the id of the task that failed can be accessed
+    /// using the [`JoinError::id`] method.
+    ///
+    /// [task ID]: crate::task::Id
+    pub async fn join_next_with_id(&mut self) -> Option<Result<(Id, T), JoinError>> {
+        std::future::poll_fn(|cx| self.poll_join_next_with_id(cx)).await
+    }
+
+    /// Polls for one of the tasks in the set to complete.
+    ///
+    /// See [`tokio::task::JoinSet::poll_join_next`].
+    pub fn poll_join_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<T, JoinError>>> {
+        match self.poll_join_next_with_id(cx) {
+            Poll::Ready(Some(Ok((_, ret)))) => Poll::Ready(Some(Ok(ret))),
+            Poll::Ready(Some(Err(e))) => Poll::Ready(Some(Err(e))),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    /// Polls for one of the tasks in the set to complete.
+    ///
+    /// See [`tokio::task::JoinSet::poll_join_next_with_id`].
+    pub fn poll_join_next_with_id(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<(Id, T), JoinError>>> {
+        let ret = self.poll_join_next_unchecked(cx);
+        let policy = self.panic_policy;
+        ret.map(|ret| ret.map(|ret| PanicPolicy::apply(policy, ret, || self.abort_all())))
+    }
+
+    /// Polls for one of the tasks in the set to complete, without applying the panic
+    /// policy.
+    pub(super) fn poll_join_next_unchecked(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<(Id, T), JoinError>>> {
+        let ret = Pin::new(&mut self.handles).poll_next(cx);
+        match &ret {
+            Poll::Pending => match self.waker {
+                // clone_from can be marginally faster in some cases
+                Some(ref mut waker) => waker.clone_from(cx.waker()),
+                None => self.waker = Some(cx.waker().clone()),
+            },
+            Poll::Ready(ret) => {
+                self.waker = None;
+                // clean up the handle of the task that was joined
+                let id = match ret {
+                    Some(Ok((id, _))) => Some(*id),
+                    Some(Err(err)) => Some(err.id()),
+                    None => None,
+                };
+                if let Some(id) = id {
+                    self.to_cancel.remove(&id);
+                }
+            }
+        }
+        ret
+    }
+
+    /// Tries to join one of the tasks in the set that has completed, without waiting.
+    ///
+    /// Returns `None` if there are no completed tasks, or if the set is empty.
+    ///
+    /// See [`tokio::task::JoinSet::try_join_next`].
+    pub fn try_join_next(&mut self) -> Option<Result<T, JoinError>> {
+        self.try_join_next_with_id()
+            .map(|ret| ret.map(|(_id, out)| out))
+    }
+
+    /// Tries to join one of the tasks in the set that has completed, without waiting,
+    /// and returns its output along with the [task ID] of the completed task.
+    ///
+    /// Returns `None` if there are no completed tasks, or if the set is empty.
+    ///
+    /// See [`tokio::task::JoinSet::try_join_next_with_id`].
+    ///
+    /// [task ID]: crate::task::Id
+    pub fn try_join_next_with_id(&mut self) -> Option<Result<(Id, T), JoinError>> {
+        // Keeps the waker of a pending `poll_join_next_with_id` call registered
+        let waker = self.waker.clone().unwrap_or_else(|| Waker::noop().clone());
+        match self.poll_join_next_with_id(&mut Context::from_waker(&waker)) {
+            Poll::Ready(ret) => ret,
+            Poll::Pending => None,
+        }
+    }
+
+    /// Awaits the completion of all tasks in this `JoinSet`, returning a vector of their results.
+    ///
+    /// The results will be stored in the order they completed not the order they were spawned.
+    ///
+    /// # Panics
+    ///
+    /// If any tasks on the `JoinSet` fail with a [`JoinError`], then this call to `join_all`
+    /// will panic and all remaining tasks on the `JoinSet` are cancelled. When a task
+    /// panicked, its panic is resumed with the original payload.
+    ///
+    /// See [`tokio::task::JoinSet::join_all`].
+    pub async fn join_all(mut self) -> Vec<T> {
+        let mut output = Vec::with_capacity(self.len());
+        while let Some(res) = std::future::poll_fn(|cx| self.poll_join_next_unchecked(cx)).await {
+            match res {
+                Ok((_id, t)) => output.push(t),
+                Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+                Err(err) => panic!("{err}"),
+            }
+        }
+        output
+    }
+
+    /// Aborts all tasks and then waits for them to finish, ignoring panics.
+    pub async fn shutdown(&mut self) {
+        self.abort_all();
+        while let Some(_res) = std::future::poll_fn(|cx| self.poll_join_next_unchecked(cx)).await {}
+    }
+
+    /// Removes all tasks from this `JoinSet` without aborting them.
+    ///
+    /// See [`tokio::task::JoinSet::detach_all`].
+    pub fn detach_all(&mut self) {
+        // Dropping the handles detaches the tasks
+        self.handles = futures_buffered::FuturesUnordered::new();
+        self.to_cancel.clear();
+    }
+}
+
+impl<T> Drop for JoinSet<T> {
+    fn drop(&mut self) {
+        self.to_cancel.values().for_each(AbortHandle::abort);
+    }
+}
+
+impl<T, F> FromIterator<F> for JoinSet<T>
+where
+    F: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    #[track_caller]
+    fn from_iter<I: IntoIterator<Item = F>>(iter: I) -> Self {
+        let mut set = Self::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl<T, F> Extend<F> for JoinSet<T>
+where
+    F: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    #[track_caller]
+    fn extend<I: IntoIterator<Item = F>>(&mut self, iter: I) {
+        for task in iter {
+            self.spawn(task);
+        }
+    }
+}
+
+/// A handle to a spawned task.
+///
+/// Wraps a [`tokio::task::JoinHandle`], or the handle of a task spawned on the installed
+/// [`Runtime`], together with the task's metadata.
+///
+/// [`Runtime`]: crate::runtime::Runtime
+pub struct JoinHandle<T> {
+    repr: HandleRepr<T>,
+    meta: TaskMeta,
+}
+
+enum HandleRepr<T> {
+    Tokio(tokio::task::JoinHandle<T>),
+    Custom(custom::Task<T>),
+}
+
+impl<T> Debug for JoinHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JoinHandle")
+            .field("id", &self.id())
+            .field("name", &self.name())
+            .field("location", &self.meta.location)
+            .field("finished", &self.is_finished())
+            .finish()
+    }
+}
+
+impl<T> JoinHandle<T> {
+    fn new(repr: HandleRepr<T>, meta: TaskMeta) -> Self {
+        let handle = Self { repr, meta };
+        handle.meta.set_id(handle.id());
+        handle
+    }
+
+    /// Aborts this task.
+    pub fn abort(&self) {
+        match &self.repr {
+            HandleRepr::Tokio(handle) => handle.abort(),
+            HandleRepr::Custom(task) => task.state.cancel(),
+        }
+    }
+
+    /// Returns a new [`AbortHandle`] that can be used to remotely abort this task.
+    pub fn abort_handle(&self) -> AbortHandle {
+        let repr = match &self.repr {
+            HandleRepr::Tokio(handle) => AbortRepr::Tokio(handle.abort_handle()),
+            HandleRepr::Custom(task) => AbortRepr::Custom(task.state.clone()),
+        };
+        AbortHandle {
+            repr,
+            meta: self.meta.clone(),
+        }
+    }
+
+    /// Returns a [task ID] that uniquely identifies this task relative to other
+    /// currently spawned tasks.
+    ///
+    /// [task ID]: crate::task::Id
+    pub fn id(&self) -> Id {
+        match &self.repr {
+            HandleRepr::Tokio(handle) => handle.id().into(),
+            HandleRepr::Custom(task) => Id(IdRepr::Custom(task.state.id)),
+        }
+    }
+
+    /// Returns the name of this task, if it was spawned with one using [`Builder::name`].
+    ///
+    /// [`Builder::name`]: crate::task::Builder::name
+    pub fn name(&self) -> Option<&str> {
+        self.meta.name()
+    }
+
+    /// Returns the poll metrics of this task.
+    ///
+    /// Only available with the `metrics` feature.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> super::TaskMetrics {
+        self.meta.metrics()
+    }
+
+    /// Checks if the task associated with this `JoinHandle` has finished.
+    pub fn is_finished(&self) -> bool {
+        match &self.repr {
+            HandleRepr::Tokio(handle) => handle.is_finished(),
+            HandleRepr::Custom(task) => task.state.is_finished(),
+        }
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let id = self.id();
+        let name = self.meta.name.clone();
+        match &mut self.repr {
+            HandleRepr::Tokio(handle) => Pin::new(handle)
+                .poll(cx)
+                .map_err(|inner| JoinError::from_tokio(inner, name)),
+            HandleRepr::Custom(task) => {
+                Pin::new(task)
+                    .poll(cx)
+                    .map_err(|cause| JoinError { cause, id, name })
+            }
+        }
+    }
+}
+
+/// Wraps the handle of a task that was spawned directly on tokio.
+///
+/// Since the task's future wasn't wrapped when it was spawned, it doesn't show up in the
+/// task registry or in traces, and doesn't have poll metrics.
+impl<T> From<tokio::task::JoinHandle<T>> for JoinHandle<T> {
+    #[track_caller]
+    fn from(handle: tokio::task::JoinHandle<T>) -> Self {
+        Self::new(HandleRepr::Tokio(handle), TaskMeta::untracked())
+    }
+}
+
+/// Unwraps the underlying tokio handle. The task is still tracked until it completes.
+///
+/// Fails for tasks that were spawned on an installed [`Runtime`] instead of tokio.
+///
+/// [`Runtime`]: crate::runtime::Runtime
+impl<T> TryFrom<JoinHandle<T>> for tokio::task::JoinHandle<T> {
+    type Error = JoinHandle<T>;
+
+    fn try_from(handle: JoinHandle<T>) -> Result<Self, Self::Error> {
+        match handle.repr {
+            HandleRepr::Tokio(inner) => Ok(inner),
+            repr => Err(JoinHandle {
+                repr,
+                meta: handle.meta,
+            }),
+        }
+    }
+}
+
+struct JoinHandleWithId<T>(JoinHandle<T>);
+
+impl<T> Future for JoinHandleWithId<T> {
+    type Output = Result<(Id, T), JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match Pin::new(&mut self.0).poll(cx) {
+            Poll::Ready(out) => Poll::Ready(out.map(|out| (self.0.id(), out))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// An owned permission to abort a spawned task, without awaiting its completion.
+#[derive(Clone)]
+pub struct AbortHandle {
+    repr: AbortRepr,
+    meta: TaskMeta,
+}
+
+#[derive(Clone)]
+enum AbortRepr {
+    Tokio(tokio::task::AbortHandle),
+    Custom(Arc<custom::State>),
+}
+
+impl Debug for AbortHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AbortHandle")
+            .field("id", &self.id())
+            .field("name", &self.name())
+            .field("location", &self.meta.location)
+            .field("finished", &self.is_finished())
+            .finish()
+    }
+}
+
+impl AbortHandle {
+    /// Abort the task associated with the handle.
+    pub fn abort(&self) {
+        match &self.repr {
+            AbortRepr::Tokio(handle) => handle.abort(),
+            AbortRepr::Custom(state) => state.cancel(),
+        }
+    }
+
+    /// Returns a [task ID] that uniquely identifies this task relative to other
+    /// currently spawned tasks.
+    ///
+    /// [task ID]: crate::task::Id
+    pub fn id(&self) -> Id {
+        match &self.repr {
+            AbortRepr::Tokio(handle) => handle.id().into(),
+            AbortRepr::Custom(state) => Id(IdRepr::Custom(state.id)),
+        }
+    }
+
+    /// Returns the name of the task associated with this handle, if it was spawned
+    /// with one using [`Builder::name`].
+    ///
+    /// [`Builder::name`]: crate::task::Builder::name
+    pub fn name(&self) -> Option<&str> {
+        self.meta.name()
+    }
+
+    /// Returns the poll metrics of this task.
+    ///
+    /// Only available with the `metrics` feature.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> super::TaskMetrics {
+        self.meta.metrics()
+    }
+
+    /// Checks if the task associated with this `AbortHandle` has finished.
+    pub fn is_finished(&self) -> bool {
+        match &self.repr {
+            AbortRepr::Tokio(handle) => handle.is_finished(),
+            AbortRepr::Custom(state) => state.is_finished(),
+        }
+    }
+}
+
+/// Unwraps the underlying tokio handle.
+///
+/// Fails for tasks that were spawned on an installed [`Runtime`] instead of tokio.
+///
+/// [`Runtime`]: crate::runtime::Runtime
+impl TryFrom<AbortHandle> for tokio::task::AbortHandle {
+    type Error = AbortHandle;
+
+    fn try_from(handle: AbortHandle) -> Result<Self, Self::Error> {
+        match handle.repr {
+            AbortRepr::Tokio(inner) => Ok(inner),
+            repr => Err(AbortHandle {
+                repr,
+                meta: handle.meta,
+            }),
+        }
+    }
+}
+
+/// An error that can occur when waiting for the completion of a task.
+///
+/// Like [`tokio::task::JoinError`], but also carries the name of the task.
+#[derive(derive_more::Display, Debug)]
+#[display("task {id} {cause}")]
+pub struct JoinError {
+    cause: JoinErrorCause,
+    id: Id,
+    name: Option<Arc<str>>,
+}
+
+#[derive(Debug)]
+enum JoinErrorCause {
+    /// The error that's returned when the task that's being waited on
+    /// has been cancelled.
+    Cancelled,
+    /// The error that's returned when the task that's being waited on
+    /// has panicked.
+    Panicked(Box<dyn Any + Send + 'static>),
+}
+
+// Same as tokio's messages
+impl fmt::Display for JoinErrorCause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Cancelled => write!(f, "was cancelled"),
+            Self::Panicked(payload) => match panic_message(payload.as_ref()) {
+                Some(message) => write!(f, "panicked with message {message:?}"),
+                None => write!(f, "panicked"),
+            },
+        }
+    }
+}
+
+impl std::error::Error for JoinError {}
+
+impl From<JoinError> for std::io::Error {
+    fn from(err: JoinError) -> Self {
+        let message = match err.cause {
+            JoinErrorCause::Cancelled => "task was cancelled",
+            JoinErrorCause::Panicked(_) => "task panicked",
+        };
+        std::io::Error::other(message)
+    }
+}
+
+impl JoinError {
+    fn from_tokio(inner: tokio::task::JoinError, name: Option<Arc<str>>) -> Self {
+        let id = inner.id().into();
+        let cause = match inner.try_into_panic() {
+            Ok(payload) => JoinErrorCause::Panicked(payload),
+            Err(_) => JoinErrorCause::Cancelled,
+        };
+        Self { cause, id, name }
+    }
+
+    /// Returns whether this join error is due to cancellation.
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self.cause, JoinErrorCause::Cancelled)
+    }
+
+    /// Returns whether this join error is due to the task panicking.
+    pub fn is_panic(&self) -> bool {
+        matches!(self.cause, JoinErrorCause::Panicked(_))
+    }
+
+    /// Consumes the join error, returning the object with which the task panicked.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the error does not represent the task panicking.
+    #[track_caller]
+    pub fn into_panic(self) -> Box<dyn Any + Send + 'static> {
+        self.try_into_panic()
+            .expect("`JoinError` reason is not a panic.")
+    }
+
+    /// Consumes the join error, returning the object with which the task
+    /// panicked if the task terminated due to a panic. Otherwise, `self` is
+    /// returned.
+    pub fn try_into_panic(self) -> Result<Box<dyn Any + Send + 'static>, JoinError> {
+        match self.cause {
+            JoinErrorCause::Panicked(payload) => Ok(payload),
+            cause => Err(JoinError { cause, ..self }),
+        }
+    }
+
+    /// Returns the message the task panicked with, if it panicked with a `&str` or
+    /// `String` payload, as is the case for `panic!` and friends.
+    pub fn panic_message(&self) -> Option<&str> {
+        match &self.cause {
+            JoinErrorCause::Panicked(payload) => panic_message(payload.as_ref()),
+            JoinErrorCause::Cancelled => None,
+        }
+    }
+
+    /// Returns a task ID that identifies the task which errored relative to other currently spawned tasks.
+    pub fn id(&self) -> Id {
+        self.id
+    }
+
+    /// Returns the name of the task which errored, if it was spawned with one
+    /// using [`Builder::name`].
+    ///
+    /// [`Builder::name`]: crate::task::Builder::name
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+}
+
+/// Spawns a new asynchronous task, returning a [`JoinHandle`] for it.
+///
+/// The task is spawned on the installed [`Runtime`], or on tokio if none is installed,
+/// see [`tokio::task::spawn`].
+///
+/// [`Runtime`]: crate::runtime::Runtime
+#[track_caller]
+pub fn spawn<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    spawn_with_meta(TaskMeta::new(None), future)
+}
+
+#[track_caller]
+pub(super) fn spawn_with_meta<F>(meta: TaskMeta, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    if let Some(runtime) = runtime::installed() {
+        let task = custom::spawn(runtime, meta.track(future));
+        return JoinHandle::new(HandleRepr::Custom(task), meta);
+    }
+
+    #[cfg(all(tokio_unstable, feature = "tracing"))]
+    let handle = {
+        let mut builder = tokio::task::Builder::new();
+        if let Some(name) = meta.name() {
+            builder = builder.name(name);
+        }
+        builder
+            .spawn(meta.track(future))
+            .expect("spawning a task with tokio's task builder failed")
+    };
+    #[cfg(not(all(tokio_unstable, feature = "tracing")))]
+    let handle = tokio::spawn(meta.track(future));
+
+    JoinHandle::new(HandleRepr::Tokio(handle), meta)
+}
+
+/// Spawns a `!Send` future on the current [`LocalSet`], returning a [`JoinHandle`] for it.
+///
+/// The task is spawned using [`Runtime::spawn_local`] if a runtime is installed, see
+/// [`tokio::task::spawn_local`] otherwise.
+///
+/// # Panics
+///
+/// Without an installed runtime, this function panics if called outside of a
+/// [`LocalSet`].
+///
+/// [`Runtime::spawn_local`]: crate::runtime::Runtime::spawn_local
+#[track_caller]
+pub fn spawn_local<F>(future: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    spawn_local_with_meta(TaskMeta::new(None), future)
+}
+
+#[track_caller]
+pub(super) fn spawn_local_with_meta<F>(meta: TaskMeta, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    if let Some(runtime) = runtime::installed() {
+        let task = custom::spawn_local(runtime, meta.track(future));
+        return JoinHandle::new(HandleRepr::Custom(task), meta);
+    }
+
+    #[cfg(all(tokio_unstable, feature = "tracing"))]
+    let handle = {
+        let mut builder = tokio::task::Builder::new();
+        if let Some(name) = meta.name() {
+            builder = builder.name(name);
+        }
+        builder
+            .spawn_local(meta.track(future))
+            .expect("spawning a task with tokio's task builder failed")
+    };
+    #[cfg(not(all(tokio_unstable, feature = "tracing")))]
+    let handle = tokio::task::spawn_local(meta.track(future));
+
+    JoinHandle::new(HandleRepr::Tokio(handle), meta)
+}
+
+/// A set of tasks which are executed on the same thread.
+///
+/// Wraps a [`tokio::task::LocalSet`], so that tasks spawned on it return this
+/// module's [`JoinHandle`].
+#[derive(Debug, Default)]
+pub struct LocalSet {
+    inner: tokio::task::LocalSet,
+}
+
+impl LocalSet {
+    /// Returns a new local task set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spawns a `!Send` task onto the local task set.
+    ///
+    /// See [`tokio::task::LocalSet::spawn_local`].
+    #[track_caller]
+    pub fn spawn_local<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+        F::Output: 'static,
+    {
+        let meta = TaskMeta::new(None);
+        let handle = self.inner.spawn_local(meta.track(future));
+        JoinHandle::new(HandleRepr::Tokio(handle), meta)
+    }
+
+    /// Runs a future to completion on the local set, returning its output.
+    ///
+    /// See [`tokio::task::LocalSet::run_until`].
+    pub async fn run_until<F: Future>(&self, future: F) -> F::Output {
+        self.inner.run_until(future).await
+    }
+}
+
+impl Future for LocalSet {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.inner).poll(cx)
+    }
+}
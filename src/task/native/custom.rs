@@ -0,0 +1,177 @@
+//! Implements the tasks that are spawned on a [`Runtime`] installed using
+//! [`runtime::set_global`] or [`runtime::with_runtime`], in place of tokio.
+//!
+//! [`runtime::set_global`]: crate::runtime::set_global
+//! [`runtime::with_runtime`]: crate::runtime::with_runtime
+
+use std::{
+    future::Future,
+    panic::AssertUnwindSafe,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use futures_util::task::AtomicWaker;
+use tokio::sync::oneshot;
+
+use super::JoinErrorCause;
+use crate::runtime::{Runtime, WithRuntime};
+
+static NEXT_TASK_ID: AtomicU64 = AtomicU64::new(1);
+
+/// The state of a task shared between the task and its handles.
+#[derive(Debug)]
+pub(super) struct State {
+    pub(super) id: u64,
+    cancelled: AtomicBool,
+    finished: AtomicBool,
+    waker: AtomicWaker,
+}
+
+impl State {
+    fn new() -> Arc<Self> {
+        Arc::new(Self {
+            id: NEXT_TASK_ID.fetch_add(1, Ordering::Relaxed),
+            cancelled: AtomicBool::new(false),
+            finished: AtomicBool::new(false),
+            waker: AtomicWaker::new(),
+        })
+    }
+
+    pub(super) fn cancel(&self) {
+        // Like in tokio, aborting a task that already completed has no effect.
+        if !self.finished.load(Ordering::Acquire) {
+            self.cancelled.store(true, Ordering::Release);
+            self.waker.wake();
+        }
+    }
+
+    pub(super) fn is_finished(&self) -> bool {
+        self.finished.load(Ordering::Acquire)
+    }
+}
+
+/// The future driving a spawned task, which completes early when the task is aborted,
+/// and catches the task's panics.
+#[pin_project::pin_project(PinnedDrop)]
+struct Abortable<F> {
+    #[pin]
+    fut: F,
+    state: Arc<State>,
+}
+
+impl<F: Future> Future for Abortable<F> {
+    type Output = Result<F::Output, JoinErrorCause>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        this.state.waker.register(cx.waker());
+        let res = if this.state.cancelled.load(Ordering::Acquire) {
+            Err(JoinErrorCause::Cancelled)
+        } else {
+            match std::panic::catch_unwind(AssertUnwindSafe(|| this.fut.poll(cx))) {
+                Ok(Poll::Pending) => return Poll::Pending,
+                Ok(Poll::Ready(out)) => Ok(out),
+                Err(payload) => Err(JoinErrorCause::Panicked(payload)),
+            }
+        };
+        this.state.finished.store(true, Ordering::Release);
+        Poll::Ready(res)
+    }
+}
+
+#[pin_project::pinned_drop]
+impl<F> PinnedDrop for Abortable<F> {
+    fn drop(self: Pin<&mut Self>) {
+        // The runtime might drop the task before it completed
+        self.state.finished.store(true, Ordering::Release);
+    }
+}
+
+/// A task spawned on a runtime, which sends its result back to its handle.
+#[derive(Debug)]
+pub(super) struct Task<T> {
+    pub(super) state: Arc<State>,
+    result: oneshot::Receiver<Result<T, JoinErrorCause>>,
+}
+
+impl<T> Task<T> {
+    /// Returns the task's handle along with the future that drives it.
+    fn new<F>(fut: F) -> (Self, impl Future<Output = ()>)
+    where
+        F: Future<Output = T>,
+    {
+        let state = State::new();
+        let (tx, result) = oneshot::channel();
+        let fut = Abortable {
+            fut,
+            state: state.clone(),
+        };
+        let run = async move {
+            // The handle might be gone, which detached the task
+            tx.send(fut.await).ok();
+        };
+        (Self { state, result }, run)
+    }
+}
+
+impl<T> Future for Task<T> {
+    type Output = Result<T, JoinErrorCause>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.result)
+            .poll(cx)
+            // The runtime dropped the task before it completed
+            .map(|res| res.unwrap_or(Err(JoinErrorCause::Cancelled)))
+    }
+}
+
+/// Spawns `fut` on `runtime`, which is installed for the task as well.
+pub(super) fn spawn<F>(runtime: Arc<dyn Runtime>, fut: F) -> Task<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let (task, run) = Task::new(fut);
+    runtime.spawn(Box::pin(WithRuntime::new(runtime.clone(), run)));
+    task
+}
+
+/// Spawns the `!Send` future `fut` on `runtime`, which is installed for the task as well.
+pub(super) fn spawn_local<F>(runtime: Arc<dyn Runtime>, fut: F) -> Task<F::Output>
+where
+    F: Future + 'static,
+    F::Output: 'static,
+{
+    let (task, run) = Task::new(fut);
+    runtime.spawn_local(Box::pin(WithRuntime::new(runtime.clone(), run)));
+    task
+}
+
+/// Runs the blocking closure `f` using [`Runtime::spawn_blocking`].
+pub(super) fn spawn_blocking<F, T>(runtime: &dyn Runtime, f: F) -> Task<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let state = State::new();
+    let (tx, result) = oneshot::channel();
+    runtime.spawn_blocking(Box::new({
+        let state = state.clone();
+        move || {
+            // Like in tokio, a blocking task that's aborted before it started doesn't run
+            let res = if state.cancelled.load(Ordering::Acquire) {
+                Err(JoinErrorCause::Cancelled)
+            } else {
+                std::panic::catch_unwind(AssertUnwindSafe(f)).map_err(JoinErrorCause::Panicked)
+            };
+            state.finished.store(true, Ordering::Release);
+            tx.send(res).ok();
+        }
+    }));
+    Task { state, result }
+}
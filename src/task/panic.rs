@@ -0,0 +1,188 @@
+//! Implements what happens when a task in a [`JoinSet`] panicked, see [`PanicPolicy`].
+//!
+//! [`JoinSet`]: super::JoinSet
+
+use std::{
+    any::Any,
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use super::JoinError;
+
+/// The policy of all `JoinSet`s that don't have their own, see [`set_panic_policy`].
+static GLOBAL: AtomicU8 = AtomicU8::new(PanicPolicy::ReturnError as u8);
+
+/// What happens when a task in a [`JoinSet`] panicked, once its result is joined.
+///
+/// The policy applies to the results returned from [`JoinSet::join_next`] and its
+/// variants. [`JoinSet::join_all_results`] and [`JoinSet::try_join_all`] always return
+/// panics as [`JoinError`]s, while [`JoinSet::join_all`] always resumes them.
+/// It can be set for all sets using [`set_panic_policy`], or for a single set using
+/// [`JoinSet::set_panic_policy`].
+///
+/// # Example
+///
+/// ```ignore-wasm32-unknown-unknown
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// use n0_future::task::{JoinSet, PanicPolicy};
+///
+/// let mut set = JoinSet::new();
+/// set.set_panic_policy(PanicPolicy::AbortSet);
+/// set.spawn(std::future::pending());
+/// set.spawn(async { panic!("fatal") });
+///
+/// let err = set.join_next().await.unwrap().unwrap_err();
+/// assert_eq!(err.panic_message(), Some("fatal"));
+/// // The other task was aborted
+/// assert!(set.join_next().await.unwrap().unwrap_err().is_cancelled());
+/// # }
+/// ```
+///
+/// [`JoinSet`]: super::JoinSet
+/// [`JoinSet::join_next`]: super::JoinSet::join_next
+/// [`JoinSet::join_all_results`]: super::JoinSet::join_all_results
+/// [`JoinSet::try_join_all`]: super::JoinSet::try_join_all
+/// [`JoinSet::join_all`]: super::JoinSet::join_all
+/// [`JoinSet::set_panic_policy`]: super::JoinSet::set_panic_policy
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PanicPolicy {
+    /// Returns a [`JoinError`] for the panicked task, like tokio does.
+    #[default]
+    ReturnError,
+    /// Resumes the panic in the task that joins the result, using
+    /// [`std::panic::resume_unwind`].
+    Resume,
+    /// Aborts all other tasks in the set, and returns a [`JoinError`] for the
+    /// panicked task.
+    AbortSet,
+}
+
+impl PanicPolicy {
+    fn from_u8(policy: u8) -> Self {
+        match policy {
+            p if p == Self::Resume as u8 => Self::Resume,
+            p if p == Self::AbortSet as u8 => Self::AbortSet,
+            _ => Self::ReturnError,
+        }
+    }
+
+    /// Applies the policy of a set to a task's result, calling `abort_all` to abort
+    /// the set if needed.
+    pub(super) fn apply<T, E: PanicError>(
+        policy: Option<Self>,
+        res: Result<T, E>,
+        abort_all: impl FnOnce(),
+    ) -> Result<T, E> {
+        let err = match res {
+            Err(err) if err.is_panic() => err,
+            res => return res,
+        };
+        match policy.unwrap_or_else(panic_policy) {
+            Self::ReturnError => Err(err),
+            Self::Resume => std::panic::resume_unwind(err.into_panic()),
+            Self::AbortSet => {
+                abort_all();
+                Err(err)
+            }
+        }
+    }
+}
+
+/// The `JoinError` of a backend, which isn't necessarily [`JoinError`] when the `shim`
+/// feature is enabled natively.
+pub(super) trait PanicError {
+    fn is_panic(&self) -> bool;
+
+    fn into_panic(self) -> Box<dyn Any + Send + 'static>;
+}
+
+impl PanicError for JoinError {
+    fn is_panic(&self) -> bool {
+        self.is_panic()
+    }
+
+    fn into_panic(self) -> Box<dyn Any + Send + 'static> {
+        self.into_panic()
+    }
+}
+
+/// Sets the [`PanicPolicy`] of all `JoinSet`s that don't have their own.
+///
+/// This defaults to [`PanicPolicy::ReturnError`]. Setting this to
+/// [`PanicPolicy::Resume`] in tests makes sure that panics of tasks aren't
+/// missed.
+pub fn set_panic_policy(policy: PanicPolicy) {
+    GLOBAL.store(policy as u8, Ordering::Relaxed);
+}
+
+/// Returns the [`PanicPolicy`] of all `JoinSet`s that don't have their own.
+pub fn panic_policy() -> PanicPolicy {
+    PanicPolicy::from_u8(GLOBAL.load(Ordering::Relaxed))
+}
+
+/// Returns the message of a panic payload, if it's a `&str` or `String`.
+pub(super) fn panic_message(payload: &(dyn Any + Send)) -> Option<&str> {
+    if let Some(message) = payload.downcast_ref::<&'static str>() {
+        Some(message)
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        Some(message)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::AssertUnwindSafe;
+
+    #[cfg(not(wasm_browser))]
+    use tokio::test;
+    #[cfg(wasm_browser)]
+    use wasm_bindgen_test::wasm_bindgen_test as test;
+
+    use super::*;
+    use crate::{task::JoinSet, FutureExt};
+
+    #[test]
+    async fn panic_policy_return_error() {
+        let mut set = JoinSet::new();
+        set.set_panic_policy(PanicPolicy::ReturnError);
+        set.spawn(async { panic!("oops {}", 1) });
+        let err = set.join_next().await.unwrap().unwrap_err();
+        assert!(err.is_panic());
+        assert_eq!(err.panic_message(), Some("oops 1"));
+        assert_eq!(
+            err.to_string(),
+            format!("task {} panicked with message \"oops 1\"", err.id())
+        );
+    }
+
+    #[test]
+    async fn panic_policy_resume() {
+        let mut set = JoinSet::new();
+        set.set_panic_policy(PanicPolicy::Resume);
+        set.spawn(async { panic!("oops") });
+        let payload = AssertUnwindSafe(set.join_next())
+            .catch_unwind()
+            .await
+            .unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), Some("oops"));
+    }
+
+    #[test]
+    async fn panic_policy_abort_set() {
+        let mut set = JoinSet::<()>::new();
+        set.set_panic_policy(PanicPolicy::AbortSet);
+        let pending = set.spawn(std::future::pending());
+        set.spawn(async { panic!("oops") });
+
+        let err = set.join_next().await.unwrap().unwrap_err();
+        assert_eq!(err.panic_message(), Some("oops"));
+        let err = set.join_next().await.unwrap().unwrap_err();
+        assert!(err.is_cancelled());
+        assert_eq!(err.id(), pending.id());
+        assert_eq!(err.panic_message(), None);
+    }
+}
@@ -0,0 +1,476 @@
+//! Implements the opt-in task registry, see [`dump`].
+
+use std::{
+    collections::BTreeMap,
+    fmt,
+    future::Future,
+    panic::Location,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, AtomicU8, Ordering},
+        Arc, Mutex, OnceLock, Weak,
+    },
+    task::{Context, Poll},
+};
+
+use super::Id;
+use crate::time::SystemTime;
+
+/// All tasks that are currently alive, keyed by the order they were spawned in.
+static REGISTRY: Mutex<BTreeMap<u64, Weak<Entry>>> = Mutex::new(BTreeMap::new());
+
+static NEXT_KEY: AtomicU64 = AtomicU64::new(0);
+
+/// Returns a snapshot of all tasks that were spawned through [`crate::task`] and are
+/// still alive.
+///
+/// Tasks are alive while they're running, or while a [`JoinHandle`] or [`AbortHandle`]
+/// for them exists. Tasks are listed in the order they were spawned in.
+///
+/// Only available with the `registry` feature.
+///
+/// # Example
+///
+/// ```ignore-wasm32-unknown-unknown
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// use n0_future::task;
+///
+/// let handle = task::Builder::new()
+///     .name("stuck")
+///     .spawn(std::future::pending::<()>());
+/// n0_future::future::yield_now().await;
+///
+/// let dump = task::dump();
+/// let info = dump.iter().find(|info| info.id() == handle.id()).unwrap();
+/// assert_eq!(info.name(), Some("stuck"));
+/// assert_eq!(info.state(), task::TaskState::Idle);
+/// println!("{dump}");
+/// # }
+/// ```
+///
+/// [`JoinHandle`]: super::JoinHandle
+/// [`AbortHandle`]: super::AbortHandle
+pub fn dump() -> TaskDump {
+    // Upgrading outside of the lock, as the upgraded entry can be the last one, whose drop
+    // locks the registry again
+    let entries: Vec<Weak<Entry>> = REGISTRY
+        .lock()
+        .expect("poisoned")
+        .values()
+        .cloned()
+        .collect();
+    let tasks = entries
+        .iter()
+        .filter_map(Weak::upgrade)
+        .filter_map(|entry| {
+            Some(TaskInfo {
+                id: *entry.id.get()?,
+                name: entry.name.clone(),
+                location: entry.location,
+                spawned_at: entry.spawned_at,
+                state: entry.state(),
+            })
+        })
+        .collect();
+    TaskDump { tasks }
+}
+
+/// A snapshot of the tasks that were alive when [`dump`] was called.
+///
+/// With the `serde` feature, it can be serialized as the list of its tasks.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(transparent))]
+pub struct TaskDump {
+    tasks: Vec<TaskInfo>,
+}
+
+impl TaskDump {
+    /// Returns the tasks in this snapshot, in the order they were spawned in.
+    pub fn tasks(&self) -> &[TaskInfo] {
+        &self.tasks
+    }
+
+    /// Returns an iterator over the tasks in this snapshot.
+    pub fn iter(&self) -> std::slice::Iter<'_, TaskInfo> {
+        self.tasks.iter()
+    }
+
+    /// Returns the number of tasks in this snapshot.
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    /// Returns whether this snapshot doesn't contain any tasks.
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+}
+
+impl IntoIterator for TaskDump {
+    type Item = TaskInfo;
+    type IntoIter = std::vec::IntoIter<TaskInfo>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.tasks.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a TaskDump {
+    type Item = &'a TaskInfo;
+    type IntoIter = std::slice::Iter<'a, TaskInfo>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.tasks.iter()
+    }
+}
+
+impl fmt::Display for TaskDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} tasks", self.tasks.len())?;
+        for task in &self.tasks {
+            write!(f, "\n  {task}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Information about a single task in a [`TaskDump`].
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TaskInfo {
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_display"))]
+    id: Id,
+    name: Option<Arc<str>>,
+    #[cfg_attr(feature = "serde", serde(serialize_with = "serialize_display"))]
+    location: &'static Location<'static>,
+    spawned_at: SystemTime,
+    state: TaskState,
+}
+
+impl TaskInfo {
+    /// Returns the id of the task.
+    pub fn id(&self) -> Id {
+        self.id
+    }
+
+    /// Returns the name of the task, if it was spawned with one using [`Builder::name`].
+    ///
+    /// [`Builder::name`]: super::Builder::name
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Returns the location in the source code that spawned the task.
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+
+    /// Returns the time at which the task was spawned.
+    pub fn spawned_at(&self) -> SystemTime {
+        self.spawned_at
+    }
+
+    /// Returns the state of the task when the snapshot was taken.
+    pub fn state(&self) -> TaskState {
+        self.state
+    }
+}
+
+impl fmt::Display for TaskInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "task {}", self.id)?;
+        if let Some(name) = &self.name {
+            write!(f, " {name:?}")?;
+        }
+        write!(f, " {}", self.state)?;
+        if let Ok(age) = self.spawned_at.elapsed() {
+            write!(f, " for {age:.1?}")?;
+        }
+        write!(f, ", spawned at {}", self.location)
+    }
+}
+
+/// Serializes `value` as the string it displays as.
+#[cfg(feature = "serde")]
+fn serialize_display<T: fmt::Display, S: serde::Serializer>(
+    value: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(value)
+}
+
+/// The state of a task in a [`TaskDump`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, derive_more::Display)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize),
+    serde(rename_all = "lowercase")
+)]
+pub enum TaskState {
+    /// The task is currently being polled.
+    #[display("running")]
+    Running,
+    /// The task is waiting to be woken up.
+    #[display("idle")]
+    Idle,
+    /// The task completed, was aborted or panicked, but handles to it still exist.
+    #[display("completed")]
+    Completed,
+}
+
+impl TaskState {
+    fn from_u8(state: u8) -> Self {
+        match state {
+            s if s == Self::Running as u8 => Self::Running,
+            s if s == Self::Idle as u8 => Self::Idle,
+            _ => Self::Completed,
+        }
+    }
+}
+
+/// The registry entry of a task, shared by the task and all of its handles.
+#[derive(Debug)]
+pub(super) struct Entry {
+    key: u64,
+    id: OnceLock<Id>,
+    name: Option<Arc<str>>,
+    location: &'static Location<'static>,
+    spawned_at: SystemTime,
+    state: AtomicU8,
+}
+
+impl Entry {
+    /// Adds a new idle task to the registry.
+    pub(super) fn register(
+        name: Option<Arc<str>>,
+        location: &'static Location<'static>,
+    ) -> Arc<Self> {
+        let entry = Self::untracked(name, location);
+        REGISTRY
+            .lock()
+            .expect("poisoned")
+            .insert(entry.key, Arc::downgrade(&entry));
+        entry
+    }
+
+    /// Creates the entry of a task that wasn't spawned through [`crate::task`], which
+    /// isn't added to the registry.
+    pub(super) fn untracked(
+        name: Option<Arc<str>>,
+        location: &'static Location<'static>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            key: NEXT_KEY.fetch_add(1, Ordering::Relaxed),
+            id: OnceLock::new(),
+            name,
+            location,
+            spawned_at: SystemTime::now(),
+            state: AtomicU8::new(TaskState::Idle as u8),
+        })
+    }
+
+    /// Sets the id of the task, once it's known after spawning it.
+    pub(super) fn set_id(&self, id: Id) {
+        self.id.get_or_init(|| id);
+    }
+
+    /// Runs the closure of a blocking task, which is running until it returns.
+    #[cfg(not(wasm_browser))]
+    pub(super) fn run_blocking<R>(&self, f: impl FnOnce() -> R) -> R {
+        /// Marks the task as completed once the closure returned or panicked.
+        struct Completed<'a>(&'a Entry);
+
+        impl Drop for Completed<'_> {
+            fn drop(&mut self) {
+                self.0.set_state(TaskState::Completed);
+            }
+        }
+
+        self.set_state(TaskState::Running);
+        let _completed = Completed(self);
+        f()
+    }
+
+    fn set_state(&self, state: TaskState) {
+        self.state.store(state as u8, Ordering::Relaxed);
+    }
+
+    fn state(&self) -> TaskState {
+        TaskState::from_u8(self.state.load(Ordering::Relaxed))
+    }
+}
+
+impl Drop for Entry {
+    fn drop(&mut self) {
+        if let Ok(mut registry) = REGISTRY.lock() {
+            registry.remove(&self.key);
+        }
+    }
+}
+
+/// Wraps a task's future to keep track of its state in the registry.
+#[pin_project::pin_project(PinnedDrop)]
+pub(super) struct Tracked<F> {
+    #[pin]
+    fut: F,
+    entry: Arc<Entry>,
+}
+
+impl<F> Tracked<F> {
+    pub(super) fn new(fut: F, entry: Arc<Entry>) -> Self {
+        Self { fut, entry }
+    }
+}
+
+impl<F: Future> Future for Tracked<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        this.entry.set_state(TaskState::Running);
+        let poll = this.fut.poll(cx);
+        this.entry.set_state(match poll {
+            Poll::Ready(_) => TaskState::Completed,
+            Poll::Pending => TaskState::Idle,
+        });
+        poll
+    }
+}
+
+/// Tasks are dropped once they completed, or when they're aborted.
+#[pin_project::pinned_drop]
+impl<F> PinnedDrop for Tracked<F> {
+    fn drop(self: Pin<&mut Self>) {
+        self.entry.set_state(TaskState::Completed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    #[cfg(not(wasm_browser))]
+    use tokio::test;
+    #[cfg(wasm_browser)]
+    use wasm_bindgen_test::wasm_bindgen_test as test;
+
+    use super::*;
+    use crate::task;
+
+    fn find(id: Id) -> Option<TaskInfo> {
+        dump().into_iter().find(|info| info.id() == id)
+    }
+
+    #[test]
+    async fn registry_tracks_task_state() {
+        let (tx, rx) = std::sync::mpsc::channel::<()>();
+        let handle = task::Builder::new().name("tracked").spawn(async move {
+            while rx.try_recv().is_err() {
+                crate::time::sleep(Duration::from_millis(1)).await;
+            }
+        });
+        crate::time::sleep(Duration::from_millis(5)).await;
+
+        let info = find(handle.id()).expect("task is registered");
+        assert_eq!(info.name(), Some("tracked"));
+        assert_eq!(info.state(), TaskState::Idle);
+        assert_eq!(info.location().file(), file!());
+        assert!(info.to_string().contains("\"tracked\" idle"));
+
+        tx.send(()).unwrap();
+        while !handle.is_finished() {
+            crate::time::sleep(Duration::from_millis(1)).await;
+        }
+        let id = handle.id();
+        assert_eq!(find(id).unwrap().state(), TaskState::Completed);
+
+        // Once all handles are gone, the task is removed from the registry
+        handle.await.unwrap();
+        assert!(find(id).is_none());
+    }
+
+    #[cfg(not(wasm_browser))]
+    #[test]
+    async fn dump_while_dropping_handles() {
+        let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let dumpers: Vec<_> = (0..4)
+            .map(|_| {
+                let done = done.clone();
+                std::thread::spawn(move || {
+                    while !done.load(Ordering::Relaxed) {
+                        dump();
+                    }
+                })
+            })
+            .collect();
+        let id = task::spawn(async {}).id();
+        let droppers: Vec<_> = (0..4)
+            .map(|_| {
+                std::thread::spawn(move || {
+                    for _ in 0..100_000 {
+                        let entry = Entry::register(Some("dropped".into()), Location::caller());
+                        entry.set_id(id);
+                        // Drops the last handle while a dumper might hold the entry
+                        drop(entry);
+                    }
+                })
+            })
+            .collect();
+        for dropper in droppers {
+            dropper.join().unwrap();
+        }
+        done.store(true, Ordering::Relaxed);
+        for dumper in dumpers {
+            dumper.join().unwrap();
+        }
+    }
+
+    #[test]
+    async fn registry_tracks_join_set() {
+        let mut set = task::JoinSet::new();
+        let handle = set.spawn(std::future::pending::<()>());
+        assert!(find(handle.id()).is_some());
+
+        set.shutdown().await;
+        let id = handle.id();
+        assert_eq!(find(id).unwrap().state(), TaskState::Completed);
+        drop(handle);
+        assert!(find(id).is_none());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    async fn serialize_dump() {
+        let handle = task::Builder::new()
+            .name("serialized")
+            .spawn(std::future::pending::<()>());
+        crate::future::yield_now().await;
+
+        let dump = serde_json::to_value(dump()).unwrap();
+        let info = dump
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|info| info["id"] == handle.id().to_string())
+            .expect("task is serialized");
+        assert_eq!(info["name"], "serialized");
+        assert_eq!(info["state"], "idle");
+        assert!(info["location"].as_str().unwrap().starts_with(file!()));
+        assert!(info["spawned_at"].is_object());
+        handle.abort();
+    }
+
+    #[cfg(all(feature = "shim", not(wasm_browser)))]
+    #[test]
+    async fn registry_tracks_shim_tasks() {
+        use crate::shim;
+
+        shim::block_on(async {
+            let handle = shim::task::spawn(std::future::pending::<()>());
+            let info = find(handle.id().into()).expect("task is registered");
+            assert_eq!(info.state(), TaskState::Idle);
+            handle.abort();
+        });
+    }
+}
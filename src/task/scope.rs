@@ -0,0 +1,536 @@
+//! Implements structured-concurrency task scopes, see [`scope`].
+
+#[cfg(wasm_browser)]
+use std::future::IntoFuture;
+use std::{
+    fmt,
+    future::Future,
+    pin::pin,
+    sync::{Arc, Mutex, MutexGuard, PoisonError},
+    task::{Poll, Waker},
+};
+
+use super::{AbortHandle, JoinError, JoinHandle, JoinSet, PanicPolicy};
+
+/// Runs `f` with a [`Scope`] that tasks can be spawned on, and waits for all of them.
+///
+/// Every task spawned through the scope is guaranteed to be either joined or aborted
+/// before the future returned from this function completes. When the future is dropped
+/// before it completes, all tasks that are still running are aborted.
+///
+/// The future returned by `f` and all tasks spawned on the scope return a
+/// `Result<_, E>`. The first error is returned from the scope once all tasks
+/// finished. Use [`ScopeBuilder::cancel_on_error`] to instead cancel all other
+/// tasks as soon as the first error happens.
+///
+/// This is a shorthand for `ScopeBuilder::new().run(f)`.
+///
+/// # Example
+///
+/// ```ignore-wasm32-unknown-unknown
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// use std::sync::{
+///     atomic::{AtomicUsize, Ordering},
+///     Arc,
+/// };
+///
+/// use n0_future::task;
+///
+/// let counter = Arc::new(AtomicUsize::new(0));
+/// let res = task::scope(|s| {
+///     let counter = counter.clone();
+///     async move {
+///         for _ in 0..10 {
+///             let counter = counter.clone();
+///             s.spawn(async move {
+///                 counter.fetch_add(1, Ordering::SeqCst);
+///                 Ok(())
+///             });
+///         }
+///         Ok::<_, std::io::Error>("done")
+///     }
+/// })
+/// .await;
+///
+/// // All tasks have finished by the time the scope finishes
+/// assert_eq!(res.unwrap(), "done");
+/// assert_eq!(counter.load(Ordering::SeqCst), 10);
+/// # }
+/// ```
+pub async fn scope<F, Fut, T, E>(f: F) -> Result<T, ScopeError<E>>
+where
+    F: FnOnce(Scope<E>) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: 'static,
+{
+    ScopeBuilder::new().run(f).await
+}
+
+/// Configures and runs a task [`scope`].
+#[derive(Debug, Default, Clone)]
+pub struct ScopeBuilder {
+    cancel_on_error: bool,
+}
+
+impl ScopeBuilder {
+    /// Creates a new scope builder with the default configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether the first error cancels all other tasks in the scope.
+    ///
+    /// When enabled, the first error returned from either a spawned task or the scope's
+    /// future itself aborts all other tasks, and the scope returns this error once
+    /// they have been shut down.
+    ///
+    /// Defaults to `false`, in which case the scope waits for all tasks to finish,
+    /// before returning the first error.
+    pub fn cancel_on_error(mut self, cancel_on_error: bool) -> Self {
+        self.cancel_on_error = cancel_on_error;
+        self
+    }
+
+    /// Runs `f` with a [`Scope`] that tasks can be spawned on, and waits for all of them.
+    ///
+    /// See [`scope`] for details.
+    pub async fn run<F, Fut, T, E>(self, f: F) -> Result<T, ScopeError<E>>
+    where
+        F: FnOnce(Scope<E>) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: 'static,
+    {
+        let mut tasks = JoinSet::new();
+        // Panics are returned as `ScopeError::Panic`, whatever the global policy is
+        tasks.set_panic_policy(PanicPolicy::ReturnError);
+        let scope = Scope {
+            inner: Arc::new(Mutex::new(Inner {
+                spawned: Vec::new(),
+                waker: None,
+                closed: false,
+            })),
+        };
+        let guard = CloseOnDrop(scope.clone());
+        let mut body = pin!(f(scope.clone()));
+        let mut body_done = false;
+        let mut output = None;
+        let mut error = None;
+
+        let res = std::future::poll_fn(|cx| {
+            // The body keeps running after a task failed, unless that cancels the scope
+            if !body_done {
+                if let Poll::Ready(res) = body.as_mut().poll(cx) {
+                    body_done = true;
+                    match res {
+                        Ok(out) => output = Some(out),
+                        Err(err) => {
+                            error.get_or_insert(ScopeError::Error(err));
+                            if self.cancel_on_error {
+                                return Poll::Ready(());
+                            }
+                        }
+                    }
+                }
+            }
+
+            loop {
+                // The tasks are polled without holding the lock, so that spawning onto the
+                // scope never waits for them
+                scope.take_spawned(&mut tasks, cx.waker());
+                let err = match tasks.poll_join_next(cx) {
+                    Poll::Pending => return Poll::Pending,
+                    Poll::Ready(None) => {
+                        if !body_done {
+                            // The scope's future is still running and may spawn more tasks
+                            return Poll::Pending;
+                        }
+                        let mut inner = scope.lock();
+                        if !inner.spawned.is_empty() {
+                            // Tasks were spawned since they were taken above
+                            continue;
+                        }
+                        inner.closed = true;
+                        return Poll::Ready(());
+                    }
+                    Poll::Ready(Some(Ok(Ok(())))) => continue,
+                    // Tasks that were aborted through their `AbortHandle` aren't errors
+                    Poll::Ready(Some(Err(err))) if err.is_cancelled() => continue,
+                    Poll::Ready(Some(Ok(Err(err)))) => ScopeError::Error(err),
+                    Poll::Ready(Some(Err(err))) => ScopeError::Panic(err),
+                };
+                error.get_or_insert(err);
+                if self.cancel_on_error {
+                    return Poll::Ready(());
+                }
+            }
+        });
+        res.await;
+
+        // Either everything finished, or we're cancelling the remaining tasks after an error.
+        drop(guard);
+        let spawned = std::mem::take(&mut scope.lock().spawned);
+        for handle in spawned {
+            tasks.insert(handle);
+        }
+        tasks.shutdown().await;
+
+        match (error, output) {
+            (Some(err), _) => Err(err),
+            (None, Some(out)) => Ok(out),
+            (None, None) => unreachable!("scope finished without an output or error"),
+        }
+    }
+}
+
+/// A handle for spawning tasks inside a [`scope`].
+///
+/// The handle can be cloned and moved into the tasks of the scope, so that they can
+/// spawn tasks onto the scope as well.
+///
+/// Once the scope has finished, tasks spawned using this handle are aborted immediately.
+pub struct Scope<E> {
+    inner: Arc<Mutex<Inner<E>>>,
+}
+
+struct Inner<E> {
+    /// The tasks spawned since the scope last took them into its `JoinSet`.
+    spawned: Vec<JoinHandle<Result<(), E>>>,
+    /// Wakes the scope to take newly spawned tasks.
+    waker: Option<Waker>,
+    closed: bool,
+}
+
+impl<E> Clone for Scope<E> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<E> fmt::Debug for Scope<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Scope")
+            .field("closed", &self.lock().closed)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<E: 'static> Scope<E> {
+    /// Spawns a task onto this scope.
+    ///
+    /// The scope won't finish before this task finished, or was aborted.
+    #[cfg(not(wasm_browser))]
+    #[track_caller]
+    pub fn spawn<F>(&self, task: F) -> AbortHandle
+    where
+        F: Future<Output = Result<(), E>> + Send + 'static,
+        E: Send,
+    {
+        self.insert(super::spawn(task))
+    }
+
+    /// Spawns a task onto this scope.
+    ///
+    /// The scope won't finish before this task finished, or was aborted.
+    #[cfg(wasm_browser)]
+    #[track_caller]
+    pub fn spawn(&self, task: impl IntoFuture<Output = Result<(), E>> + 'static) -> AbortHandle {
+        self.insert(super::spawn(task))
+    }
+
+    /// Returns whether the scope has finished.
+    ///
+    /// Tasks spawned onto a finished scope are aborted immediately.
+    pub fn is_closed(&self) -> bool {
+        self.lock().closed
+    }
+
+    /// Hands the spawned task of `handle` to the scope, or aborts it if the scope finished.
+    fn insert(&self, handle: JoinHandle<Result<(), E>>) -> AbortHandle {
+        let abort_handle = handle.abort_handle();
+        let mut inner = self.lock();
+        if inner.closed {
+            drop(inner);
+            abort_handle.abort();
+        } else {
+            inner.spawned.push(handle);
+            let waker = inner.waker.take();
+            drop(inner);
+            if let Some(waker) = waker {
+                waker.wake();
+            }
+        }
+        abort_handle
+    }
+
+    /// Moves the tasks spawned since the last call into `tasks`, and registers `waker` to
+    /// be woken once more tasks are spawned.
+    fn take_spawned(&self, tasks: &mut JoinSet<Result<(), E>>, waker: &Waker) {
+        let mut inner = self.lock();
+        let spawned = std::mem::take(&mut inner.spawned);
+        match &mut inner.waker {
+            Some(registered) => registered.clone_from(waker),
+            None => inner.waker = Some(waker.clone()),
+        }
+        drop(inner);
+        for handle in spawned {
+            tasks.insert(handle);
+        }
+    }
+}
+
+impl<E> Scope<E> {
+    fn lock(&self) -> MutexGuard<'_, Inner<E>> {
+        // Tasks are neither polled, aborted nor woken while the lock is held, so a panic
+        // can't leave `Inner` in an inconsistent state. Recovering from poisoning keeps
+        // `CloseOnDrop` from panicking in `drop`.
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Closes the scope and aborts all of its tasks when the scope's future is dropped.
+///
+/// Tasks the scope already took are aborted by dropping its `JoinSet`.
+struct CloseOnDrop<E: 'static>(Scope<E>);
+
+impl<E: 'static> Drop for CloseOnDrop<E> {
+    fn drop(&mut self) {
+        let mut inner = self.0.lock();
+        inner.closed = true;
+        let spawned: Vec<_> = inner.spawned.iter().map(JoinHandle::abort_handle).collect();
+        drop(inner);
+        spawned.iter().for_each(AbortHandle::abort);
+    }
+}
+
+/// The error returned from a [`scope`].
+#[derive(Debug, derive_more::Display)]
+pub enum ScopeError<E> {
+    /// A task of the scope, or the scope's future itself, returned an error.
+    #[display("{_0}")]
+    Error(E),
+    /// A task of the scope panicked.
+    #[display("{_0}")]
+    Panic(JoinError),
+}
+
+impl<E: std::error::Error + 'static> std::error::Error for ScopeError<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Error(err) => Some(err),
+            Self::Panic(err) => Some(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    #[cfg(not(wasm_browser))]
+    use tokio::test;
+    #[cfg(wasm_browser)]
+    use wasm_bindgen_test::wasm_bindgen_test as test;
+
+    use super::*;
+    use crate::time;
+
+    /// Sets the flag once dropped, i.e. once the task owning it finished or was aborted.
+    struct SetOnDrop(Arc<AtomicBool>);
+
+    impl Drop for SetOnDrop {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::SeqCst);
+        }
+    }
+
+    async fn wait_for(flag: &AtomicBool) {
+        time::timeout(Duration::from_secs(1), async {
+            while !flag.load(Ordering::SeqCst) {
+                time::sleep(Duration::from_millis(1)).await;
+            }
+        })
+        .await
+        .expect("flag wasn't set in time");
+    }
+
+    #[test]
+    async fn scope_joins_all_tasks() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let res = scope(|s| {
+            let counter = counter.clone();
+            async move {
+                for i in 0..5 {
+                    let counter = counter.clone();
+                    let s2 = s.clone();
+                    s.spawn(async move {
+                        time::sleep(Duration::from_millis(i)).await;
+                        counter.fetch_add(1, Ordering::SeqCst);
+                        // Nested spawns are joined as well
+                        let counter = counter.clone();
+                        s2.spawn(async move {
+                            time::sleep(Duration::from_millis(1)).await;
+                            counter.fetch_add(1, Ordering::SeqCst);
+                            Ok(())
+                        });
+                        Ok(())
+                    });
+                }
+                Ok::<_, ()>(42)
+            }
+        })
+        .await;
+
+        assert_eq!(res.unwrap(), 42);
+        assert_eq!(counter.load(Ordering::SeqCst), 10);
+    }
+
+    #[cfg(not(wasm_browser))]
+    #[test]
+    async fn scope_joins_tasks_spawned_from_other_threads() {
+        let counter = Arc::new(AtomicUsize::new(0));
+        let res = scope(|s| {
+            let counter = counter.clone();
+            async move {
+                let runtime = tokio::runtime::Handle::current();
+                let threads: Vec<_> = (0..4)
+                    .map(|_| {
+                        let (s, counter, runtime) = (s.clone(), counter.clone(), runtime.clone());
+                        std::thread::spawn(move || {
+                            let _guard = runtime.enter();
+                            for _ in 0..100 {
+                                let counter = counter.clone();
+                                s.spawn(async move {
+                                    counter.fetch_add(1, Ordering::SeqCst);
+                                    Ok(())
+                                });
+                            }
+                        })
+                    })
+                    .collect();
+                for thread in threads {
+                    thread.join().unwrap();
+                }
+                Ok::<_, ()>(())
+            }
+        })
+        .await;
+
+        assert!(res.is_ok());
+        assert_eq!(counter.load(Ordering::SeqCst), 400);
+    }
+
+    #[test]
+    async fn scope_aborts_tasks_on_drop() {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let res = time::timeout(
+            Duration::from_millis(10),
+            scope(|s| {
+                let guard = SetOnDrop(dropped.clone());
+                async move {
+                    s.spawn(async move {
+                        let _guard = guard;
+                        std::future::pending::<()>().await;
+                        Ok(())
+                    });
+                    Ok::<_, ()>(())
+                }
+            }),
+        )
+        .await;
+
+        assert!(res.is_err());
+        wait_for(&dropped).await;
+    }
+
+    #[test]
+    async fn scope_returns_first_error_after_all_tasks() {
+        let finished = Arc::new(AtomicBool::new(false));
+        let res = scope(|s| {
+            let finished = finished.clone();
+            async move {
+                s.spawn(async { Err("failed") });
+                s.spawn(async move {
+                    time::sleep(Duration::from_millis(10)).await;
+                    finished.store(true, Ordering::SeqCst);
+                    Ok(())
+                });
+                Ok(())
+            }
+        })
+        .await;
+
+        assert!(matches!(res, Err(ScopeError::Error("failed"))));
+        assert!(finished.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    async fn scope_body_runs_to_completion_after_task_error() {
+        let finished = Arc::new(AtomicBool::new(false));
+        let res = scope(|s| {
+            let finished = finished.clone();
+            async move {
+                s.spawn(async { Err("failed") });
+                // The task fails while the body is still waiting
+                time::sleep(Duration::from_millis(10)).await;
+                finished.store(true, Ordering::SeqCst);
+                Err::<(), _>("body failed")
+            }
+        })
+        .await;
+
+        // Only the first error is returned
+        assert!(matches!(res, Err(ScopeError::Error("failed"))));
+        assert!(finished.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    async fn scope_cancel_on_error() {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let res = time::timeout(
+            Duration::from_secs(1),
+            ScopeBuilder::new().cancel_on_error(true).run(|s| {
+                let guard = SetOnDrop(dropped.clone());
+                async move {
+                    s.spawn(async move {
+                        let _guard = guard;
+                        std::future::pending::<()>().await;
+                        Ok(())
+                    });
+                    s.spawn(async {
+                        time::sleep(Duration::from_millis(1)).await;
+                        Err("failed")
+                    });
+                    std::future::pending::<Result<(), _>>().await
+                }
+            }),
+        )
+        .await
+        .expect("scope wasn't cancelled");
+
+        assert!(matches!(res, Err(ScopeError::Error("failed"))));
+        assert!(dropped.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    async fn scope_closed_after_finish() {
+        let escaped = scope(|s| async move { Ok::<_, ()>(s.clone()) })
+            .await
+            .unwrap();
+        assert!(escaped.is_closed());
+
+        let dropped = Arc::new(AtomicBool::new(false));
+        let guard = SetOnDrop(dropped.clone());
+        escaped.spawn(async move {
+            let _guard = guard;
+            std::future::pending::<()>().await;
+            Ok(())
+        });
+        wait_for(&dropped).await;
+    }
+}
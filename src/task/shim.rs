@@ -0,0 +1,972 @@
+use std::{
+    any::Any,
+    cell::RefCell,
+    fmt::{self, Debug},
+    future::{Future, IntoFuture},
+    panic::Location,
+    pin::Pin,
+    rc::Rc,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+use futures_lite::{stream::StreamExt, FutureExt};
+use send_wrapper::SendWrapper;
+
+use super::{panic_message, PanicPolicy, TaskMeta};
+
+static TASK_ID_COUNTER: Mutex<u64> = Mutex::new(0);
+
+fn next_task_id() -> u64 {
+    let mut counter = TASK_ID_COUNTER.lock().expect("poisoned");
+    *counter += 1;
+    *counter
+}
+
+/// An opaque ID that uniquely identifies a task relative to all other currently running tasks.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, derive_more::Display)]
+pub struct Id(pub(crate) u64);
+
+/// Wasm shim for tokio's `JoinSet`.
+///
+/// Uses a [`futures_buffered::FuturesUnordered`] queue of
+/// [`JoinHandle`]s inside.
+pub struct JoinSet<T> {
+    handles: futures_buffered::FuturesUnordered<JoinHandleWithId<T>>,
+    // We need to keep a second list of JoinHandles so we can access them for cancellation
+    to_cancel: Vec<JoinHandle<T>>,
+    // The waker of the most recent pending `poll_join_next_with_id` call.
+    // Newly spawned tasks aren't polled by `handles` until it is polled again,
+    // so `spawn` needs to wake this to have the new task be observed.
+    waker: Option<Waker>,
+    panic_policy: Option<PanicPolicy>,
+    #[cfg(feature = "metrics")]
+    metrics: Arc<super::metrics::Counters>,
+}
+
+impl<T> Debug for JoinSet<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JoinSet").field("len", &self.len()).finish()
+    }
+}
+
+impl<T> Default for JoinSet<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> JoinSet<T> {
+    /// Creates a new, empty `JoinSet`
+    pub fn new() -> Self {
+        Self {
+            handles: futures_buffered::FuturesUnordered::new(),
+            to_cancel: Vec::new(),
+            waker: None,
+            panic_policy: None,
+            #[cfg(feature = "metrics")]
+            metrics: Default::default(),
+        }
+    }
+
+    /// Spawns a task into this `JoinSet`.
+    #[track_caller]
+    pub fn spawn(&mut self, fut: impl IntoFuture<Output = T> + 'static) -> AbortHandle
+    where
+        T: 'static,
+    {
+        self.spawn_at(Location::caller(), fut)
+    }
+
+    /// Spawns a task into this `JoinSet`, reporting `location` as where it was spawned.
+    pub(super) fn spawn_at(
+        &mut self,
+        location: &'static Location<'static>,
+        fut: impl IntoFuture<Output = T> + 'static,
+    ) -> AbortHandle
+    where
+        T: 'static,
+    {
+        let meta = TaskMeta::at(None, location);
+        #[cfg(feature = "metrics")]
+        let meta = meta.in_set(&self.metrics);
+        self.insert(spawn_with_meta(meta, fut))
+    }
+
+    /// Adds the spawned task of `handle` to this `JoinSet`.
+    pub(super) fn insert(&mut self, handle: JoinHandle<T>) -> AbortHandle {
+        let abort_handle = handle.abort_handle();
+        let handle_for_cancel = JoinHandle {
+            task: handle.task.clone(),
+        };
+
+        self.handles.push(JoinHandleWithId(handle));
+        self.to_cancel.push(handle_for_cancel);
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+        abort_handle
+    }
+
+    /// Alias to [`Self::spawn`].
+    ///
+    /// Mirrors [`tokio::JoinSet::spawn_local](https://docs.rs/tokio/latest/tokio/task/struct.JoinSet.html#method.spawn_local).
+    /// Because all tasks in WebAssembly are local, this is a simple alias to [`Self::spawn`].
+    #[track_caller]
+    pub fn spawn_local(&mut self, fut: impl IntoFuture<Output = T> + 'static) -> AbortHandle
+    where
+        T: 'static,
+    {
+        self.spawn(fut)
+    }
+
+    /// Aborts all tasks inside this `JoinSet`
+    pub fn abort_all(&self) {
+        self.to_cancel.iter().for_each(JoinHandle::abort);
+    }
+
+    /// Awaits the next `JoinSet`'s completion.
+    ///
+    /// Returns `None` if the set is empty.
+    pub async fn join_next(&mut self) -> Option<Result<T, JoinError>> {
+        self.join_next_with_id()
+            .await
+            .map(|ret| ret.map(|(_id, out)| out))
+    }
+
+    /// Waits until one of the tasks in the set completes and returns its
+    /// output, along with the [task ID] of the completed task.
+    ///
+    /// Returns `None` if the set is empty.
+    ///
+    /// When this method returns an error, then the id of the task that failed can be accessed
+    /// using the [`JoinError::id`] method.
+    ///
+    /// [task ID]: crate::task::Id
+    /// [`JoinError::id`]: fn@crate::task::JoinError::id
+    pub async fn join_next_with_id(&mut self) -> Option<Result<(Id, T), JoinError>> {
+        futures_lite::future::poll_fn(|cx| self.poll_join_next_with_id(cx)).await
+    }
+
+    /// Polls for one of the tasks in the set to complete.
+    ///
+    /// If this returns `Poll::Ready(Some(_))`, then the task that completed is removed from the set.
+    ///
+    /// When the method returns `Poll::Pending`, the `Waker` in the provided `Context` is scheduled
+    /// to receive a wakeup when a task in the `JoinSet` completes. Note that on multiple calls to
+    /// `poll_join_next`, only the `Waker` from the `Context` passed to the most recent call is
+    /// scheduled to receive a wakeup.
+    ///
+    /// # Returns
+    ///
+    /// This function returns:
+    ///
+    ///  * `Poll::Pending` if the `JoinSet` is not empty but there is no task whose output is
+    ///    available right now.
+    ///  * `Poll::Ready(Some(Ok(value)))` if one of the tasks in this `JoinSet` has completed.
+    ///    The `value` is the return value of one of the tasks that completed.
+    ///  * `Poll::Ready(Some(Err(err)))` if one of the tasks in this `JoinSet` has panicked or been
+    ///    aborted. The `err` is the `JoinError` from the panicked/aborted task.
+    ///  * `Poll::Ready(None)` if the `JoinSet` is empty.
+    pub fn poll_join_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<T, JoinError>>> {
+        match self.poll_join_next_with_id(cx) {
+            Poll::Ready(Some(Ok((_, ret)))) => Poll::Ready(Some(Ok(ret))),
+            Poll::Ready(Some(Err(e))) => Poll::Ready(Some(Err(e))),
+            Poll::Ready(None) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    /// Polls for one of the tasks in the set to complete.
+    ///
+    /// If this returns `Poll::Ready(Some(_))`, then the task that completed is removed from the set.
+    ///
+    /// When the method returns `Poll::Pending`, the `Waker` in the provided `Context` is scheduled
+    /// to receive a wakeup when a task in the `JoinSet` completes. Note that on multiple calls to
+    /// `poll_join_next`, only the `Waker` from the `Context` passed to the most recent call is
+    /// scheduled to receive a wakeup.
+    ///
+    /// # Returns
+    ///
+    /// This function returns:
+    ///
+    ///  * `Poll::Pending` if the `JoinSet` is not empty but there is no task whose output is
+    ///    available right now.
+    ///  * `Poll::Ready(Some(Ok((id, value))))` if one of the tasks in this `JoinSet` has completed.
+    ///    The `value` is the return value of one of the tasks that completed, and
+    ///    `id` is the [task ID] of that task.
+    ///  * `Poll::Ready(Some(Err(err)))` if one of the tasks in this `JoinSet` has panicked or been
+    ///    aborted. The `err` is the `JoinError` from the panicked/aborted task.
+    ///  * `Poll::Ready(None)` if the `JoinSet` is empty.
+    ///
+    /// [task ID]: crate::task::Id
+    pub fn poll_join_next_with_id(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<(Id, T), JoinError>>> {
+        let ret = self.poll_join_next_unchecked(cx);
+        let policy = self.panic_policy;
+        ret.map(|ret| {
+            ret.map(|ret| {
+                PanicPolicy::apply(policy, ret, || {
+                    self.to_cancel.iter().for_each(JoinHandle::abort);
+                })
+            })
+        })
+    }
+
+    /// Polls for one of the tasks in the set to complete, without applying the panic
+    /// policy.
+    pub(super) fn poll_join_next_unchecked(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<(Id, T), JoinError>>> {
+        let ret = self.handles.poll_next(cx);
+        // clean up handles that are either cancelled or have finished
+        self.to_cancel.retain(JoinHandle::is_running);
+        match ret {
+            Poll::Pending => match self.waker {
+                // clone_from can be marginally faster in some cases
+                Some(ref mut waker) => waker.clone_from(cx.waker()),
+                None => self.waker = Some(cx.waker().clone()),
+            },
+            Poll::Ready(_) => self.waker = None,
+        }
+        ret
+    }
+
+    /// Returns whether there's any tasks that are either still running or
+    /// have pending results in this `JoinSet`.
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+
+    /// Returns the amount of tasks that are either still running or have
+    /// pending results in this `JoinSet`.
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    /// Sets what happens when a task in this set panicked, overriding the global
+    /// [`PanicPolicy`] set with [`set_panic_policy`].
+    ///
+    /// [`set_panic_policy`]: super::set_panic_policy
+    pub fn set_panic_policy(&mut self, policy: PanicPolicy) {
+        self.panic_policy = Some(policy);
+    }
+
+    /// Returns the poll metrics aggregated over all tasks that were spawned on this
+    /// `JoinSet`, including the ones that already completed.
+    ///
+    /// Only available with the `metrics` feature.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> super::TaskMetrics {
+        self.metrics.snapshot()
+    }
+
+    /// Awaits the completion of all tasks in this `JoinSet`, returning a vector of their results.
+    ///
+    /// The results will be stored in the order they completed not the order they were spawned.
+    ///
+    /// # Panics
+    ///
+    /// If any tasks on the `JoinSet` fail with a [`JoinError`], then this call to `join_all`
+    /// will panic and all remaining tasks on the `JoinSet` are cancelled. When a task
+    /// panicked, its panic is resumed with the original payload.
+    pub async fn join_all(mut self) -> Vec<T> {
+        let mut output = Vec::with_capacity(self.len());
+        while let Some(res) = self.join_next().await {
+            match res {
+                Ok(t) => output.push(t),
+                Err(err) if err.is_panic() => std::panic::resume_unwind(err.into_panic()),
+                Err(err) => panic!("{err}"),
+            }
+        }
+        output
+    }
+
+    /// Aborts all tasks and then waits for them to finish, ignoring panics.
+    pub async fn shutdown(&mut self) {
+        self.abort_all();
+        while let Some(_res) = std::future::poll_fn(|cx| self.poll_join_next_unchecked(cx)).await {}
+    }
+}
+
+impl<T> Drop for JoinSet<T> {
+    fn drop(&mut self) {
+        self.abort_all()
+    }
+}
+
+/// A handle to a spawned task.
+pub struct JoinHandle<T> {
+    task: Task<T>,
+}
+
+struct Task<T> {
+    // Using SendWrapper here is safe as long as you keep all of your
+    // work on the main UI worker in the browser.
+    // The only exception to that being the case would be if our user
+    // would use multiple Wasm instances with a single SharedArrayBuffer,
+    // put the instances on different Web Workers and finally shared
+    // the JoinHandle across the Web Worker boundary.
+    // In that case, using the JoinHandle would panic.
+    state: SendWrapper<Rc<RefCell<State>>>,
+    result: SendWrapper<Rc<RefCell<Option<T>>>>,
+    meta: TaskMeta,
+}
+
+impl<T> Clone for Task<T> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+            result: self.result.clone(),
+            meta: self.meta.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    id: Id,
+    cancelled: bool,
+    completed: bool,
+    /// Whether the task's future was dropped, after it completed or was cancelled.
+    finished: bool,
+    panic: Option<Box<dyn Any + Send + 'static>>,
+    waker_handler: Option<Waker>,
+    waker_spawn_fn: Option<Waker>,
+}
+
+impl State {
+    fn cancel(&mut self) {
+        // Like in tokio, aborting a task that already completed has no effect.
+        if !self.is_complete() {
+            self.cancelled = true;
+            self.wake();
+        }
+    }
+
+    fn complete(&mut self) {
+        self.completed = true;
+        self.wake();
+    }
+
+    fn panicked(&mut self, payload: Box<dyn Any + Send + 'static>) {
+        self.panic = Some(payload);
+        self.complete();
+    }
+
+    fn is_complete(&self) -> bool {
+        self.completed || self.cancelled
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker_handler.take() {
+            waker.wake();
+        }
+        if let Some(waker) = self.waker_spawn_fn.take() {
+            waker.wake();
+        }
+    }
+
+    fn register_handler(&mut self, cx: &mut Context<'_>) {
+        match self.waker_handler {
+            // clone_from can be marginally faster in some cases
+            Some(ref mut waker) => waker.clone_from(cx.waker()),
+            None => self.waker_handler = Some(cx.waker().clone()),
+        }
+    }
+
+    fn register_spawn_fn(&mut self, cx: &mut Context<'_>) {
+        match self.waker_spawn_fn {
+            // clone_from can be marginally faster in some cases
+            Some(ref mut waker) => waker.clone_from(cx.waker()),
+            None => self.waker_spawn_fn = Some(cx.waker().clone()),
+        }
+    }
+}
+
+impl<T> Debug for JoinHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.task.state.valid() {
+            let state = self.task.state.borrow();
+            f.debug_struct("JoinHandle")
+                .field("id", &state.id)
+                .field("name", &self.task.meta.name())
+                .field("location", &self.task.meta.location)
+                .field("cancelled", &state.cancelled)
+                .field("completed", &state.completed)
+                .finish()
+        } else {
+            f.debug_tuple("JoinHandle")
+                .field(&format_args!("<other thread>"))
+                .finish()
+        }
+    }
+}
+
+impl<T> JoinHandle<T> {
+    fn new(meta: TaskMeta) -> Self {
+        let id = Id(next_task_id());
+        meta.set_id(id);
+        Self {
+            task: Task {
+                state: SendWrapper::new(Rc::new(RefCell::new(State {
+                    cancelled: false,
+                    completed: false,
+                    finished: false,
+                    panic: None,
+                    waker_handler: None,
+                    waker_spawn_fn: None,
+                    id,
+                }))),
+                result: SendWrapper::new(Rc::new(RefCell::new(None))),
+                meta,
+            },
+        }
+    }
+
+    /// Aborts this task.
+    pub fn abort(&self) {
+        self.task.state.borrow_mut().cancel();
+    }
+
+    /// Returns a new [`AbortHandle`] that can be used to remotely abort this task.
+    ///
+    /// Awaiting a task cancelled by the [`AbortHandle`] might complete as usual if the task was
+    /// already completed at the time it was cancelled, but most likely it
+    /// will fail with a [cancelled] `JoinError`.
+    ///
+    /// [cancelled]: JoinError::is_cancelled
+    pub fn abort_handle(&self) -> AbortHandle {
+        AbortHandle {
+            state: self.task.state.clone(),
+            meta: self.task.meta.clone(),
+        }
+    }
+
+    /// Returns a [task ID] that uniquely identifies this task relative to other
+    /// currently spawned tasks.
+    ///
+    /// [task ID]: crate::task::Id
+    pub fn id(&self) -> Id {
+        let state = self.task.state.borrow();
+        state.id
+    }
+
+    /// Returns the name of this task, if it was spawned with one using [`Builder::name`].
+    ///
+    /// [`Builder::name`]: crate::task::Builder::name
+    pub fn name(&self) -> Option<&str> {
+        self.task.meta.name()
+    }
+
+    /// Returns the poll metrics of this task.
+    ///
+    /// Only available with the `metrics` feature.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> super::TaskMetrics {
+        self.task.meta.metrics()
+    }
+
+    /// Checks if the task associated with this `JoinHandle` has finished.
+    ///
+    /// Like in tokio, an aborted task only finishes once its future was dropped.
+    pub fn is_finished(&self) -> bool {
+        self.task.state.borrow().finished
+    }
+
+    fn is_running(&self) -> bool {
+        !self.is_finished()
+    }
+}
+
+/// An error that can occur when waiting for the completion of a task.
+#[derive(derive_more::Display, Debug)]
+#[display("task {id} {cause}")]
+pub struct JoinError {
+    cause: JoinErrorCause,
+    id: Id,
+    name: Option<Arc<str>>,
+}
+
+#[derive(Debug)]
+enum JoinErrorCause {
+    /// The error that's returned when the task that's being waited on
+    /// has been cancelled.
+    Cancelled,
+    /// The error that's returned when the task that's being waited on
+    /// has panicked.
+    Panicked(Box<dyn Any + Send + 'static>),
+}
+
+impl fmt::Display for JoinErrorCause {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Cancelled => write!(f, "was cancelled"),
+            Self::Panicked(payload) => match panic_message(payload.as_ref()) {
+                Some(message) => write!(f, "panicked with message {message:?}"),
+                None => write!(f, "panicked"),
+            },
+        }
+    }
+}
+
+impl std::error::Error for JoinError {}
+
+#[cfg(not(wasm_browser))]
+impl super::panic::PanicError for JoinError {
+    fn is_panic(&self) -> bool {
+        self.is_panic()
+    }
+
+    fn into_panic(self) -> Box<dyn Any + Send + 'static> {
+        self.into_panic()
+    }
+}
+
+impl From<JoinError> for std::io::Error {
+    fn from(err: JoinError) -> Self {
+        let message = match err.cause {
+            JoinErrorCause::Cancelled => "task was cancelled",
+            JoinErrorCause::Panicked(_) => "task panicked",
+        };
+        std::io::Error::other(message)
+    }
+}
+
+impl JoinError {
+    /// Returns whether this join error is due to cancellation.
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self.cause, JoinErrorCause::Cancelled)
+    }
+
+    /// Returns whether this join error is due to the task panicking.
+    ///
+    /// Panics are caught when the Wasm module is built with unwinding support.
+    /// Otherwise they're recorded through a panic hook, but the panic still
+    /// aborts the current call into the Wasm module.
+    pub fn is_panic(&self) -> bool {
+        matches!(self.cause, JoinErrorCause::Panicked(_))
+    }
+
+    /// Consumes the join error, returning the object with which the task panicked.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the error does not represent the task panicking.
+    #[track_caller]
+    pub fn into_panic(self) -> Box<dyn Any + Send + 'static> {
+        self.try_into_panic()
+            .expect("`JoinError` reason is not a panic.")
+    }
+
+    /// Consumes the join error, returning the object with which the task
+    /// panicked if the task terminated due to a panic. Otherwise, `self` is
+    /// returned.
+    ///
+    /// When the panic was only recorded through the panic hook (i.e. the
+    /// Wasm module was built without unwinding support), the payload is the
+    /// panic message as a `String`.
+    pub fn try_into_panic(self) -> Result<Box<dyn Any + Send + 'static>, JoinError> {
+        match self.cause {
+            JoinErrorCause::Panicked(payload) => Ok(payload),
+            cause => Err(JoinError { cause, ..self }),
+        }
+    }
+
+    /// Returns the message the task panicked with, if it panicked with a `&str` or
+    /// `String` payload, as is the case for `panic!` and friends.
+    pub fn panic_message(&self) -> Option<&str> {
+        match &self.cause {
+            JoinErrorCause::Panicked(payload) => panic_message(payload.as_ref()),
+            JoinErrorCause::Cancelled => None,
+        }
+    }
+
+    /// Returns a task ID that identifies the task which errored relative to other currently spawned tasks.
+    pub fn id(&self) -> Id {
+        self.id
+    }
+
+    /// Returns the name of the task which errored, if it was spawned with one
+    /// using [`Builder::name`].
+    ///
+    /// [`Builder::name`]: crate::task::Builder::name
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.task.state.borrow_mut();
+        if !state.finished {
+            // Aborted tasks only report being cancelled once their future was dropped
+            state.register_handler(cx);
+            return Poll::Pending;
+        }
+
+        if let Some(payload) = state.panic.take() {
+            return Poll::Ready(Err(JoinError {
+                cause: JoinErrorCause::Panicked(payload),
+                id: state.id,
+                name: self.task.meta.name.clone(),
+            }));
+        }
+
+        if let Some(result) = self.task.result.borrow_mut().take() {
+            return Poll::Ready(Ok(result));
+        }
+
+        // The task was aborted, or its future was dropped along with the runtime
+        Poll::Ready(Err(JoinError {
+            cause: JoinErrorCause::Cancelled,
+            id: state.id,
+            name: self.task.meta.name.clone(),
+        }))
+    }
+}
+
+struct JoinHandleWithId<T>(JoinHandle<T>);
+
+impl<T> Future for JoinHandleWithId<T> {
+    type Output = Result<(Id, T), JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.0.poll(cx) {
+            Poll::Ready(out) => Poll::Ready(out.map(|out| (self.0.id(), out))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// The future driving a spawned task on the current [`Runtime`](crate::runtime::Runtime).
+///
+/// Panics inside the task are captured so they can be reported through
+/// [`JoinError::is_panic`]:
+/// - When the Wasm module is built with unwinding support (`-Cpanic=unwind`),
+///   the panic is caught and its payload is handed to the `JoinHandle`.
+/// - Otherwise the panic can't be caught. Instead, a panic hook records the
+///   panic message for the task that is currently being polled, before the
+///   panic aborts the current call into the Wasm module.
+#[pin_project::pin_project]
+struct SpawnFuture<Fut: Future<Output = T>, T> {
+    handle: JoinHandle<T>,
+    #[pin]
+    fut: Fut,
+    /// Dropped after `fut`, as it's declared after it.
+    finished: FinishGuard,
+}
+
+/// Marks the task as finished once its future was dropped.
+struct FinishGuard(SendWrapper<Rc<RefCell<State>>>);
+
+impl Drop for FinishGuard {
+    fn drop(&mut self) {
+        let mut state = self.0.borrow_mut();
+        state.finished = true;
+        state.wake();
+    }
+}
+
+impl<Fut: Future<Output = T>, T> Future for SpawnFuture<Fut, T> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let state = &this.handle.task.state;
+
+        if state.borrow().cancelled {
+            return Poll::Ready(());
+        }
+
+        // We must not hold on to a borrow of the state while polling the task:
+        // The task might abort itself, or panic while the panic hook needs access.
+        let poll = poll_catch_panic(state, this.fut, cx);
+
+        let mut state = state.borrow_mut();
+        if state.cancelled {
+            return Poll::Ready(());
+        }
+
+        match poll {
+            Poll::Ready(Ok(value)) => {
+                let _ = this.handle.task.result.borrow_mut().insert(value);
+                state.complete();
+                Poll::Ready(())
+            }
+            Poll::Ready(Err(payload)) => {
+                state.panicked(payload);
+                Poll::Ready(())
+            }
+            Poll::Pending => {
+                state.register_spawn_fn(cx);
+                Poll::Pending
+            }
+        }
+    }
+}
+
+type PanicPayload = Box<dyn Any + Send + 'static>;
+
+#[cfg(panic = "unwind")]
+fn poll_catch_panic<Fut: Future>(
+    _state: &Rc<RefCell<State>>,
+    fut: Pin<&mut Fut>,
+    cx: &mut Context<'_>,
+) -> Poll<Result<Fut::Output, PanicPayload>> {
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| fut.poll(cx))) {
+        Ok(Poll::Ready(value)) => Poll::Ready(Ok(value)),
+        Ok(Poll::Pending) => Poll::Pending,
+        Err(payload) => Poll::Ready(Err(payload)),
+    }
+}
+
+#[cfg(not(panic = "unwind"))]
+fn poll_catch_panic<Fut: Future>(
+    state: &Rc<RefCell<State>>,
+    fut: Pin<&mut Fut>,
+    cx: &mut Context<'_>,
+) -> Poll<Result<Fut::Output, PanicPayload>> {
+    panic_hook::install();
+    panic_hook::CURRENT_TASK.with_borrow_mut(|current| *current = Some(state.clone()));
+    let poll = fut.poll(cx);
+    // If the task panicked, we never get here, the panic aborts instead.
+    panic_hook::CURRENT_TASK.with_borrow_mut(|current| *current = None);
+    poll.map(Ok)
+}
+
+/// Records panics of tasks when panics can't be caught by unwinding.
+#[cfg(not(panic = "unwind"))]
+mod panic_hook {
+    use std::{cell::RefCell, rc::Rc, sync::Once};
+
+    use super::{panic_message, State};
+
+    thread_local! {
+        /// The state of the task that's currently being polled.
+        pub(super) static CURRENT_TASK: RefCell<Option<Rc<RefCell<State>>>> = const { RefCell::new(None) };
+    }
+
+    /// Installs a panic hook that marks the currently polled task as panicked.
+    ///
+    /// The previously installed panic hook (e.g. `console_error_panic_hook`)
+    /// is still called afterwards.
+    pub(super) fn install() {
+        static INSTALL: Once = Once::new();
+        INSTALL.call_once(|| {
+            let prev_hook = std::panic::take_hook();
+            std::panic::set_hook(Box::new(move |info| {
+                let current = CURRENT_TASK
+                    .try_with(|current| current.try_borrow_mut().ok()?.take())
+                    .ok()
+                    .flatten();
+                if let Some(state) = current {
+                    if let Ok(mut state) = state.try_borrow_mut() {
+                        let message = panic_message(info.payload())
+                            .unwrap_or("Box<dyn Any>")
+                            .to_string();
+                        state.panicked(Box::new(message));
+                        // The task's future is never dropped, as the panic aborts
+                        state.finished = true;
+                    }
+                }
+                prev_hook(info);
+            }));
+        });
+    }
+}
+
+/// An owned permission to abort a spawned task, without awaiting its completion.
+#[derive(Clone)]
+pub struct AbortHandle {
+    state: SendWrapper<Rc<RefCell<State>>>,
+    meta: TaskMeta,
+}
+
+impl Debug for AbortHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.state.valid() {
+            let state = self.state.borrow();
+            f.debug_struct("AbortHandle")
+                .field("id", &state.id)
+                .field("name", &self.meta.name())
+                .field("location", &self.meta.location)
+                .field("cancelled", &state.cancelled)
+                .field("completed", &state.completed)
+                .finish()
+        } else {
+            f.debug_tuple("AbortHandle")
+                .field(&format_args!("<other thread>"))
+                .finish()
+        }
+    }
+}
+
+impl AbortHandle {
+    /// Abort the task associated with the handle.
+    pub fn abort(&self) {
+        self.state.borrow_mut().cancel();
+    }
+
+    /// Returns a [task ID] that uniquely identifies this task relative to other
+    /// currently spawned tasks.
+    ///
+    /// [task ID]: crate::task::Id
+    pub fn id(&self) -> Id {
+        self.state.borrow().id
+    }
+
+    /// Returns the name of the task associated with this handle, if it was spawned
+    /// with one using [`Builder::name`].
+    ///
+    /// [`Builder::name`]: crate::task::Builder::name
+    pub fn name(&self) -> Option<&str> {
+        self.meta.name()
+    }
+
+    /// Returns the poll metrics of this task.
+    ///
+    /// Only available with the `metrics` feature.
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> super::TaskMetrics {
+        self.meta.metrics()
+    }
+
+    /// Checks if the task associated with this `AbortHandle` has finished.
+    ///
+    /// Like in tokio, an aborted task only finishes once its future was dropped.
+    pub fn is_finished(&self) -> bool {
+        self.state.borrow().finished
+    }
+}
+
+/// Spawns a future as a task in the browser runtime.
+///
+/// This is powered by `wasm_bidngen_futures`.
+#[track_caller]
+pub fn spawn<T: 'static>(fut: impl IntoFuture<Output = T> + 'static) -> JoinHandle<T> {
+    spawn_with_meta(TaskMeta::new(None), fut)
+}
+
+pub(super) fn spawn_with_meta<T: 'static>(
+    meta: TaskMeta,
+    fut: impl IntoFuture<Output = T> + 'static,
+) -> JoinHandle<T> {
+    let handle = JoinHandle::new(meta);
+
+    crate::runtime::spawn_local_inherit(SpawnFuture {
+        handle: JoinHandle {
+            task: handle.task.clone(),
+        },
+        fut: handle.task.meta.track(fut.into_future()),
+        finished: FinishGuard(handle.task.state.clone()),
+    });
+
+    handle
+}
+
+/// Spawns a `!Send` future as a task in the browser runtime.
+///
+/// Because all tasks in WebAssembly are local, this is a simple alias to [`spawn`].
+#[track_caller]
+pub fn spawn_local<T: 'static>(fut: impl IntoFuture<Output = T> + 'static) -> JoinHandle<T> {
+    spawn_with_meta(TaskMeta::new(None), fut)
+}
+
+/// Wasm shim for tokio's `LocalSet`.
+///
+/// All tasks in WebAssembly are local and driven by the browser's event loop,
+/// so tasks spawned on this set make progress even when the set isn't polled.
+/// Awaiting the `LocalSet` waits for all tasks spawned on it to complete, and
+/// dropping it aborts these tasks, just like in tokio.
+#[derive(Debug, Default)]
+pub struct LocalSet {
+    shared: Rc<RefCell<LocalSetShared>>,
+}
+
+#[derive(Debug, Default)]
+struct LocalSetShared {
+    tasks: Vec<AbortHandle>,
+    running: usize,
+    waker: Option<Waker>,
+}
+
+/// Keeps a [`LocalSet`]'s count of running tasks up-to-date.
+///
+/// Moved into its task, so it's dropped once the task completes or gets aborted.
+struct LocalSetGuard(Rc<RefCell<LocalSetShared>>);
+
+impl Drop for LocalSetGuard {
+    fn drop(&mut self) {
+        let mut shared = self.0.borrow_mut();
+        shared.running -= 1;
+        if shared.running == 0 {
+            if let Some(waker) = shared.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl LocalSet {
+    /// Returns a new local task set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spawns a `!Send` task onto the local task set.
+    #[track_caller]
+    pub fn spawn_local<T: 'static>(
+        &self,
+        fut: impl IntoFuture<Output = T> + 'static,
+    ) -> JoinHandle<T> {
+        let fut = fut.into_future();
+        self.shared.borrow_mut().running += 1;
+        let guard = LocalSetGuard(self.shared.clone());
+        let handle = spawn_with_meta(TaskMeta::new(None), async move {
+            let _guard = guard;
+            fut.await
+        });
+
+        let mut shared = self.shared.borrow_mut();
+        shared.tasks.retain(|task| !task.is_finished());
+        shared.tasks.push(handle.abort_handle());
+        handle
+    }
+
+    /// Runs a future to completion on the local set, returning its output.
+    ///
+    /// In WebAssembly, this simply awaits the given future.
+    pub async fn run_until<F: IntoFuture>(&self, future: F) -> F::Output {
+        future.await
+    }
+}
+
+impl Future for LocalSet {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut shared = self.shared.borrow_mut();
+        if shared.running == 0 {
+            return Poll::Ready(());
+        }
+        match shared.waker {
+            // clone_from can be marginally faster in some cases
+            Some(ref mut waker) => waker.clone_from(cx.waker()),
+            None => shared.waker = Some(cx.waker().clone()),
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for LocalSet {
+    fn drop(&mut self) {
+        let tasks = std::mem::take(&mut self.shared.borrow_mut().tasks);
+        tasks.iter().for_each(AbortHandle::abort);
+    }
+}
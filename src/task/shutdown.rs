@@ -0,0 +1,251 @@
+//! Implements graceful shutdown of tasks, see [`JoinSet::shutdown_graceful`].
+
+use std::{future::poll_fn, pin::pin};
+
+use super::{AbortOnDropHandle, Id, JoinError, JoinSet};
+use crate::time::{self, Duration};
+
+/// How a task ended during a graceful shutdown.
+///
+/// See [`JoinSet::shutdown_graceful`] and [`AbortOnDropHandle::cancel_and_wait`].
+#[derive(Debug)]
+pub enum ShutdownOutcome<T> {
+    /// The task exited on its own before the timeout elapsed.
+    ///
+    /// This contains the task's output, or the error if it panicked, or was aborted by
+    /// something other than the shutdown.
+    Exited(Result<T, JoinError>),
+    /// The task was still running when the timeout elapsed and was aborted.
+    Aborted,
+}
+
+impl<T> ShutdownOutcome<T> {
+    /// Returns whether the task exited on its own.
+    pub fn is_exited(&self) -> bool {
+        matches!(self, Self::Exited(_))
+    }
+
+    /// Returns whether the task had to be aborted.
+    pub fn is_aborted(&self) -> bool {
+        matches!(self, Self::Aborted)
+    }
+
+    fn from_join(res: Result<T, JoinError>) -> Self {
+        match res {
+            Err(err) if err.is_cancelled() => Self::Aborted,
+            res => Self::Exited(res),
+        }
+    }
+}
+
+impl<T: 'static> JoinSet<T> {
+    /// Gracefully shuts down all tasks in this `JoinSet`.
+    ///
+    /// First calls `signal`, which should signal the tasks to finish up cooperatively,
+    /// e.g. by cancelling a token they're listening on. Then waits up to `timeout` for
+    /// the tasks to exit, and aborts all tasks that are still running afterwards.
+    ///
+    /// Returns the outcome of every task in the set, in the order they ended. Tasks that
+    /// panicked are returned as [`ShutdownOutcome::Exited`] with their [`JoinError`],
+    /// regardless of the set's [`PanicPolicy`].
+    ///
+    /// # Example
+    ///
+    /// ```ignore-wasm32-unknown-unknown
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// use std::sync::{
+    ///     atomic::{AtomicBool, Ordering},
+    ///     Arc,
+    /// };
+    ///
+    /// use n0_future::{
+    ///     task::JoinSet,
+    ///     time::{self, Duration},
+    /// };
+    ///
+    /// let stop = Arc::new(AtomicBool::new(false));
+    /// let mut set = JoinSet::new();
+    /// let stop2 = stop.clone();
+    /// set.spawn(async move {
+    ///     // Cooperates with the shutdown signal
+    ///     while !stop2.load(Ordering::Relaxed) {
+    ///         time::sleep(Duration::from_millis(1)).await;
+    ///     }
+    ///     "flushed"
+    /// });
+    /// // Ignores the shutdown signal
+    /// set.spawn(std::future::pending::<&str>());
+    ///
+    /// let outcomes = set
+    ///     .shutdown_graceful(|| stop.store(true, Ordering::Relaxed), Duration::from_millis(50))
+    ///     .await;
+    /// assert_eq!(outcomes.iter().filter(|(_, o)| o.is_exited()).count(), 1);
+    /// assert_eq!(outcomes.iter().filter(|(_, o)| o.is_aborted()).count(), 1);
+    /// # }
+    /// ```
+    ///
+    /// [`PanicPolicy`]: super::PanicPolicy
+    pub async fn shutdown_graceful(
+        &mut self,
+        signal: impl FnOnce(),
+        timeout: Duration,
+    ) -> Vec<(Id, ShutdownOutcome<T>)> {
+        let mut outcomes = Vec::with_capacity(self.len());
+        signal();
+
+        time::timeout(timeout, async {
+            while let Some(res) = poll_fn(|cx| self.poll_join_next_unchecked(cx)).await {
+                outcomes.push(exited(res));
+            }
+        })
+        .await
+        .ok();
+
+        self.abort_all();
+        while let Some(res) = poll_fn(|cx| self.poll_join_next_unchecked(cx)).await {
+            outcomes.push(match res {
+                Err(err) if err.is_cancelled() => (err.id(), ShutdownOutcome::Aborted),
+                res => exited(res),
+            });
+        }
+        outcomes
+    }
+}
+
+fn exited<T>(res: Result<(Id, T), JoinError>) -> (Id, ShutdownOutcome<T>) {
+    match res {
+        Ok((id, out)) => (id, ShutdownOutcome::Exited(Ok(out))),
+        Err(err) => (err.id(), ShutdownOutcome::Exited(Err(err))),
+    }
+}
+
+impl<T> AbortOnDropHandle<T> {
+    /// Gracefully shuts down the task.
+    ///
+    /// First calls `signal`, which should signal the task to finish up cooperatively,
+    /// then waits up to `timeout` for it to exit, and aborts it if it's still running
+    /// afterwards.
+    ///
+    /// See [`JoinSet::shutdown_graceful`].
+    pub async fn cancel_and_wait(
+        self,
+        signal: impl FnOnce(),
+        timeout: Duration,
+    ) -> ShutdownOutcome<T> {
+        let mut this = pin!(self);
+        signal();
+        if let Ok(res) = time::timeout(timeout, this.as_mut()).await {
+            return ShutdownOutcome::Exited(res);
+        }
+        this.abort();
+        ShutdownOutcome::from_join(this.await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    #[cfg(not(wasm_browser))]
+    use tokio::test;
+    #[cfg(wasm_browser)]
+    use wasm_bindgen_test::wasm_bindgen_test as test;
+
+    use super::*;
+    use crate::task::{self, PanicPolicy};
+
+    /// Waits for the flag to be set, like a task listening for a shutdown signal.
+    async fn wait_for(flag: Arc<AtomicBool>) {
+        while !flag.load(Ordering::SeqCst) {
+            time::sleep(Duration::from_millis(1)).await;
+        }
+    }
+
+    #[test]
+    async fn join_set_shutdown_graceful() {
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut set = JoinSet::new();
+        let cooperative = set.spawn({
+            let stop = stop.clone();
+            async move {
+                wait_for(stop).await;
+                1
+            }
+        });
+        let stubborn = set.spawn(async {
+            std::future::pending::<()>().await;
+            2
+        });
+
+        let outcomes = set
+            .shutdown_graceful(
+                || stop.store(true, Ordering::SeqCst),
+                Duration::from_millis(50),
+            )
+            .await;
+        assert!(set.is_empty());
+        assert_eq!(outcomes.len(), 2);
+        assert_eq!(outcomes[0].0, cooperative.id());
+        assert!(matches!(outcomes[0].1, ShutdownOutcome::Exited(Ok(1))));
+        assert_eq!(outcomes[1].0, stubborn.id());
+        assert!(outcomes[1].1.is_aborted());
+    }
+
+    #[test]
+    async fn shutdown_graceful_reports_aborts_from_elsewhere() {
+        let mut set = JoinSet::new();
+        let aborted = set.spawn(std::future::pending::<()>());
+        let outcomes = set
+            .shutdown_graceful(|| aborted.abort(), Duration::from_secs(1))
+            .await;
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(outcomes[0].0, aborted.id());
+        match &outcomes[0].1 {
+            ShutdownOutcome::Exited(Err(err)) => assert!(err.is_cancelled()),
+            outcome => panic!("unexpected outcome: {outcome:?}"),
+        }
+    }
+
+    #[test]
+    async fn shutdown_graceful_returns_panics() {
+        let mut set = JoinSet::new();
+        set.set_panic_policy(PanicPolicy::Resume);
+        let panicked = set.spawn(async { panic!("oops") });
+        let outcomes = set.shutdown_graceful(|| {}, Duration::from_secs(1)).await;
+        assert_eq!(outcomes.len(), 1);
+        assert_eq!(outcomes[0].0, panicked.id());
+        match &outcomes[0].1 {
+            ShutdownOutcome::Exited(Err(err)) => assert_eq!(err.panic_message(), Some("oops")),
+            outcome => panic!("unexpected outcome: {outcome:?}"),
+        }
+    }
+
+    #[test]
+    async fn abort_on_drop_cancel_and_wait() {
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = AbortOnDropHandle::new(task::spawn({
+            let stop = stop.clone();
+            async move {
+                wait_for(stop).await;
+                "flushed"
+            }
+        }));
+        let outcome = handle
+            .cancel_and_wait(
+                || stop.store(true, Ordering::SeqCst),
+                Duration::from_millis(50),
+            )
+            .await;
+        assert!(matches!(outcome, ShutdownOutcome::Exited(Ok("flushed"))));
+
+        let handle = AbortOnDropHandle::new(task::spawn(std::future::pending::<()>()));
+        let outcome = handle
+            .cancel_and_wait(|| {}, Duration::from_millis(1))
+            .await;
+        assert!(outcome.is_aborted());
+    }
+}
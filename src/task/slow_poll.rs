@@ -0,0 +1,273 @@
+//! Implements reporting polls that take too long, see [`SlowPollDetector`].
+
+#[cfg(not(wasm_browser))]
+use std::time::Instant;
+use std::{
+    fmt,
+    future::Future,
+    panic::Location,
+    pin::Pin,
+    sync::{Arc, OnceLock},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+#[cfg(wasm_browser)]
+use web_time::Instant;
+
+/// The detector installed with [`SlowPollDetector::install`].
+static GLOBAL: OnceLock<SlowPollDetector> = OnceLock::new();
+
+type Handler = Arc<dyn Fn(&SlowPoll) + Send + Sync>;
+
+/// Reports polls of futures that take longer than a threshold.
+///
+/// A future that blocks in its poll stalls all other futures on the same thread, which
+/// freezes the UI in browsers and stalls a worker thread of the tokio runtime natively.
+///
+/// The detector can be installed globally with [`SlowPollDetector::install`], to
+/// time the polls of all tasks spawned through [`crate::task`], or be used for
+/// individual futures with [`SlowPollExt::detect_slow_polls`].
+///
+/// Slow polls are passed to the handler set with [`SlowPollDetector::on_slow_poll`].
+/// Without a handler, they're logged as warnings using the `tracing` crate when the
+/// `tracing` feature is enabled, and ignored otherwise.
+///
+/// # Example
+///
+/// ```ignore-wasm32-unknown-unknown
+/// use std::time::Duration;
+///
+/// use n0_future::task::{SlowPollDetector, SlowPollExt};
+///
+/// let detector = SlowPollDetector::new(Duration::from_millis(10))
+///     .on_slow_poll(|poll| eprintln!("{poll}"));
+/// let fut = async { std::thread::sleep(Duration::from_millis(20)) };
+/// // Reports that the future blocked for 20ms
+/// n0_future::future::block_on(fut.detect_slow_polls(detector));
+/// ```
+#[derive(Clone, derive_more::Debug)]
+pub struct SlowPollDetector {
+    threshold: Duration,
+    #[debug(skip)]
+    handler: Option<Handler>,
+}
+
+impl SlowPollDetector {
+    /// Creates a detector that reports polls that take longer than `threshold`.
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            threshold,
+            handler: None,
+        }
+    }
+
+    /// Sets the function that's called with every slow poll.
+    pub fn on_slow_poll(mut self, handler: impl Fn(&SlowPoll) + Send + Sync + 'static) -> Self {
+        self.handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Returns the threshold above which polls are reported.
+    pub fn threshold(&self) -> Duration {
+        self.threshold
+    }
+
+    /// Installs the detector for all tasks spawned through [`crate::task`].
+    ///
+    /// This can only be done once. The detector applies to tasks that are already
+    /// running, too.
+    pub fn install(self) -> Result<(), SlowPollDetectorSet> {
+        GLOBAL.set(self).map_err(|_| SlowPollDetectorSet(()))
+    }
+
+    fn report(&self, poll: &SlowPoll) {
+        match &self.handler {
+            Some(handler) => handler(poll),
+            #[cfg(feature = "tracing")]
+            None => tracing::warn!(
+                name = poll.name(),
+                location = %poll.location(),
+                duration = ?poll.duration(),
+                "{poll}"
+            ),
+            #[cfg(not(feature = "tracing"))]
+            None => {}
+        }
+    }
+}
+
+/// Error returned by [`SlowPollDetector::install`] if a detector is installed already.
+#[derive(Debug, PartialEq, Eq, derive_more::Display)]
+#[display("a slow poll detector is installed already")]
+pub struct SlowPollDetectorSet(());
+
+impl std::error::Error for SlowPollDetectorSet {}
+
+/// A poll that took longer than the threshold of a [`SlowPollDetector`].
+#[derive(Debug, Clone)]
+pub struct SlowPoll {
+    name: Option<Arc<str>>,
+    location: &'static Location<'static>,
+    spawned: bool,
+    duration: Duration,
+    threshold: Duration,
+}
+
+impl SlowPoll {
+    /// Returns the name of the task, if it was spawned with one using [`Builder::name`].
+    ///
+    /// [`Builder::name`]: super::Builder::name
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Returns the location in the source code that spawned the task, or that called
+    /// [`SlowPollExt::detect_slow_polls`].
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+
+    /// Returns how long the poll took.
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// Returns the threshold of the detector that reported the poll.
+    pub fn threshold(&self) -> Duration {
+        self.threshold
+    }
+}
+
+impl fmt::Display for SlowPoll {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.name, self.spawned) {
+            (Some(name), _) => write!(f, "poll of task {name:?}")?,
+            (None, true) => write!(f, "poll of task")?,
+            (None, false) => write!(f, "poll of future")?,
+        }
+        write!(
+            f,
+            " took {:.1?}, more than {:.1?}, {} at {}",
+            self.duration,
+            self.threshold,
+            if self.spawned { "spawned" } else { "created" },
+            self.location
+        )
+    }
+}
+
+/// Extension trait to report the slow polls of individual futures.
+pub trait SlowPollExt: Future + Sized {
+    /// Reports the polls of this future that take longer than the threshold of `detector`.
+    #[track_caller]
+    fn detect_slow_polls(self, detector: SlowPollDetector) -> DetectSlowPolls<Self> {
+        DetectSlowPolls {
+            fut: self,
+            name: None,
+            location: Location::caller(),
+            detector: Some(detector),
+        }
+    }
+}
+
+impl<F: Future> SlowPollExt for F {}
+
+/// Future returned by [`SlowPollExt::detect_slow_polls`].
+#[pin_project::pin_project]
+#[derive(derive_more::Debug)]
+pub struct DetectSlowPolls<F> {
+    #[pin]
+    #[debug(skip)]
+    fut: F,
+    name: Option<Arc<str>>,
+    location: &'static Location<'static>,
+    /// The detector of this future, or `None` to use the installed one.
+    detector: Option<SlowPollDetector>,
+}
+
+impl<F> DetectSlowPolls<F> {
+    /// Wraps a task's future to report its slow polls to the installed detector.
+    pub(super) fn spawned(
+        fut: F,
+        name: Option<Arc<str>>,
+        location: &'static Location<'static>,
+    ) -> Self {
+        Self {
+            fut,
+            name,
+            location,
+            detector: None,
+        }
+    }
+}
+
+impl<F: Future> Future for DetectSlowPolls<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let Some(detector) = this.detector.as_ref().or_else(|| GLOBAL.get()) else {
+            return this.fut.poll(cx);
+        };
+        let start = Instant::now();
+        let poll = this.fut.poll(cx);
+        let duration = start.elapsed();
+        if duration > detector.threshold {
+            detector.report(&SlowPoll {
+                name: this.name.clone(),
+                location: this.location,
+                spawned: this.detector.is_none(),
+                duration,
+                threshold: detector.threshold,
+            });
+        }
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    #[cfg(not(wasm_browser))]
+    use tokio::test;
+    #[cfg(wasm_browser)]
+    use wasm_bindgen_test::wasm_bindgen_test as test;
+
+    use super::*;
+
+    /// Blocks the thread, to have the future spend time in a poll.
+    fn busy(duration: Duration) {
+        let start = Instant::now();
+        while start.elapsed() < duration {
+            std::hint::spin_loop();
+        }
+    }
+
+    fn recording() -> (SlowPollDetector, Arc<Mutex<Vec<SlowPoll>>>) {
+        let polls = Arc::new(Mutex::new(Vec::new()));
+        let detector = SlowPollDetector::new(Duration::from_millis(5)).on_slow_poll({
+            let polls = polls.clone();
+            move |poll| polls.lock().unwrap().push(poll.clone())
+        });
+        (detector, polls)
+    }
+
+    #[test]
+    async fn detects_slow_polls_of_future() {
+        let (detector, polls) = recording();
+        async {
+            busy(Duration::from_millis(10));
+            crate::future::yield_now().await;
+        }
+        .detect_slow_polls(detector)
+        .await;
+
+        let polls = polls.lock().unwrap();
+        assert_eq!(polls.len(), 1);
+        assert!(polls[0].duration() >= Duration::from_millis(10));
+        assert_eq!(polls[0].location().file(), file!());
+        assert!(polls[0].to_string().starts_with("poll of future took"));
+    }
+}
@@ -0,0 +1,648 @@
+//! Implements the [`Supervisor`] utility.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    future::Future,
+    panic::Location,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures_lite::Stream;
+
+use super::{AbortHandle, Id, JoinError, JoinSet, PanicPolicy};
+use crate::time::{self, Duration, Instant};
+
+/// Whether to only restart the failed child, or all children of a [`Supervisor`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum RestartStrategy {
+    /// Only the child that failed is restarted.
+    #[default]
+    OneForOne,
+    /// When a child fails, all other children are aborted and all of them are restarted.
+    OneForAll,
+}
+
+/// Configures when and how a [`Supervisor`] restarts its children.
+///
+/// A child is restarted when it returns an error or panics. Children that return
+/// `Ok(())` are considered done and are not restarted.
+///
+/// Restarts are delayed by an exponential backoff, which starts at the
+/// [minimum backoff] and doubles for every restart within the restart window, up to
+/// the [maximum backoff]. If a child needs to be restarted more than
+/// [`max_restarts`] times within the window, the supervisor gives up on it.
+///
+/// [minimum backoff]: RestartPolicy::backoff
+/// [maximum backoff]: RestartPolicy::backoff
+/// [`max_restarts`]: RestartPolicy::max_restarts
+#[derive(Debug, Clone)]
+pub struct RestartPolicy {
+    strategy: RestartStrategy,
+    max_restarts: usize,
+    window: Duration,
+    min_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            strategy: RestartStrategy::OneForOne,
+            max_restarts: 5,
+            window: Duration::from_secs(60),
+            min_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// Creates the default restart policy.
+    ///
+    /// By default, children are restarted one-for-one at most 5 times within 60 seconds,
+    /// with a backoff between 100 milliseconds and 10 seconds.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the [`RestartStrategy`].
+    pub fn strategy(mut self, strategy: RestartStrategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Sets the maximum number of restarts of a single child within `window`.
+    pub fn max_restarts(mut self, max_restarts: usize, window: Duration) -> Self {
+        self.max_restarts = max_restarts;
+        self.window = window;
+        self
+    }
+
+    /// Sets the minimum and maximum delay before restarting a child.
+    pub fn backoff(mut self, min: Duration, max: Duration) -> Self {
+        self.min_backoff = min;
+        self.max_backoff = max.max(min);
+        self
+    }
+
+    /// Returns the backoff delay for the given restart, starting at 1.
+    fn delay(&self, restart: usize) -> Duration {
+        let factor = 1u32.checked_shl(restart.saturating_sub(1) as u32);
+        factor
+            .and_then(|factor| self.min_backoff.checked_mul(factor))
+            .map_or(self.max_backoff, |delay| delay.min(self.max_backoff))
+    }
+}
+
+/// A lifecycle event of a [`Supervisor`]'s child.
+#[derive(Debug)]
+pub enum SupervisorEvent<E> {
+    /// The child was started.
+    ///
+    /// Restarted children only start running once their backoff delay elapsed.
+    Started {
+        /// The name of the child.
+        name: Arc<str>,
+        /// How often the child was restarted within the current restart window.
+        restarts: usize,
+    },
+    /// The child exited, by either returning `Ok(())` or an error.
+    ///
+    /// Children returning errors are restarted, unless the supervisor gives up on them.
+    Exited {
+        /// The name of the child.
+        name: Arc<str>,
+        /// The value returned by the child.
+        result: Result<(), E>,
+    },
+    /// The child panicked, and will be restarted unless the supervisor gives up on it.
+    Panicked {
+        /// The name of the child.
+        name: Arc<str>,
+        /// The error containing the panic payload.
+        error: JoinError,
+    },
+    /// The child's task was aborted through its [`AbortHandle`], which stops the child
+    /// without restarting it.
+    ///
+    /// Children stopped with [`Supervisor::abort`] or [`Supervisor::shutdown`] don't
+    /// report this.
+    Aborted {
+        /// The name of the child.
+        name: Arc<str>,
+    },
+    /// The child was restarted too often within the restart window and was stopped.
+    ///
+    /// With [`RestartStrategy::OneForAll`], all children are stopped.
+    GaveUp {
+        /// The name of the child.
+        name: Arc<str>,
+    },
+}
+
+/// Spawns a new task for a child onto the given tasks, which starts after the given delay.
+#[cfg(not(wasm_browser))]
+type Factory<E> = Box<dyn FnMut(&mut JoinSet<Result<(), E>>, Duration) -> AbortHandle + Send>;
+/// Spawns a new task for a child onto the given tasks, which starts after the given delay.
+#[cfg(wasm_browser)]
+type Factory<E> = Box<dyn FnMut(&mut JoinSet<Result<(), E>>, Duration) -> AbortHandle>;
+
+struct Child<E> {
+    /// The id of the child's initial task, which identifies the child.
+    id: Id,
+    name: Arc<str>,
+    factory: Factory<E>,
+    /// The handle of the currently running task, if any.
+    handle: Option<AbortHandle>,
+    /// Times of the restarts within the restart window.
+    restarts: VecDeque<Instant>,
+}
+
+/// Owns a set of long-running child tasks, and restarts them when they fail.
+///
+/// Children are spawned from factories, which are called again to restart the child
+/// according to the supervisor's [`RestartPolicy`]. The supervisor is driven by polling
+/// it for [`SupervisorEvent`]s, either with [`Supervisor::next_event`] or through its
+/// [`Stream`] implementation. Restarts only happen while the supervisor is polled.
+///
+/// Dropping the supervisor aborts all children.
+///
+/// # Example
+///
+/// ```ignore-wasm32-unknown-unknown
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// use n0_future::{
+///     task::{RestartPolicy, Supervisor, SupervisorEvent},
+///     time::Duration,
+/// };
+///
+/// let policy = RestartPolicy::new()
+///     .max_restarts(2, Duration::from_secs(10))
+///     .backoff(Duration::from_millis(1), Duration::from_millis(10));
+/// let mut supervisor = Supervisor::new(policy);
+/// supervisor.spawn("flaky", || async { Err("failed") });
+///
+/// while let Some(event) = supervisor.next_event().await {
+///     if let SupervisorEvent::GaveUp { name } = event {
+///         assert_eq!(&*name, "flaky");
+///     }
+/// }
+/// # }
+/// ```
+pub struct Supervisor<E> {
+    policy: RestartPolicy,
+    tasks: JoinSet<Result<(), E>>,
+    children: Vec<Child<E>>,
+    /// Maps the ids of running tasks to their index in `children`.
+    ids: HashMap<Id, usize>,
+    events: VecDeque<SupervisorEvent<E>>,
+}
+
+impl<E> fmt::Debug for Supervisor<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Supervisor")
+            .field("policy", &self.policy)
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+impl<E: 'static> Default for Supervisor<E> {
+    fn default() -> Self {
+        Self::new(RestartPolicy::default())
+    }
+}
+
+impl<E> Supervisor<E> {
+    /// Returns the number of children that are currently running or waiting to be restarted.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    /// Returns whether the supervisor has no running children.
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+}
+
+impl<E: 'static> Supervisor<E> {
+    /// Creates a new supervisor without any children.
+    pub fn new(policy: RestartPolicy) -> Self {
+        let mut tasks = JoinSet::new();
+        // Children that panicked are restarted, whatever the global policy is
+        tasks.set_panic_policy(PanicPolicy::ReturnError);
+        Self {
+            policy,
+            tasks,
+            children: Vec::new(),
+            ids: HashMap::new(),
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Spawns a new child, which is restarted by calling `factory` again when it fails.
+    ///
+    /// Returns an [`AbortHandle`] for the initial task of the child. Aborting it stops
+    /// the child without restarting it, as long as the initial task runs. Use
+    /// [`Supervisor::abort`] with the handle's id to stop the child after restarts, too.
+    #[cfg(not(wasm_browser))]
+    #[track_caller]
+    pub fn spawn<F, Fut>(&mut self, name: impl Into<Arc<str>>, mut factory: F) -> AbortHandle
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: Send,
+    {
+        // Restarted tasks are reported as spawned where the child was spawned, too
+        let location = Location::caller();
+        self.insert(
+            name.into(),
+            Box::new(move |tasks, delay| tasks.spawn_at(location, delayed(delay, factory()))),
+        )
+    }
+
+    /// Spawns a new child, which is restarted by calling `factory` again when it fails.
+    ///
+    /// Returns an [`AbortHandle`] for the initial task of the child. Aborting it stops
+    /// the child without restarting it, as long as the initial task runs. Use
+    /// [`Supervisor::abort`] with the handle's id to stop the child after restarts, too.
+    #[cfg(wasm_browser)]
+    #[track_caller]
+    pub fn spawn<F, Fut>(&mut self, name: impl Into<Arc<str>>, mut factory: F) -> AbortHandle
+    where
+        F: FnMut() -> Fut + 'static,
+        Fut: Future<Output = Result<(), E>> + 'static,
+    {
+        // Restarted tasks are reported as spawned where the child was spawned, too
+        let location = Location::caller();
+        self.insert(
+            name.into(),
+            Box::new(move |tasks, delay| tasks.spawn_at(location, delayed(delay, factory()))),
+        )
+    }
+
+    /// Stops the child that was spawned with the given id, without restarting it.
+    ///
+    /// The id is the one of the [`AbortHandle`] returned from [`Supervisor::spawn`].
+    /// Unlike aborting that handle, this also stops the child once it was restarted.
+    ///
+    /// Returns whether the child was running or waiting to be restarted.
+    pub fn abort(&mut self, id: Id) -> bool {
+        let Some(index) = self.children.iter().position(|child| child.id == id) else {
+            return false;
+        };
+        let running = self.children[index].handle.is_some();
+        self.stop(index);
+        running
+    }
+
+    fn insert(&mut self, name: Arc<str>, mut factory: Factory<E>) -> AbortHandle {
+        let handle = factory(&mut self.tasks, Duration::ZERO);
+        self.children.push(Child {
+            id: handle.id(),
+            name,
+            factory,
+            handle: None,
+            restarts: VecDeque::new(),
+        });
+        self.track(self.children.len() - 1, handle.clone());
+        handle
+    }
+
+    /// Starts the child at `index` after `delay`.
+    fn start(&mut self, index: usize, delay: Duration) {
+        let handle = (self.children[index].factory)(&mut self.tasks, delay);
+        self.track(index, handle);
+    }
+
+    /// Keeps track of `handle` as the running task of the child at `index`.
+    fn track(&mut self, index: usize, handle: AbortHandle) {
+        let child = &mut self.children[index];
+        self.ids.insert(handle.id(), index);
+        child.handle = Some(handle);
+        self.events.push_back(SupervisorEvent::Started {
+            name: child.name.clone(),
+            restarts: child.restarts.len(),
+        });
+    }
+
+    /// Stops the child at `index`, without restarting it.
+    fn stop(&mut self, index: usize) {
+        if let Some(handle) = self.children[index].handle.take() {
+            handle.abort();
+            self.ids.remove(&handle.id());
+        }
+    }
+
+    /// Restarts the child at `index` after it failed, or gives up on it.
+    fn restart(&mut self, index: usize) {
+        let now = Instant::now();
+        let window = self.policy.window;
+        let child = &mut self.children[index];
+        while child
+            .restarts
+            .front()
+            .is_some_and(|time| now.duration_since(*time) > window)
+        {
+            child.restarts.pop_front();
+        }
+
+        if child.restarts.len() >= self.policy.max_restarts {
+            let others = match self.policy.strategy {
+                RestartStrategy::OneForOne => vec![],
+                RestartStrategy::OneForAll => self.running_except(index),
+            };
+            for index in std::iter::once(index).chain(others) {
+                self.stop(index);
+                self.events.push_back(SupervisorEvent::GaveUp {
+                    name: self.children[index].name.clone(),
+                });
+            }
+            return;
+        }
+
+        child.restarts.push_back(now);
+        let delay = self.policy.delay(child.restarts.len());
+        if self.policy.strategy == RestartStrategy::OneForAll {
+            for other in self.running_except(index) {
+                self.stop(other);
+                self.start(other, delay);
+            }
+        }
+        self.start(index, delay);
+    }
+
+    fn running_except(&self, index: usize) -> Vec<usize> {
+        (0..self.children.len())
+            .filter(|i| *i != index && self.children[*i].handle.is_some())
+            .collect()
+    }
+
+    /// Waits for the next lifecycle event of the supervisor's children.
+    ///
+    /// Returns `None` once no children are running anymore.
+    pub async fn next_event(&mut self) -> Option<SupervisorEvent<E>> {
+        std::future::poll_fn(|cx| self.poll_next_event(cx)).await
+    }
+
+    /// Polls for the next lifecycle event of the supervisor's children.
+    ///
+    /// When the method returns `Poll::Pending`, the `Waker` in the provided `Context` is
+    /// scheduled to receive a wakeup when a child exits.
+    pub fn poll_next_event(&mut self, cx: &mut Context<'_>) -> Poll<Option<SupervisorEvent<E>>> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Poll::Ready(Some(event));
+            }
+            let (id, res) = match self.tasks.poll_join_next_with_id(cx) {
+                Poll::Pending => return Poll::Pending,
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Ready(Some(Ok((id, out)))) => (id, Ok(out)),
+                Poll::Ready(Some(Err(err))) => (err.id(), Err(err)),
+            };
+            // Tasks that were stopped by the supervisor aren't tracked anymore.
+            let Some(index) = self.ids.remove(&id) else {
+                continue;
+            };
+            let child = &mut self.children[index];
+            child.handle = None;
+            let name = child.name.clone();
+            match res {
+                Ok(Ok(())) => {
+                    self.events.push_back(SupervisorEvent::Exited {
+                        name,
+                        result: Ok(()),
+                    });
+                }
+                Ok(Err(err)) => {
+                    self.events.push_back(SupervisorEvent::Exited {
+                        name,
+                        result: Err(err),
+                    });
+                    self.restart(index);
+                }
+                // The child was aborted through its `AbortHandle`.
+                Err(err) if err.is_cancelled() => {
+                    self.events.push_back(SupervisorEvent::Aborted { name });
+                }
+                Err(error) => {
+                    self.events
+                        .push_back(SupervisorEvent::Panicked { name, error });
+                    self.restart(index);
+                }
+            }
+        }
+    }
+
+    /// Aborts all children and waits for them to finish, without restarting them.
+    pub async fn shutdown(&mut self) {
+        for child in self.children.iter_mut() {
+            child.handle = None;
+        }
+        self.ids.clear();
+        self.tasks.shutdown().await;
+    }
+}
+
+/// Runs `fut` once `delay` elapsed, right away if it's zero.
+async fn delayed<F: Future>(delay: Duration, fut: F) -> F::Output {
+    if !delay.is_zero() {
+        time::sleep(delay).await;
+    }
+    fut.await
+}
+
+// `events` holds values of `E`, which would make the supervisor only `Unpin` if `E` is,
+// but the events are never pinned.
+impl<E> Unpin for Supervisor<E> {}
+
+impl<E: 'static> Stream for Supervisor<E> {
+    type Item = SupervisorEvent<E>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().poll_next_event(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[cfg(not(wasm_browser))]
+    use tokio::test;
+    #[cfg(wasm_browser)]
+    use wasm_bindgen_test::wasm_bindgen_test as test;
+
+    use super::*;
+
+    fn policy(max_restarts: usize) -> RestartPolicy {
+        RestartPolicy::new()
+            .max_restarts(max_restarts, Duration::from_secs(60))
+            .backoff(Duration::from_millis(1), Duration::from_millis(4))
+    }
+
+    #[test]
+    async fn supervisor_restarts_until_success() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let mut supervisor = Supervisor::new(policy(5));
+        supervisor.spawn("child", {
+            let runs = runs.clone();
+            move || {
+                let runs = runs.clone();
+                async move {
+                    match runs.fetch_add(1, Ordering::SeqCst) {
+                        0 => Err("failed"),
+                        1 => panic!("boom"),
+                        _ => Ok(()),
+                    }
+                }
+            }
+        });
+
+        let mut events = Vec::new();
+        while let Some(event) = supervisor.next_event().await {
+            events.push(event);
+        }
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        assert!(matches!(
+            events.as_slice(),
+            [
+                SupervisorEvent::Started { restarts: 0, .. },
+                SupervisorEvent::Exited {
+                    result: Err("failed"),
+                    ..
+                },
+                SupervisorEvent::Started { restarts: 1, .. },
+                SupervisorEvent::Panicked { .. },
+                SupervisorEvent::Started { restarts: 2, .. },
+                SupervisorEvent::Exited { result: Ok(()), .. },
+            ]
+        ));
+        assert!(supervisor.is_empty());
+    }
+
+    #[test]
+    async fn supervisor_gives_up() {
+        let policy = policy(2);
+        assert_eq!(policy.delay(1), Duration::from_millis(1));
+        assert_eq!(policy.delay(2), Duration::from_millis(2));
+        assert_eq!(policy.delay(3), Duration::from_millis(4));
+        assert_eq!(policy.delay(100), Duration::from_millis(4));
+
+        let mut supervisor = Supervisor::new(policy);
+        supervisor.spawn("child", || async { Err(()) });
+
+        let mut started = 0;
+        let mut gave_up = false;
+        while let Some(event) = supervisor.next_event().await {
+            match event {
+                SupervisorEvent::Started { .. } => started += 1,
+                SupervisorEvent::GaveUp { name } => {
+                    assert_eq!(&*name, "child");
+                    gave_up = true;
+                }
+                _ => {}
+            }
+        }
+        assert_eq!(started, 3);
+        assert!(gave_up);
+    }
+
+    #[test]
+    async fn supervisor_one_for_all() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let mut supervisor = Supervisor::new(policy(1).strategy(RestartStrategy::OneForAll));
+        supervisor.spawn("long", {
+            let runs = runs.clone();
+            move || {
+                runs.fetch_add(1, Ordering::SeqCst);
+                std::future::pending::<Result<(), ()>>()
+            }
+        });
+        supervisor.spawn("failing", || async { Err(()) });
+
+        let mut gave_up = Vec::new();
+        while let Some(event) = supervisor.next_event().await {
+            if let SupervisorEvent::GaveUp { name } = event {
+                gave_up.push(name.to_string());
+            }
+        }
+        // The long-running child was restarted along with the failing one, and stopped
+        // once the supervisor gave up.
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert_eq!(gave_up, ["failing", "long"]);
+    }
+
+    #[test]
+    async fn supervisor_abort_stops_restarted_child() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let mut supervisor = Supervisor::new(policy(5));
+        let handle = supervisor.spawn("child", {
+            let runs = runs.clone();
+            move || {
+                let runs = runs.clone();
+                async move {
+                    if runs.fetch_add(1, Ordering::SeqCst) == 0 {
+                        return Err(());
+                    }
+                    std::future::pending().await
+                }
+            }
+        });
+
+        while let Some(event) = supervisor.next_event().await {
+            if let SupervisorEvent::Started { restarts: 1, .. } = event {
+                break;
+            }
+        }
+        // Wait for the restarted task to run past its backoff
+        while runs.load(Ordering::SeqCst) < 2 {
+            time::sleep(Duration::from_millis(1)).await;
+        }
+        // The handle only refers to the initial task, which exited already
+        handle.abort();
+        assert_eq!(supervisor.len(), 1);
+
+        assert!(supervisor.abort(handle.id()));
+        assert!(!supervisor.abort(handle.id()));
+        assert!(supervisor.is_empty());
+        assert!(supervisor.next_event().await.is_none());
+    }
+
+    #[test]
+    async fn supervisor_reports_aborted_child() {
+        let mut supervisor = Supervisor::new(policy(5));
+        let handle = supervisor.spawn("child", std::future::pending::<Result<(), ()>>);
+        handle.abort();
+
+        let mut events = Vec::new();
+        while let Some(event) = supervisor.next_event().await {
+            events.push(event);
+        }
+        assert!(matches!(
+            events.as_slice(),
+            [
+                SupervisorEvent::Started { restarts: 0, .. },
+                SupervisorEvent::Aborted { name },
+            ] if &**name == "child"
+        ));
+        assert!(supervisor.is_empty());
+    }
+
+    #[cfg(feature = "registry")]
+    #[test]
+    async fn supervisor_reports_caller_location() {
+        let mut supervisor = Supervisor::<()>::new(policy(5));
+        let (handle, line) = (supervisor.spawn("child", std::future::pending), line!());
+        let info = crate::task::dump()
+            .into_iter()
+            .find(|info| info.id() == handle.id())
+            .expect("task is registered");
+        assert_eq!(info.location().line(), line);
+        supervisor.shutdown().await;
+    }
+}
@@ -0,0 +1,546 @@
+//! Async stack traces of the tasks spawned through [`crate::task`].
+//!
+//! Futures wrapped with [`TraceExt::traced`] record themselves as frames of the task
+//! that polls them, nested inside of the traced futures that contain them. Calling
+//! [`dump`] returns the frames of all running tasks as a tree, which shows the chain
+//! of `.await`s each task is currently suspended in.
+//!
+//! This doesn't depend on tokio's task dumps, and works the same natively and in
+//! browsers. Only available with the `trace` feature.
+//!
+//! # Example
+//!
+//! ```ignore-wasm32-unknown-unknown
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! use n0_future::task::{
+//!     self,
+//!     trace::{self, TraceExt},
+//! };
+//!
+//! async fn fetch() {
+//!     std::future::pending::<()>().traced("wait for response").await
+//! }
+//!
+//! let handle = task::Builder::new()
+//!     .name("fetcher")
+//!     .spawn(fetch().traced("fetch"));
+//! n0_future::time::sleep(n0_future::time::Duration::from_millis(10)).await;
+//!
+//! let dump = trace::dump();
+//! let trace = dump.iter().find(|t| t.id() == handle.id()).unwrap();
+//! assert_eq!(trace.frames()[0].name(), "fetch");
+//! assert_eq!(trace.frames()[0].children()[0].name(), "wait for response");
+//! // Prints the frames of each task as a tree:
+//! // task 1 "fetcher", spawned at src/main.rs:12:6
+//! // └─ fetch at src/main.rs:12:23
+//! //    └─ wait for response at src/main.rs:7:35
+//! println!("{dump}");
+//! # }
+//! ```
+
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fmt,
+    future::Future,
+    panic::Location,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, OnceLock, Weak,
+    },
+    task::{Context, Poll},
+};
+
+use super::Id;
+
+/// All tasks that were spawned and not dropped yet, keyed by the order they were
+/// spawned in.
+static TASKS: Mutex<BTreeMap<u64, Weak<Task>>> = Mutex::new(BTreeMap::new());
+
+static NEXT_KEY: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// The frame that's currently being polled on this thread.
+    static CURRENT: RefCell<Option<Arc<Node>>> = const { RefCell::new(None) };
+}
+
+/// Returns the async stack traces of all tasks that were spawned through
+/// [`crate::task`] and are still running.
+///
+/// Tasks are listed in the order they were spawned in.
+pub fn dump() -> TraceDump {
+    // Upgrading outside of the lock, as the upgraded task can be the last handle to it,
+    // whose drop locks the tasks again
+    let tasks: Vec<Weak<Task>> = TASKS.lock().expect("poisoned").values().cloned().collect();
+    let tasks = tasks
+        .iter()
+        .filter_map(Weak::upgrade)
+        .filter(|task| !task.root.done.load(Ordering::Relaxed))
+        .filter_map(|task| {
+            // Tasks whose id isn't recorded yet are only just being spawned
+            Some(TaskTrace {
+                id: *task.id.get()?,
+                name: task.name.clone(),
+                location: task.location,
+                frames: task.root.children(),
+            })
+        })
+        .collect();
+    TraceDump { tasks }
+}
+
+/// The async stack traces of the tasks that were running when [`dump`] was called.
+#[derive(Debug, Clone)]
+pub struct TraceDump {
+    tasks: Vec<TaskTrace>,
+}
+
+impl TraceDump {
+    /// Returns the traces of the tasks, in the order they were spawned in.
+    pub fn tasks(&self) -> &[TaskTrace] {
+        &self.tasks
+    }
+
+    /// Returns an iterator over the traces of the tasks.
+    pub fn iter(&self) -> std::slice::Iter<'_, TaskTrace> {
+        self.tasks.iter()
+    }
+}
+
+impl IntoIterator for TraceDump {
+    type Item = TaskTrace;
+    type IntoIter = std::vec::IntoIter<TaskTrace>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.tasks.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a TraceDump {
+    type Item = &'a TaskTrace;
+    type IntoIter = std::slice::Iter<'a, TaskTrace>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.tasks.iter()
+    }
+}
+
+impl fmt::Display for TraceDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, task) in self.tasks.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{task}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The async stack trace of a single task in a [`TraceDump`].
+#[derive(Debug, Clone)]
+pub struct TaskTrace {
+    id: Id,
+    name: Option<Arc<str>>,
+    location: &'static Location<'static>,
+    frames: Vec<Frame>,
+}
+
+impl TaskTrace {
+    /// Returns the id of the task.
+    pub fn id(&self) -> Id {
+        self.id
+    }
+
+    /// Returns the name of the task, if it was spawned with one using [`Builder::name`].
+    ///
+    /// [`Builder::name`]: super::Builder::name
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Returns the location in the source code that spawned the task.
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+
+    /// Returns the outermost traced futures the task is suspended in.
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+}
+
+impl fmt::Display for TaskTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "task {}", self.id)?;
+        if let Some(name) = &self.name {
+            write!(f, " {name:?}")?;
+        }
+        write!(f, ", spawned at {}", self.location)?;
+        write_frames(f, &self.frames, "")
+    }
+}
+
+fn write_frames(f: &mut fmt::Formatter<'_>, frames: &[Frame], indent: &str) -> fmt::Result {
+    for (i, frame) in frames.iter().enumerate() {
+        let last = i + 1 == frames.len();
+        let (branch, nested) = if last {
+            ("└─", "   ")
+        } else {
+            ("├─", "│  ")
+        };
+        write!(f, "\n{indent}{branch} {frame}")?;
+        write_frames(f, &frame.children, &format!("{indent}{nested}"))?;
+    }
+    Ok(())
+}
+
+/// A traced future in a [`TaskTrace`].
+#[derive(Debug, Clone)]
+pub struct Frame {
+    name: &'static str,
+    location: &'static Location<'static>,
+    children: Vec<Frame>,
+}
+
+impl Frame {
+    /// Returns the name passed to [`TraceExt::traced`].
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the location in the source code that called [`TraceExt::traced`].
+    pub fn location(&self) -> &'static Location<'static> {
+        self.location
+    }
+
+    /// Returns the traced futures that this future is suspended in.
+    pub fn children(&self) -> &[Frame] {
+        &self.children
+    }
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}", self.name, self.location)
+    }
+}
+
+/// Extension trait to record futures as frames of async stack traces.
+pub trait TraceExt: Future + Sized {
+    /// Records this future as a frame named `name` in the trace of the task polling it.
+    ///
+    /// Outside of tasks spawned through [`crate::task`], this has no effect.
+    #[track_caller]
+    fn traced(self, name: &'static str) -> Traced<Self> {
+        Traced {
+            fut: self,
+            name,
+            location: Location::caller(),
+            node: None,
+        }
+    }
+}
+
+impl<F: Future> TraceExt for F {}
+
+/// Future returned by [`TraceExt::traced`].
+#[pin_project::pin_project]
+#[derive(derive_more::Debug)]
+pub struct Traced<F> {
+    #[pin]
+    #[debug(skip)]
+    fut: F,
+    name: &'static str,
+    location: &'static Location<'static>,
+    #[debug(skip)]
+    node: Option<Arc<Node>>,
+}
+
+impl<F: Future> Future for Traced<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        if this.node.is_none() {
+            // Attaches to the frame that polls this future for the first time
+            let Some(parent) = CURRENT.with(|current| current.borrow().clone()) else {
+                return this.fut.poll(cx);
+            };
+            let node = Arc::new(Node::new(Some((this.name, this.location))));
+            parent.push(&node);
+            *this.node = Some(node);
+        }
+        let node = this.node.as_ref().expect("just set");
+        let _enter = Enter::new(node.clone());
+        let poll = this.fut.poll(cx);
+        if poll.is_ready() {
+            node.done.store(true, Ordering::Relaxed);
+        }
+        poll
+    }
+}
+
+/// A frame of an async stack trace, or the root of the frames of a task.
+#[derive(Debug)]
+struct Node {
+    frame: Option<(&'static str, &'static Location<'static>)>,
+    /// The traced futures inside of this one, which are dropped when the futures are.
+    children: Mutex<Vec<Weak<Node>>>,
+    done: AtomicBool,
+}
+
+impl Node {
+    fn new(frame: Option<(&'static str, &'static Location<'static>)>) -> Self {
+        Self {
+            frame,
+            children: Mutex::new(Vec::new()),
+            done: AtomicBool::new(false),
+        }
+    }
+
+    fn push(&self, child: &Arc<Node>) {
+        let mut children = self.children.lock().expect("poisoned");
+        children.retain(|child| child.strong_count() > 0);
+        children.push(Arc::downgrade(child));
+    }
+
+    fn children(&self) -> Vec<Frame> {
+        let children = self.children.lock().expect("poisoned");
+        children
+            .iter()
+            .filter_map(Weak::upgrade)
+            .filter(|node| !node.done.load(Ordering::Relaxed))
+            .filter_map(|node| {
+                let (name, location) = node.frame?;
+                Some(Frame {
+                    name,
+                    location,
+                    children: node.children(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Sets the frame that's currently being polled, restoring the previous one afterwards.
+struct Enter(Option<Arc<Node>>);
+
+impl Enter {
+    fn new(node: Arc<Node>) -> Self {
+        Self(CURRENT.with(|current| current.replace(Some(node))))
+    }
+}
+
+impl Drop for Enter {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+/// The trace of a task, shared by the task and all of its handles.
+#[derive(Debug)]
+pub(super) struct Task {
+    key: u64,
+    id: OnceLock<Id>,
+    name: Option<Arc<str>>,
+    location: &'static Location<'static>,
+    root: Arc<Node>,
+}
+
+impl Task {
+    /// Adds a new task to the traced tasks.
+    pub(super) fn register(
+        name: Option<Arc<str>>,
+        location: &'static Location<'static>,
+    ) -> Arc<Self> {
+        let task = Self::untracked(name, location);
+        TASKS
+            .lock()
+            .expect("poisoned")
+            .insert(task.key, Arc::downgrade(&task));
+        task
+    }
+
+    /// Creates the trace of a task that wasn't spawned through [`crate::task`], which
+    /// isn't added to the traced tasks.
+    pub(super) fn untracked(
+        name: Option<Arc<str>>,
+        location: &'static Location<'static>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            key: NEXT_KEY.fetch_add(1, Ordering::Relaxed),
+            id: OnceLock::new(),
+            name,
+            location,
+            root: Arc::new(Node::new(None)),
+        })
+    }
+
+    /// Runs the closure of a blocking task, which doesn't have any frames, and which is
+    /// done once it returns.
+    #[cfg(not(wasm_browser))]
+    pub(super) fn run_blocking<R>(&self, f: impl FnOnce() -> R) -> R {
+        /// Marks the task as done once the closure returned or panicked.
+        struct Done<'a>(&'a Node);
+
+        impl Drop for Done<'_> {
+            fn drop(&mut self) {
+                self.0.done.store(true, Ordering::Relaxed);
+            }
+        }
+
+        let _done = Done(&self.root);
+        f()
+    }
+
+    /// Sets the id of the task, once it's known after spawning it.
+    pub(super) fn set_id(&self, id: Id) {
+        self.id.get_or_init(|| id);
+    }
+}
+
+impl Drop for Task {
+    fn drop(&mut self) {
+        if let Ok(mut tasks) = TASKS.lock() {
+            tasks.remove(&self.key);
+        }
+    }
+}
+
+/// Wraps a task's future to make it the root of the frames polled inside of it.
+#[pin_project::pin_project(PinnedDrop)]
+pub(super) struct Root<F> {
+    #[pin]
+    fut: F,
+    task: Arc<Task>,
+}
+
+impl<F> Root<F> {
+    pub(super) fn new(fut: F, task: Arc<Task>) -> Self {
+        Self { fut, task }
+    }
+}
+
+impl<F: Future> Future for Root<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let _enter = Enter::new(this.task.root.clone());
+        let poll = this.fut.poll(cx);
+        if poll.is_ready() {
+            this.task.root.done.store(true, Ordering::Relaxed);
+        }
+        poll
+    }
+}
+
+/// Tasks are dropped once they completed, or when they're aborted.
+#[pin_project::pinned_drop]
+impl<F> PinnedDrop for Root<F> {
+    fn drop(self: Pin<&mut Self>) {
+        self.task.root.done.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(not(wasm_browser))]
+    use tokio::test;
+    #[cfg(wasm_browser)]
+    use wasm_bindgen_test::wasm_bindgen_test as test;
+
+    use super::*;
+    use crate::task;
+
+    fn find(id: Id) -> Option<TaskTrace> {
+        dump().into_iter().find(|trace| trace.id() == id)
+    }
+
+    #[cfg(not(wasm_browser))]
+    #[test]
+    async fn dump_while_dropping_tasks() {
+        let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let dumpers: Vec<_> = (0..4)
+            .map(|_| {
+                let done = done.clone();
+                std::thread::spawn(move || {
+                    while !done.load(Ordering::Relaxed) {
+                        dump();
+                    }
+                })
+            })
+            .collect();
+        let droppers: Vec<_> = (0..4)
+            .map(|_| {
+                std::thread::spawn(|| {
+                    for _ in 0..100_000 {
+                        // Drops the last handle while a dumper might hold the task
+                        drop(Task::register(Some("dropped".into()), Location::caller()));
+                    }
+                })
+            })
+            .collect();
+        for dropper in droppers {
+            dropper.join().unwrap();
+        }
+        done.store(true, Ordering::Relaxed);
+        for dumper in dumpers {
+            dumper.join().unwrap();
+        }
+    }
+
+    #[test]
+    async fn trace_records_await_chain() {
+        let handle = task::Builder::new().name("traced").spawn(
+            async {
+                let first = std::future::ready(()).traced("ready");
+                first.await;
+                futures_lite::future::zip(
+                    std::future::pending::<()>().traced("left"),
+                    std::future::pending::<()>().traced("right"),
+                )
+                .await;
+            }
+            .traced("outer"),
+        );
+        // Waits until the task is suspended in both futures
+        let trace = loop {
+            let trace = find(handle.id()).expect("task is traced");
+            if trace
+                .frames()
+                .first()
+                .is_some_and(|f| f.children().len() == 2)
+            {
+                break trace;
+            }
+            crate::time::sleep(std::time::Duration::from_millis(1)).await;
+        };
+        assert_eq!(trace.name(), Some("traced"));
+        let outer = &trace.frames()[0];
+        assert_eq!(outer.name(), "outer");
+        let children: Vec<_> = outer.children().iter().map(Frame::name).collect();
+        assert_eq!(children, ["left", "right"]);
+        assert_eq!(outer.location().file(), file!());
+
+        let rendered = trace.to_string();
+        assert!(rendered.contains("\n└─ outer at "), "{rendered}");
+        assert!(rendered.contains("\n   ├─ left at "), "{rendered}");
+        assert!(rendered.contains("\n   └─ right at "), "{rendered}");
+
+        // Completed tasks aren't part of the dump
+        handle.abort();
+        let id = handle.id();
+        handle.await.unwrap_err();
+        assert!(find(id).is_none());
+    }
+
+    #[test]
+    async fn traced_outside_of_task() {
+        assert_eq!(async { 1 }.traced("untracked").await, 1);
+    }
+}
@@ -0,0 +1,234 @@
+//! Implements propagating deadlines to nested operations, see [`with_deadline`].
+
+use std::{
+    cell::Cell,
+    future::{Future, IntoFuture},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use super::{Duration, Instant};
+
+thread_local! {
+    /// The deadline installed for the future that's currently being polled.
+    static CURRENT: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// The point in time by which an operation, and all operations nested in it, should
+/// complete.
+///
+/// Deadlines are installed for a future using [`with_deadline`], and apply to all
+/// [`timeout`]s created while it's polled, as well as to the tasks it spawns through
+/// [`crate::task`].
+///
+/// [`timeout`]: super::timeout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Deadline(Instant);
+
+impl Deadline {
+    /// Creates a deadline at the given instant.
+    pub fn at(instant: Instant) -> Self {
+        Self(instant)
+    }
+
+    /// Returns the instant of the deadline.
+    pub fn instant(&self) -> Instant {
+        self.0
+    }
+
+    /// Returns the time left until the deadline, which is zero once it passed.
+    pub fn remaining(&self) -> Duration {
+        self.0.saturating_duration_since(Instant::now())
+    }
+
+    /// Returns whether the deadline passed.
+    pub fn is_elapsed(&self) -> bool {
+        Instant::now() >= self.0
+    }
+}
+
+/// Returns the deadline installed for the current future using [`with_deadline`].
+///
+/// Spawned tasks inherit the deadline of the future that spawned them. The deadlines of
+/// [`Timeout`]s aren't installed for the futures they wrap, so they don't propagate.
+///
+/// [`Timeout`]: super::Timeout
+pub fn current_deadline() -> Option<Deadline> {
+    CURRENT.get().map(Deadline)
+}
+
+/// Runs `fut` with `deadline` installed as the [`current_deadline`].
+///
+/// If a deadline is installed already, the earlier one of both applies. All
+/// [`timeout`]s created within `fut` elapse by the deadline at the latest, and tasks
+/// spawned from within `fut` inherit the deadline.
+///
+/// This doesn't cancel `fut` once the deadline passed, use [`timeout_at`] for that.
+///
+/// # Example
+///
+/// ```ignore-wasm32-unknown-unknown
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// use n0_future::time::{self, Duration, Instant};
+///
+/// let deadline = Instant::now() + Duration::from_secs(1);
+/// time::with_deadline(deadline, async {
+///     assert_eq!(time::current_deadline().unwrap().instant(), deadline);
+///     // Nested timeouts don't exceed the deadline
+///     let timeout = time::timeout(Duration::from_secs(60), async {});
+///     assert_eq!(timeout.deadline(), deadline);
+/// })
+/// .await;
+/// # }
+/// ```
+///
+/// [`timeout`]: super::timeout
+/// [`timeout_at`]: super::timeout_at
+pub fn with_deadline<F: IntoFuture>(deadline: Instant, fut: F) -> WithDeadline<F::IntoFuture> {
+    WithDeadline {
+        deadline: Some(deadline),
+        clamp: true,
+        fut: fut.into_future(),
+    }
+}
+
+/// Future returned by [`with_deadline`].
+#[derive(derive_more::Debug)]
+#[pin_project::pin_project]
+pub struct WithDeadline<F> {
+    deadline: Option<Instant>,
+    /// Whether the deadline is clamped to the one of the caller, which tasks don't do, as
+    /// they captured the deadline they inherit when they were spawned.
+    clamp: bool,
+    #[debug(skip)]
+    #[pin]
+    fut: F,
+}
+
+impl<F> WithDeadline<F> {
+    /// Installs `deadline` for a task, whichever deadline is installed where it's polled.
+    pub(crate) fn task(deadline: Option<Instant>, fut: F) -> Self {
+        Self {
+            deadline,
+            clamp: false,
+            fut,
+        }
+    }
+}
+
+impl<F: Future> Future for WithDeadline<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let deadline = match *this.deadline {
+            Some(deadline) if *this.clamp => Some(clamp(deadline)),
+            deadline => deadline,
+        };
+        let _guard = enter(deadline);
+        this.fut.poll(cx)
+    }
+}
+
+/// Returns the earlier one of `deadline` and the [`current_deadline`].
+pub(crate) fn clamp(deadline: Instant) -> Instant {
+    CURRENT
+        .get()
+        .map_or(deadline, |current| current.min(deadline))
+}
+
+/// Installs `deadline` as the current deadline until the returned guard is dropped.
+pub(crate) fn enter(deadline: Option<Instant>) -> Restore {
+    Restore(CURRENT.replace(deadline))
+}
+
+/// Restores the previously installed deadline once the future was polled, even if it
+/// panics.
+pub(crate) struct Restore(Option<Instant>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        CURRENT.set(self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(not(wasm_browser))]
+    use tokio::test;
+    #[cfg(wasm_browser)]
+    use wasm_bindgen_test::wasm_bindgen_test as test;
+
+    use super::*;
+    use crate::{task, time};
+
+    #[test]
+    async fn nested_deadlines_clamp() {
+        let outer = Instant::now() + Duration::from_secs(10);
+        let inner = outer + Duration::from_secs(10);
+        assert_eq!(current_deadline(), None);
+        with_deadline(outer, async {
+            // The tighter deadline of the caller applies
+            with_deadline(inner, async {
+                assert_eq!(current_deadline(), Some(Deadline::at(outer)));
+            })
+            .await;
+
+            let timeout = time::timeout(Duration::from_secs(60), async {});
+            assert_eq!(timeout.deadline(), outer);
+
+            let tighter = time::timeout(Duration::from_secs(1), async {
+                // Timeouts don't install their deadline
+                current_deadline()
+            });
+            assert!(tighter.deadline() < outer);
+            assert_eq!(tighter.await.unwrap(), Some(Deadline::at(outer)));
+        })
+        .await;
+        assert_eq!(current_deadline(), None);
+    }
+
+    #[test]
+    async fn spawned_tasks_inherit_deadline() {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let inherited = with_deadline(deadline, async {
+            task::spawn(async { current_deadline() }).await.unwrap()
+        })
+        .await;
+        assert_eq!(inherited, Some(Deadline::at(deadline)));
+        let inherited = task::spawn(async { current_deadline() }).await.unwrap();
+        assert_eq!(inherited, None);
+    }
+
+    #[test]
+    async fn spawned_tasks_outlive_parent_timeout() {
+        let mut handle = None;
+        time::timeout(Duration::from_millis(10), async {
+            handle = Some(task::spawn(async {
+                assert_eq!(current_deadline(), None);
+                time::sleep(Duration::from_millis(20)).await;
+                time::timeout(
+                    Duration::from_secs(10),
+                    time::sleep(Duration::from_millis(1)),
+                )
+                .await
+            }));
+        })
+        .await
+        .unwrap();
+        // The task runs its own timeout after the one of its parent elapsed
+        assert!(handle.unwrap().await.unwrap().is_ok());
+    }
+
+    #[test]
+    async fn tasks_ignore_deadline_they_are_polled_in() {
+        let local = task::LocalSet::new();
+        let handle = local.spawn_local(async { current_deadline() });
+        // The task is polled within the local set's `run_until`, but spawned without a
+        // deadline
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let inherited = with_deadline(deadline, local.run_until(handle)).await;
+        assert_eq!(inherited.unwrap(), None);
+    }
+}
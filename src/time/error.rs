@@ -0,0 +1,23 @@
+//! Error types of the [`time`] module (mirroring `tokio::time::error`).
+//!
+//! [`time`]: crate::time
+
+/// Error when a timeout is elapsed.
+#[derive(Debug, PartialEq, Eq, derive_more::Display)]
+#[display("deadline has elapsed")]
+pub struct Elapsed(pub(crate) ());
+
+impl std::error::Error for Elapsed {}
+
+#[cfg(not(wasm_browser))]
+impl From<tokio::time::error::Elapsed> for Elapsed {
+    fn from(_err: tokio::time::error::Elapsed) -> Self {
+        Self(())
+    }
+}
+
+impl From<Elapsed> for std::io::Error {
+    fn from(_err: Elapsed) -> Self {
+        std::io::ErrorKind::TimedOut.into()
+    }
+}
@@ -0,0 +1,377 @@
+use std::{
+    future::{Future, IntoFuture},
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use tokio::time::{Duration, Instant, MissedTickBehavior};
+
+use super::{deadline, shim, Elapsed};
+use crate::runtime;
+
+/// Future returned by [`sleep`] and [`sleep_until`].
+///
+/// Wraps a [`tokio::time::Sleep`], or a timer scheduled on the installed [`Runtime`].
+///
+/// [`Runtime`]: crate::runtime::Runtime
+#[derive(Debug)]
+#[pin_project::pin_project]
+pub struct Sleep {
+    #[pin]
+    repr: SleepRepr,
+}
+
+#[derive(Debug)]
+#[pin_project::pin_project(project = SleepProj)]
+enum SleepRepr {
+    Tokio(#[pin] tokio::time::Sleep),
+    Custom(shim::Sleep),
+}
+
+/// Waits until `duration` has elapsed.
+///
+/// Uses the timers of the installed [`Runtime`], or tokio's if none is installed, see
+/// [`tokio::time::sleep`].
+///
+/// [`Runtime`]: crate::runtime::Runtime
+pub fn sleep(duration: Duration) -> Sleep {
+    let repr = match runtime::installed() {
+        Some(_) => SleepRepr::Custom(shim::sleep(duration)),
+        None => SleepRepr::Tokio(tokio::time::sleep(duration)),
+    };
+    Sleep { repr }
+}
+
+/// Waits until `deadline` is reached.
+///
+/// Uses the timers of the installed [`Runtime`], or tokio's if none is installed, see
+/// [`tokio::time::sleep_until`].
+///
+/// [`Runtime`]: crate::runtime::Runtime
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    let repr = match runtime::installed() {
+        Some(_) => SleepRepr::Custom(shim::sleep_until(deadline.into_std())),
+        None => SleepRepr::Tokio(tokio::time::sleep_until(deadline)),
+    };
+    Sleep { repr }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match self.project().repr.project() {
+            SleepProj::Tokio(sleep) => sleep.poll(cx),
+            SleepProj::Custom(sleep) => Pin::new(sleep).poll(cx),
+        }
+    }
+}
+
+impl Sleep {
+    /// Returns the instant at which the sleep is scheduled to wake up.
+    pub fn deadline(&self) -> Instant {
+        match &self.repr {
+            SleepRepr::Tokio(sleep) => sleep.deadline(),
+            SleepRepr::Custom(sleep) => Instant::from_std(sleep.deadline()),
+        }
+    }
+
+    /// Returns whether the sleep has reached its deadline.
+    pub fn is_elapsed(&self) -> bool {
+        match &self.repr {
+            SleepRepr::Tokio(sleep) => sleep.is_elapsed(),
+            SleepRepr::Custom(sleep) => sleep.is_elapsed(),
+        }
+    }
+
+    /// Resets this sleep's deadline to given instant.
+    ///
+    /// Also works with sleeps that have already reached their deadline
+    /// in the past.
+    pub fn reset(self: Pin<&mut Self>, deadline: Instant) {
+        match self.project().repr.project() {
+            SleepProj::Tokio(sleep) => sleep.reset(deadline),
+            SleepProj::Custom(sleep) => Pin::new(sleep).reset(deadline.into_std()),
+        }
+    }
+}
+
+/// Interval returned by [`interval`] and [`interval_at`].
+///
+/// Wraps a [`tokio::time::Interval`], or an interval scheduled on the installed
+/// [`Runtime`].
+///
+/// [`Runtime`]: crate::runtime::Runtime
+#[derive(Debug)]
+pub struct Interval {
+    repr: IntervalRepr,
+}
+
+#[derive(Debug)]
+enum IntervalRepr {
+    Tokio(tokio::time::Interval),
+    Custom(shim::Interval),
+}
+
+/// Creates new [`Interval`] that yields with interval of `period`. The first
+/// tick completes immediately.
+///
+/// Uses the timers of the installed [`Runtime`], or tokio's if none is installed, see
+/// [`tokio::time::interval`].
+///
+/// [`Runtime`]: crate::runtime::Runtime
+#[track_caller]
+pub fn interval(period: Duration) -> Interval {
+    let repr = match runtime::installed() {
+        Some(_) => IntervalRepr::Custom(shim::interval(period)),
+        None => IntervalRepr::Tokio(tokio::time::interval(period)),
+    };
+    Interval { repr }
+}
+
+/// Creates new [`Interval`] that yields with interval of `period` with the
+/// first tick completing at `start`.
+///
+/// Uses the timers of the installed [`Runtime`], or tokio's if none is installed, see
+/// [`tokio::time::interval_at`].
+///
+/// [`Runtime`]: crate::runtime::Runtime
+#[track_caller]
+pub fn interval_at(start: Instant, period: Duration) -> Interval {
+    let repr = match runtime::installed() {
+        Some(_) => IntervalRepr::Custom(shim::interval_at(start.into_std(), period)),
+        None => IntervalRepr::Tokio(tokio::time::interval_at(start, period)),
+    };
+    Interval { repr }
+}
+
+impl Interval {
+    /// Completes when the next instant in the interval has been reached.
+    pub async fn tick(&mut self) -> Instant {
+        std::future::poll_fn(|cx| self.poll_tick(cx)).await
+    }
+
+    /// Polls for the next instant in the interval to be reached.
+    pub fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<Instant> {
+        match &mut self.repr {
+            IntervalRepr::Tokio(interval) => interval.poll_tick(cx),
+            IntervalRepr::Custom(interval) => interval.poll_tick(cx).map(Instant::from_std),
+        }
+    }
+
+    /// Resets the interval to complete one period after the current time.
+    pub fn reset(&mut self) {
+        match &mut self.repr {
+            IntervalRepr::Tokio(interval) => interval.reset(),
+            IntervalRepr::Custom(interval) => interval.reset(),
+        }
+    }
+
+    /// Resets the interval immediately.
+    pub fn reset_immediately(&mut self) {
+        match &mut self.repr {
+            IntervalRepr::Tokio(interval) => interval.reset_immediately(),
+            IntervalRepr::Custom(interval) => interval.reset_immediately(),
+        }
+    }
+
+    /// Resets the interval after the specified [`std::time::Duration`].
+    pub fn reset_after(&mut self, after: Duration) {
+        match &mut self.repr {
+            IntervalRepr::Tokio(interval) => interval.reset_after(after),
+            IntervalRepr::Custom(interval) => interval.reset_after(after),
+        }
+    }
+
+    /// Resets the interval to a [`crate::time::Instant`] deadline.
+    pub fn reset_at(&mut self, deadline: Instant) {
+        match &mut self.repr {
+            IntervalRepr::Tokio(interval) => interval.reset_at(deadline),
+            IntervalRepr::Custom(interval) => interval.reset_at(deadline.into_std()),
+        }
+    }
+
+    /// Returns the [`MissedTickBehavior`] strategy currently being used.
+    pub fn missed_tick_behavior(&self) -> MissedTickBehavior {
+        match &self.repr {
+            IntervalRepr::Tokio(interval) => interval.missed_tick_behavior(),
+            IntervalRepr::Custom(interval) => match interval.missed_tick_behavior() {
+                shim::MissedTickBehavior::Burst => MissedTickBehavior::Burst,
+                shim::MissedTickBehavior::Delay => MissedTickBehavior::Delay,
+                shim::MissedTickBehavior::Skip => MissedTickBehavior::Skip,
+            },
+        }
+    }
+
+    /// Sets the [`MissedTickBehavior`] strategy that should be used.
+    pub fn set_missed_tick_behavior(&mut self, behavior: MissedTickBehavior) {
+        match &mut self.repr {
+            IntervalRepr::Tokio(interval) => interval.set_missed_tick_behavior(behavior),
+            IntervalRepr::Custom(interval) => interval.set_missed_tick_behavior(match behavior {
+                MissedTickBehavior::Burst => shim::MissedTickBehavior::Burst,
+                MissedTickBehavior::Delay => shim::MissedTickBehavior::Delay,
+                MissedTickBehavior::Skip => shim::MissedTickBehavior::Skip,
+            }),
+        }
+    }
+
+    /// Returns the period of the interval.
+    pub fn period(&self) -> Duration {
+        match &self.repr {
+            IntervalRepr::Tokio(interval) => interval.period(),
+            IntervalRepr::Custom(interval) => interval.period(),
+        }
+    }
+}
+
+/// Pauses time.
+///
+/// Pauses the clock of the timers scheduled on the installed [`Runtime`], or tokio's
+/// clock if none is installed, see [`tokio::time::pause`].
+///
+/// Unlike tokio's clock, the clock of an installed runtime's timers is local to the
+/// current thread, so timers polled on other threads keep following the real time. Also,
+/// [`Instant::now`] isn't paused along with it, so deadlines passed to [`sleep_until`]
+/// and [`timeout_at`] must be based on the paused clock, e.g. on the [`Sleep::deadline`]
+/// of a timer created after pausing.
+///
+/// [`Runtime`]: crate::runtime::Runtime
+#[track_caller]
+pub fn pause() {
+    match runtime::installed() {
+        Some(_) => shim::pause(),
+        None => tokio::time::pause(),
+    }
+}
+
+/// Resumes time.
+///
+/// Resumes the clock of the timers scheduled on the installed [`Runtime`], or tokio's
+/// clock if none is installed, see [`tokio::time::resume`].
+///
+/// [`Runtime`]: crate::runtime::Runtime
+#[track_caller]
+pub fn resume() {
+    match runtime::installed() {
+        Some(_) => shim::resume(),
+        None => tokio::time::resume(),
+    }
+}
+
+/// Advances time.
+///
+/// Moves the paused clock of the timers scheduled on the installed [`Runtime`] forward,
+/// or tokio's clock if none is installed, see [`tokio::time::advance`].
+///
+/// [`Runtime`]: crate::runtime::Runtime
+pub async fn advance(duration: Duration) {
+    match runtime::installed() {
+        Some(_) => shim::advance(duration).await,
+        None => tokio::time::advance(duration).await,
+    }
+}
+
+/// Future returned by [`timeout`] and [`timeout_at`].
+///
+/// Mirrors the API of [`tokio::time::Timeout`], and adds [`Timeout::deadline`] and
+/// [`Timeout::reset`].
+#[derive(Debug)]
+#[pin_project::pin_project]
+pub struct Timeout<T> {
+    #[pin]
+    future: T,
+    #[pin]
+    delay: Sleep,
+}
+
+/// Requires a `Future` to complete before the specified duration has elapsed.
+///
+/// The timeout elapses by the [`current_deadline`] at the latest, see
+/// [`tokio::time::timeout`].
+///
+/// [`current_deadline`]: super::current_deadline
+pub fn timeout<F>(duration: Duration, future: F) -> Timeout<F::IntoFuture>
+where
+    F: IntoFuture,
+{
+    let now = now();
+    let deadline = now
+        .checked_add(duration)
+        // Same as tokio, a deadline roughly 30 years from now
+        .unwrap_or_else(|| now + Duration::from_secs(86400 * 365 * 30));
+    timeout_at(deadline, future)
+}
+
+/// Returns the current time of the clock of the installed [`Runtime`]'s timers, which
+/// can be paused, or the current time of tokio's clock if none is installed.
+///
+/// [`Runtime`]: crate::runtime::Runtime
+fn now() -> Instant {
+    match runtime::installed() {
+        Some(_) => Instant::from_std(shim::now()),
+        None => Instant::now(),
+    }
+}
+
+/// Requires a `Future` to complete before the specified instant in time.
+///
+/// The timeout elapses by the [`current_deadline`] at the latest, see
+/// [`tokio::time::timeout_at`].
+///
+/// [`current_deadline`]: super::current_deadline
+pub fn timeout_at<F>(deadline: Instant, future: F) -> Timeout<F::IntoFuture>
+where
+    F: IntoFuture,
+{
+    Timeout {
+        future: future.into_future(),
+        delay: sleep_until(deadline::clamp(deadline)),
+    }
+}
+
+impl<T: Future> Future for Timeout<T> {
+    type Output = Result<T::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        if let Poll::Ready(result) = this.future.poll(cx) {
+            return Poll::Ready(Ok(result));
+        }
+
+        this.delay.poll(cx).map(|()| Err(Elapsed(())))
+    }
+}
+
+impl<T> Timeout<T> {
+    /// Returns a reference of the wrapped future.
+    pub fn get_ref(&self) -> &T {
+        &self.future
+    }
+
+    /// Returns a mutable reference to the wrapped future.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.future
+    }
+
+    /// Returns the wrapped future and throws away and cancels the
+    /// associated timeout.
+    pub fn into_inner(self) -> T {
+        self.future
+    }
+
+    /// Returns the instant at which the timeout elapses.
+    pub fn deadline(&self) -> Instant {
+        self.delay.deadline()
+    }
+
+    /// Resets the timeout to elapse at `deadline`, or by the [`current_deadline`] at the
+    /// latest.
+    ///
+    /// This also works with timeouts that have elapsed already.
+    ///
+    /// [`current_deadline`]: super::current_deadline
+    pub fn reset(self: Pin<&mut Self>, deadline: Instant) {
+        self.project().delay.reset(deadline::clamp(deadline));
+    }
+}
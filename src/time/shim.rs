@@ -0,0 +1,466 @@
+#[cfg(shim)]
+use std::future::IntoFuture;
+#[cfg(all(shim, not(wasm_browser)))]
+pub use std::time::SystemTime;
+#[cfg(not(wasm_browser))]
+pub use std::time::{Duration, Instant};
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+pub(crate) use clock::now;
+use clock::Timer;
+pub use clock::{advance, pause, resume};
+#[cfg(wasm_browser)]
+pub use web_time::{Duration, Instant, SystemTime};
+
+#[cfg(shim)]
+use super::Elapsed;
+
+mod clock;
+
+/// Future that will wake up once its deadline is reached.
+#[derive(derive_more::Debug)]
+pub struct Sleep {
+    deadline: Instant,
+    elapsed: bool,
+    #[debug(skip)]
+    timer: Option<Timer>,
+}
+
+/// Sleeps for given duration
+pub fn sleep(duration: Duration) -> Sleep {
+    if let Some(deadline) = now().checked_add(duration) {
+        sleep_impl(deadline)
+    } else {
+        sleep_forever()
+    }
+}
+
+/// Sleeps until given deadline
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    sleep_impl(deadline)
+}
+
+fn sleep_impl(deadline: Instant) -> Sleep {
+    Sleep {
+        deadline,
+        elapsed: false,
+        timer: Some(Timer::new(deadline)),
+    }
+}
+
+fn sleep_forever() -> Sleep {
+    // fake a deadline that's far in the future (10 years)
+    let deadline = now() + Duration::from_secs(60 * 60 * 24 * 365 * 10);
+    Sleep {
+        deadline,
+        elapsed: false,
+        timer: None,
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let Some(timer) = self.timer.as_mut() else {
+            return Poll::Pending;
+        };
+        futures_lite::ready!(Pin::new(timer).poll(cx));
+        self.elapsed = true;
+        Poll::Ready(())
+    }
+}
+
+impl Sleep {
+    /// Returns the instant at which the sleep is scheduled to wake up
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Returns whether the sleep has reached its deadline.
+    pub fn is_elapsed(&self) -> bool {
+        self.elapsed || now() >= self.deadline
+    }
+
+    /// Resets this sleep's deadline to given instant.
+    ///
+    /// Also works with sleeps that have already reached their deadline
+    /// in the past.
+    pub fn reset(mut self: Pin<&mut Self>, deadline: Instant) {
+        let mut this = self.as_mut();
+        this.deadline = deadline;
+        this.elapsed = false;
+        // Moves the existing timer, instead of scheduling a new one
+        match &mut this.timer {
+            Some(timer) => timer.reset(deadline),
+            None => this.timer = Some(Timer::new(deadline)),
+        }
+    }
+
+    /// Resets this sleep to never wake up again (unless reset to a different timeout).
+    fn reset_forever(mut self: Pin<&mut Self>) {
+        let mut this = self.as_mut();
+        this.deadline = now() + Duration::from_secs(60 * 60 * 24 * 365 * 10);
+        this.elapsed = false;
+        this.timer = None;
+    }
+}
+
+#[cfg(shim)]
+/// Future that either resolves to [`Elapsed`] if the timeout
+/// is hit first. Otherwise, it resolves to `Ok` of the wrapped future.
+#[derive(Debug)]
+#[pin_project::pin_project]
+pub struct Timeout<T> {
+    #[pin]
+    future: T,
+    #[pin]
+    sleep: Sleep,
+}
+
+#[cfg(shim)]
+/// Timeout of a function in wasm.
+///
+/// The timeout elapses by the [`current_deadline`] at the latest.
+///
+/// [`current_deadline`]: crate::time::current_deadline
+pub fn timeout<F>(duration: Duration, future: F) -> Timeout<F::IntoFuture>
+where
+    F: IntoFuture,
+{
+    let sleep = match now().checked_add(duration).or_else(inherited) {
+        Some(deadline) => sleep_until(clamp(deadline)),
+        None => sleep_forever(),
+    };
+    Timeout {
+        future: future.into_future(),
+        sleep,
+    }
+}
+
+#[cfg(shim)]
+/// Timeout of a function in wasm, which elapses at `deadline`, or by the
+/// [`current_deadline`] at the latest.
+///
+/// [`current_deadline`]: crate::time::current_deadline
+pub fn timeout_at<F>(deadline: Instant, future: F) -> Timeout<F::IntoFuture>
+where
+    F: IntoFuture,
+{
+    Timeout {
+        future: future.into_future(),
+        sleep: sleep_until(clamp(deadline)),
+    }
+}
+
+#[cfg(shim)]
+/// Returns the [`current_deadline`] as an instant of the shim.
+///
+/// [`current_deadline`]: crate::time::current_deadline
+// The instants of `crate::time` are tokio's when the shim is compiled natively next to it
+#[allow(clippy::useless_conversion)]
+fn inherited() -> Option<Instant> {
+    super::current_deadline().map(|deadline| deadline.instant().into())
+}
+
+#[cfg(shim)]
+/// Returns the earlier one of `deadline` and the [`inherited`] deadline.
+fn clamp(deadline: Instant) -> Instant {
+    inherited().map_or(deadline, |inherited| inherited.min(deadline))
+}
+
+#[cfg(shim)]
+impl<T: Future> Future for Timeout<T> {
+    type Output = Result<T::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        if let Poll::Ready(result) = this.future.poll(cx) {
+            return Poll::Ready(Ok(result));
+        }
+
+        if let Poll::Ready(()) = this.sleep.poll(cx) {
+            return Poll::Ready(Err(Elapsed(())));
+        }
+
+        Poll::Pending
+    }
+}
+
+#[cfg(shim)]
+impl<T> Timeout<T> {
+    /// Returns a reference of the wrapped future.
+    pub fn get_ref(&self) -> &T {
+        &self.future
+    }
+
+    /// Returns a mutable reference to the wrapped future.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.future
+    }
+
+    /// Returns the wrapped future and throws away and cancels the
+    /// associated timeout.
+    pub fn into_inner(self) -> T {
+        self.future
+    }
+
+    /// Returns the instant at which the timeout elapses.
+    pub fn deadline(&self) -> Instant {
+        self.sleep.deadline()
+    }
+
+    /// Resets the timeout to elapse at `deadline`, or by the [`current_deadline`] at the
+    /// latest.
+    ///
+    /// This also works with timeouts that have elapsed already.
+    ///
+    /// [`current_deadline`]: crate::time::current_deadline
+    pub fn reset(self: Pin<&mut Self>, deadline: Instant) {
+        self.project().sleep.reset(clamp(deadline));
+    }
+}
+
+/// Defines the behavior of an [`Interval`] when it misses a tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MissedTickBehavior {
+    /// Ticks as fast as possible until caught up.
+    #[default]
+    Burst,
+
+    /// Tick at multiples of `period` from when [`Interval::tick`] was called, rather than
+    /// from `start`.
+    Delay,
+
+    /// Skips missed ticks and tick on the next multiple of `period` from
+    /// `start`.
+    Skip,
+}
+
+impl MissedTickBehavior {
+    /// If a tick is missed, this method is called to determine when the next tick should happen.
+    fn next_timeout(&self, timeout: Instant, now: Instant, period: Duration) -> Instant {
+        match self {
+            Self::Burst => timeout + period,
+            Self::Delay => now + period,
+            Self::Skip => {
+                now + period
+                    - Duration::from_nanos(
+                        ((now - timeout).as_nanos() % period.as_nanos())
+                            .try_into()
+                            // This operation is practically guaranteed not to
+                            // fail, as in order for it to fail, `period` would
+                            // have to be longer than `now - timeout`, and both
+                            // would have to be longer than 584 years.
+                            //
+                            // If it did fail, there's not a good way to pass
+                            // the error along to the user, so we just panic.
+                            .expect(
+                                "too much time has elapsed since the interval was supposed to tick",
+                            ),
+                    )
+            }
+        }
+    }
+}
+
+/// Interval returned by [`interval`] and [`interval_at`].
+#[derive(Debug)]
+pub struct Interval {
+    delay: Pin<Box<Sleep>>,
+    period: Duration,
+    missed_tick_behavior: MissedTickBehavior,
+}
+
+/// Creates new [`Interval`] that yields with interval of `period`. The first
+/// tick completes immediately. The default [`MissedTickBehavior`] is
+/// [`Burst`](MissedTickBehavior::Burst), but this can be configured
+/// by calling [`set_missed_tick_behavior`](Interval::set_missed_tick_behavior).
+///
+/// An interval will tick indefinitely. At any time, the [`Interval`] value can
+/// be dropped. This cancels the interval.
+///
+/// This function is equivalent to
+/// [`interval_at(Instant::now(), period)`](interval_at).
+pub fn interval(period: Duration) -> Interval {
+    assert!(period > Duration::new(0, 0), "`period` must be non-zero.");
+
+    interval_at(now(), period)
+}
+
+/// Creates new [`Interval`] that yields with interval of `period` with the
+/// first tick completing at `start`. The default [`MissedTickBehavior`] is
+/// [`Burst`](MissedTickBehavior::Burst), but this can be configured
+/// by calling [`set_missed_tick_behavior`](Interval::set_missed_tick_behavior).
+///
+/// An interval will tick indefinitely. At any time, the [`Interval`] value can
+/// be dropped. This cancels the interval.
+#[track_caller]
+pub fn interval_at(start: Instant, period: Duration) -> Interval {
+    assert!(period > Duration::new(0, 0), "`period` must be non-zero.");
+
+    let delay = Box::pin(sleep_until(start));
+
+    Interval {
+        delay,
+        period,
+        missed_tick_behavior: MissedTickBehavior::default(),
+    }
+}
+
+impl Interval {
+    /// Completes when the next instant in the interval has been reached.
+    #[cfg(shim)]
+    pub async fn tick(&mut self) -> Instant {
+        futures_lite::future::poll_fn(|cx| self.poll_tick(cx)).await
+    }
+
+    /// Polls for the next instant in the interval to be reached.
+    pub fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<Instant> {
+        // Wait for the delay to be done
+        futures_lite::ready!(Pin::new(&mut self.delay).poll(cx));
+
+        // Get the time when we were scheduled to tick
+        let timeout = self.delay.deadline();
+
+        let now = now();
+
+        // If a tick was not missed, and thus we are being called before the
+        // next tick is due, just schedule the next tick normally, one `period`
+        // after `timeout`
+        //
+        // However, if a tick took excessively long and we are now behind,
+        // schedule the next tick according to how the user specified with
+        // `MissedTickBehavior`
+        let next = if now > timeout + Duration::from_millis(5) {
+            Some(
+                self.missed_tick_behavior
+                    .next_timeout(timeout, now, self.period),
+            )
+        } else {
+            timeout.checked_add(self.period)
+        };
+
+        if let Some(next) = next {
+            self.delay.as_mut().reset(next);
+        } else {
+            self.delay.as_mut().reset_forever()
+        }
+
+        // Return the time when we were scheduled to tick
+        Poll::Ready(timeout)
+    }
+
+    /// Resets the interval to complete one period after the current time.
+    pub fn reset(&mut self) {
+        self.delay.as_mut().reset(now() + self.period);
+    }
+
+    /// Resets the interval immediately.
+    pub fn reset_immediately(&mut self) {
+        self.delay.as_mut().reset(now());
+    }
+
+    /// Resets the interval after the specified [`std::time::Duration`].
+    pub fn reset_after(&mut self, after: Duration) {
+        self.delay.as_mut().reset(now() + after);
+    }
+
+    /// Resets the interval to a [`crate::time::Instant`] deadline.
+    pub fn reset_at(&mut self, deadline: Instant) {
+        self.delay.as_mut().reset(deadline);
+    }
+
+    /// Returns the [`MissedTickBehavior`] strategy currently being used.
+    pub fn missed_tick_behavior(&self) -> MissedTickBehavior {
+        self.missed_tick_behavior
+    }
+
+    /// Sets the [`MissedTickBehavior`] strategy that should be used.
+    pub fn set_missed_tick_behavior(&mut self, behavior: MissedTickBehavior) {
+        self.missed_tick_behavior = behavior;
+    }
+
+    /// Returns the period of the interval.
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use futures_lite::future::BoxedLocal;
+    #[cfg(not(wasm_browser))]
+    use tokio::test;
+    #[cfg(wasm_browser)]
+    use wasm_bindgen_test::wasm_bindgen_test as test;
+
+    use super::*;
+    use crate::{
+        boxed::BoxFuture,
+        runtime::{self, Runtime},
+    };
+
+    /// Completes timers after at most a millisecond, like `setTimeout` does for durations
+    /// longer than it supports.
+    #[derive(Debug)]
+    struct EarlyTimers {
+        inner: Arc<dyn Runtime>,
+        timers: AtomicUsize,
+    }
+
+    impl Runtime for EarlyTimers {
+        fn spawn(&self, fut: BoxFuture<()>) {
+            self.inner.spawn(fut)
+        }
+
+        fn spawn_local(&self, fut: BoxedLocal<()>) {
+            self.inner.spawn_local(fut)
+        }
+
+        fn sleep(&self, duration: Duration) -> BoxFuture<()> {
+            self.timers.fetch_add(1, Ordering::SeqCst);
+            self.inner.sleep(duration.min(Duration::from_millis(1)))
+        }
+
+        fn now(&self) -> Instant {
+            self.inner.now()
+        }
+    }
+
+    #[test]
+    async fn sleep_rearms_early_timers() {
+        let runtime = Arc::new(EarlyTimers {
+            inner: runtime::current(),
+            timers: AtomicUsize::new(0),
+        });
+        let start = now();
+        runtime::with_runtime(runtime.clone(), sleep(Duration::from_millis(10))).await;
+        assert!(now() >= start + Duration::from_millis(10));
+        assert!(runtime.timers.load(Ordering::SeqCst) > 0);
+    }
+
+    #[test]
+    async fn pause_only_affects_timers_of_current_thread() {
+        pause();
+        let frozen = sleep(Duration::ZERO).deadline();
+        advance(Duration::from_secs(60)).await;
+        let advanced = frozen + Duration::from_secs(60);
+        assert_eq!(sleep(Duration::ZERO).deadline(), advanced);
+        // Neither `Instant::now` nor the clocks of other threads follow the paused clock
+        assert!(Instant::now() < advanced);
+        #[cfg(not(wasm_browser))]
+        assert!(std::thread::spawn(now).join().unwrap() < advanced);
+        resume();
+    }
+}
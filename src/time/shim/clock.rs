@@ -0,0 +1,282 @@
+//! The clock of the time shim, which can be frozen and moved forward manually using
+//! [`pause`], [`advance`] and [`resume`].
+
+use std::{
+    cell::RefCell,
+    collections::{btree_map::Entry, BTreeMap},
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+};
+
+#[cfg(wasm_browser)]
+use send_wrapper::SendWrapper;
+
+use super::{Duration, Instant};
+use crate::runtime::{self, BoxTimer};
+
+thread_local! {
+    /// The clock of the current thread.
+    static CLOCK: RefCell<Clock> = RefCell::new(Clock {
+        offset: Duration::ZERO,
+        frozen: None,
+        timers: Default::default(),
+    });
+}
+
+/// Identifies the timers, in case that several have the same deadline.
+static TIMER_ID: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug)]
+struct Clock {
+    /// How far the clock was moved ahead of the runtime's clock.
+    offset: Duration,
+    /// The time the clock is frozen at, while time is paused.
+    frozen: Option<Instant>,
+    /// The wakers of the timers that are pending while time is paused.
+    timers: Timers,
+}
+
+/// The wakers of pending timers, by their deadline and id.
+///
+/// They're shared with the timers, which might be dropped on another thread than the one
+/// whose clock they were registered with.
+type Timers = Arc<Mutex<BTreeMap<(Instant, u64), Waker>>>;
+
+impl Clock {
+    fn now(&self) -> Instant {
+        self.frozen.unwrap_or_else(|| runtime::now() + self.offset)
+    }
+}
+
+/// Returns the current time of the clock.
+pub(crate) fn now() -> Instant {
+    CLOCK.with_borrow(Clock::now)
+}
+
+/// Pauses time.
+///
+/// The clock is frozen, and timers only fire once it's moved forward using [`advance`].
+/// Unlike tokio's clock, it isn't moved forward automatically once all tasks are idle.
+///
+/// The clock is local to the current thread: only the timers polled on it are paused,
+/// while timers polled on other threads, e.g. by the executor threads of the runtime,
+/// keep following the real time. Timers that are pending already when time is paused
+/// only follow the paused clock once they're polled again.
+///
+/// `Instant::now` keeps returning the real time, too. Deadlines passed to
+/// [`sleep_until`] and [`timeout_at`] must be based on the paused clock instead, e.g. on
+/// the [`Sleep::deadline`] of a timer created after pausing.
+///
+/// # Panics
+///
+/// Panics if time is already paused.
+///
+/// [`sleep_until`]: super::sleep_until
+/// [`timeout_at`]: super::timeout_at
+/// [`Sleep::deadline`]: super::Sleep::deadline
+#[track_caller]
+pub fn pause() {
+    CLOCK.with_borrow_mut(|clock| {
+        assert!(clock.frozen.is_none(), "time is already frozen");
+        clock.frozen = Some(clock.now());
+    })
+}
+
+/// Resumes time.
+///
+/// The clock continues from the time it was moved forward to using [`advance`].
+///
+/// # Panics
+///
+/// Panics if time isn't paused.
+#[track_caller]
+pub fn resume() {
+    let timers = CLOCK.with_borrow_mut(|clock| {
+        let frozen = clock.frozen.take().expect("time is not frozen");
+        clock.offset = frozen.saturating_duration_since(runtime::now());
+        std::mem::take(&mut *clock.timers.lock().expect("poisoned"))
+    });
+    // The timers are scheduled on the runtime once they're polled again
+    timers.into_values().for_each(Waker::wake);
+}
+
+/// Moves the paused clock forward by `duration`, firing the timers that elapsed.
+///
+/// Yields once afterwards, so that the tasks waiting on the timers can run.
+///
+/// # Panics
+///
+/// Panics if time isn't paused.
+pub async fn advance(duration: Duration) {
+    let due = CLOCK.with_borrow_mut(|clock| {
+        let frozen = clock.frozen.as_mut().expect("time is not frozen");
+        *frozen += duration;
+        let mut timers = clock.timers.lock().expect("poisoned");
+        let pending = timers.split_off(&(*frozen, u64::MAX));
+        std::mem::replace(&mut *timers, pending)
+    });
+    due.into_values().for_each(Waker::wake);
+    crate::future::yield_now().await;
+}
+
+/// A timer that completes once the clock reaches its deadline, and that's cancelled
+/// when dropped.
+///
+/// While time isn't paused, this waits on a timer scheduled on the current runtime, until
+/// the deadline is reached. The runtime's timer is reused when this is reset.
+#[derive(derive_more::Debug)]
+pub(super) struct Timer {
+    deadline: Instant,
+    id: u64,
+    #[debug(skip)]
+    scheduled: Option<Scheduled>,
+    /// The timers of the paused clock this timer is registered with, if any.
+    #[debug(skip)]
+    registered: Option<Timers>,
+}
+
+#[cfg(not(wasm_browser))]
+type Scheduled = BoxTimer;
+#[cfg(wasm_browser)]
+type Scheduled = SendWrapper<BoxTimer>;
+
+impl Timer {
+    pub(super) fn new(deadline: Instant) -> Self {
+        Self {
+            deadline,
+            id: TIMER_ID.fetch_add(1, Ordering::Relaxed),
+            scheduled: None,
+            registered: None,
+        }
+    }
+
+    /// Moves the timer to `deadline`, resetting the runtime's timer if it's scheduled.
+    pub(super) fn reset(&mut self, deadline: Instant) {
+        // The paused clock's timers are ordered by deadline, so the timer registers again
+        // once it's polled
+        self.deregister();
+        self.deadline = deadline;
+        let remaining = deadline.saturating_duration_since(now());
+        if let Some(scheduled) = &mut self.scheduled {
+            if !remaining.is_zero() {
+                scheduled.as_mut().reset(remaining);
+            }
+        }
+    }
+
+    /// Registers the waker to be woken by [`advance`] and [`resume`] if time is paused,
+    /// returning the time the clock is frozen at.
+    fn register(&mut self, waker: &Waker) -> Option<Instant> {
+        CLOCK.with_borrow(|clock| {
+            let frozen = clock.frozen?;
+            let registered = self
+                .registered
+                .as_ref()
+                .is_some_and(|timers| Arc::ptr_eq(timers, &clock.timers));
+            if !registered {
+                // The timer moved to another thread
+                self.deregister();
+                self.registered = Some(clock.timers.clone());
+            }
+            let mut timers = clock.timers.lock().expect("poisoned");
+            match timers.entry((self.deadline, self.id)) {
+                Entry::Occupied(mut entry) => entry.get_mut().clone_from(waker),
+                Entry::Vacant(entry) => {
+                    entry.insert(waker.clone());
+                }
+            }
+            Some(frozen)
+        })
+    }
+
+    /// Removes the waker from the clock it was registered with.
+    fn deregister(&mut self) {
+        if let Some(timers) = self.registered.take() {
+            timers
+                .lock()
+                .expect("poisoned")
+                .remove(&(self.deadline, self.id));
+        }
+    }
+}
+
+impl Future for Timer {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = &mut *self;
+        match this.register(cx.waker()) {
+            Some(frozen) if frozen >= this.deadline => {}
+            Some(_) => {
+                this.scheduled = None;
+                return Poll::Pending;
+            }
+            None => loop {
+                // Timers that are due already complete without scheduling a timer
+                let remaining = this.deadline.saturating_duration_since(now());
+                if remaining.is_zero() {
+                    break;
+                }
+                let scheduled = match &mut this.scheduled {
+                    Some(scheduled) => scheduled,
+                    None => this.scheduled.insert(schedule(remaining)),
+                };
+                futures_lite::ready!(scheduled.as_mut().poll(cx));
+                let remaining = this.deadline.saturating_duration_since(now());
+                if remaining.is_zero() {
+                    break;
+                }
+                // The runtime's timer completed early, e.g. because `setTimeout` doesn't
+                // support long durations, so it's reset to wait for the rest.
+                scheduled.as_mut().reset(remaining);
+            },
+        }
+        this.deregister();
+        Poll::Ready(())
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.deregister();
+    }
+}
+
+/// Schedules a timer on the current runtime.
+fn schedule(duration: Duration) -> Scheduled {
+    let timer = runtime::current().timer(duration);
+    #[cfg(wasm_browser)]
+    let timer = SendWrapper::new(timer);
+    timer
+}
+
+#[cfg(all(test, not(wasm_browser)))]
+mod tests {
+    use super::*;
+
+    fn paused_timers() -> usize {
+        CLOCK.with_borrow(|clock| clock.timers.lock().unwrap().len())
+    }
+
+    #[tokio::test]
+    async fn timers_deregister_from_their_clock() {
+        let mut timer = Box::pin(Timer::new(now() + Duration::from_secs(60)));
+        let mut cx = Context::from_waker(Waker::noop());
+        // Timers only register with the clock while time is paused
+        assert!(timer.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(paused_timers(), 0);
+
+        pause();
+        assert!(timer.as_mut().poll(&mut cx).is_pending());
+        assert_eq!(paused_timers(), 1);
+        // Dropping the timer on another thread removes it from this thread's clock
+        std::thread::spawn(move || drop(timer)).join().unwrap();
+        assert_eq!(paused_timers(), 0);
+        resume();
+    }
+}
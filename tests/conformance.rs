@@ -0,0 +1,519 @@
+//! Behavioral tests for the [`task`] and [`time`] modules.
+//!
+//! These run against tokio natively and against the shim in browsers, to make sure
+//! that both behave the same. Natively, they also run with a runtime installed using
+//! `runtime::with_runtime`, with the `smol` feature, with `runtime::SmolRuntime` installed,
+//! and with the `shim` feature, against the shim.
+//!
+//! [`task`]: n0_future::task
+//! [`time`]: n0_future::time
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use n0_future::task::{self, AbortOnDropHandle};
+#[cfg(not(wasm_browser))]
+use tokio::test;
+#[cfg(wasm_browser)]
+use wasm_bindgen_test::wasm_bindgen_test as test;
+
+/// Sets the flag once dropped, i.e. once the task owning it finished or was aborted.
+struct SetOnDrop(Arc<AtomicBool>);
+
+impl Drop for SetOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Generates the tests in a `native` module, which runs them against [`n0_future::task`]
+/// and [`n0_future::time`], in a `custom_runtime` module, which runs them against the same
+/// modules with a runtime installed, in a `smol` module, which runs them with
+/// `n0_future::runtime::SmolRuntime` installed when the `smol` feature is enabled, and in a
+/// `shim` module, which runs them against `n0_future::shim` when the `shim` feature is
+/// enabled.
+macro_rules! conformance {
+    ($($(#[$meta:meta])* async fn $name:ident() $body:block)*) => {
+        mod native {
+            use n0_future::{
+                task::{self, JoinSet},
+                time::{self, Instant, MissedTickBehavior},
+            };
+            #[cfg(not(wasm_browser))]
+            use tokio::test;
+            #[cfg(wasm_browser)]
+            use wasm_bindgen_test::wasm_bindgen_test as test;
+
+            use super::*;
+
+            conformance!(@helpers);
+
+            $(
+                $(#[$meta])*
+                #[test]
+                async fn $name() $body
+            )*
+        }
+
+        #[cfg(not(wasm_browser))]
+        mod custom_runtime {
+            use n0_future::{
+                runtime::{self, TokioRuntime},
+                task::{self, JoinSet},
+                time::{self, Instant, MissedTickBehavior},
+            };
+            use tokio::test;
+
+            use super::*;
+
+            conformance!(@helpers);
+
+            $(
+                $(#[$meta])*
+                #[test]
+                async fn $name() {
+                    runtime::with_runtime(TokioRuntime, async $body).await
+                }
+            )*
+        }
+
+        #[cfg(all(feature = "smol", not(wasm_browser)))]
+        mod smol {
+            use n0_future::{
+                runtime::SmolRuntime,
+                task::{self, JoinSet},
+                time::{self, Instant, MissedTickBehavior},
+            };
+
+            use super::{Arc, AtomicBool, Duration, Ordering, SetOnDrop};
+
+            conformance!(@helpers);
+
+            $(
+                $(#[$meta])*
+                #[test]
+                fn $name() {
+                    SmolRuntime::block_on(async $body)
+                }
+            )*
+        }
+
+        #[cfg(all(feature = "shim", not(wasm_browser)))]
+        mod shim {
+            use n0_future::shim::{
+                block_on,
+                task::{self, JoinSet},
+                time::{self, Instant, MissedTickBehavior},
+            };
+
+            use super::{Arc, AtomicBool, Duration, Ordering, SetOnDrop};
+
+            conformance!(@helpers);
+
+            $(
+                $(#[$meta])*
+                #[test]
+                fn $name() {
+                    block_on(async $body)
+                }
+            )*
+        }
+    };
+    (@helpers) => {
+        pub(super) async fn wait_until(mut condition: impl FnMut() -> bool) {
+            time::timeout(Duration::from_secs(1), async {
+                while !condition() {
+                    time::sleep(Duration::from_millis(1)).await;
+                }
+            })
+            .await
+            .expect("condition wasn't met in time");
+        }
+    };
+}
+
+conformance! {
+    // task::spawn and JoinHandle
+
+    async fn join_handle_output() {
+        let handle = task::spawn(async { 42 });
+        assert_eq!(handle.await.unwrap(), 42);
+    }
+
+    async fn join_handle_abort() {
+        let handle = task::spawn(std::future::pending::<()>());
+        assert!(!handle.is_finished());
+        handle.abort();
+
+        let err = handle.await.unwrap_err();
+        assert!(err.is_cancelled());
+        assert!(!err.is_panic());
+    }
+
+    async fn join_handle_abort_after_completion() {
+        let handle = task::spawn(async { 42 });
+        wait_until(|| handle.is_finished()).await;
+
+        // Aborting a task that already completed has no effect
+        handle.abort();
+        assert_eq!(handle.await.unwrap(), 42);
+    }
+
+    async fn join_handle_detach_on_drop() {
+        let flag = Arc::new(AtomicBool::new(false));
+        drop(task::spawn({
+            let flag = flag.clone();
+            async move {
+                time::sleep(Duration::from_millis(1)).await;
+                flag.store(true, Ordering::SeqCst);
+            }
+        }));
+        wait_until(|| flag.load(Ordering::SeqCst)).await;
+    }
+
+    async fn abort_handle_is_finished() {
+        let handle = task::spawn(async {
+            time::sleep(Duration::from_millis(5)).await;
+        });
+        let abort = handle.abort_handle();
+        assert_eq!(abort.id(), handle.id());
+        assert!(!abort.is_finished());
+        handle.await.unwrap();
+        assert!(abort.is_finished());
+
+        let handle = task::spawn(std::future::pending::<()>());
+        let abort = handle.abort_handle();
+        abort.abort();
+        assert!(handle.await.unwrap_err().is_cancelled());
+        assert!(abort.is_finished());
+    }
+
+    async fn aborted_task_finishes_once_dropped() {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let guard = SetOnDrop(dropped.clone());
+        let handle = task::spawn(async move {
+            let _guard = guard;
+            std::future::pending::<()>().await
+        });
+        let abort = handle.abort_handle();
+        abort.abort();
+        // The task's future is only dropped once the runtime gets to it, which can happen
+        // right away on multi-threaded runtimes
+        let finished = abort.is_finished();
+        assert!(!finished || dropped.load(Ordering::SeqCst));
+        wait_until(|| abort.is_finished()).await;
+        assert!(dropped.load(Ordering::SeqCst));
+        assert!(handle.await.unwrap_err().is_cancelled());
+    }
+
+    async fn join_error_display() {
+        let handle = task::spawn(std::future::pending::<()>());
+        let id = handle.id();
+        handle.abort();
+        let err = handle.await.unwrap_err();
+        assert_eq!(err.id(), id);
+        assert_eq!(err.to_string(), format!("task {id} was cancelled"));
+
+        let err = std::io::Error::from(err);
+        assert_eq!(err.kind(), std::io::ErrorKind::Other);
+        assert_eq!(err.to_string(), "task was cancelled");
+    }
+
+    #[cfg(panic = "unwind")]
+    async fn join_error_panic() {
+        let handle = task::spawn(async {
+            panic!("boom");
+        });
+        let id = handle.id();
+
+        let err = handle.await.unwrap_err();
+        assert!(err.is_panic());
+        assert!(!err.is_cancelled());
+        assert_eq!(
+            err.to_string(),
+            format!("task {id} panicked with message \"boom\"")
+        );
+        let err = err.try_into_panic().unwrap();
+        assert_eq!(*err.downcast::<&str>().unwrap(), "boom");
+    }
+
+    // JoinSet
+
+    async fn join_set_len() {
+        let mut set = JoinSet::new();
+        assert!(set.is_empty());
+        assert!(set.join_next().await.is_none());
+
+        set.spawn(async { 1 });
+        set.spawn(async { 2 });
+        assert_eq!(set.len(), 2);
+
+        let mut out = vec![
+            set.join_next().await.unwrap().unwrap(),
+            set.join_next().await.unwrap().unwrap(),
+        ];
+        out.sort();
+        assert_eq!(out, [1, 2]);
+        assert!(set.is_empty());
+        assert!(set.join_next().await.is_none());
+    }
+
+    async fn join_set_join_next_with_id() {
+        let mut set = JoinSet::new();
+        let ok = set.spawn(async { 1 });
+        let aborted = set.spawn(std::future::pending::<u32>());
+        aborted.abort();
+
+        let mut seen = 0;
+        while let Some(res) = set.join_next_with_id().await {
+            match res {
+                Ok((id, out)) => {
+                    assert_eq!(id, ok.id());
+                    assert_eq!(out, 1);
+                }
+                Err(err) => {
+                    assert_eq!(err.id(), aborted.id());
+                    assert!(err.is_cancelled());
+                }
+            }
+            seen += 1;
+        }
+        assert_eq!(seen, 2);
+    }
+
+    async fn join_set_abort_all() {
+        let mut set = JoinSet::new();
+        for _ in 0..3 {
+            set.spawn(std::future::pending::<()>());
+        }
+        set.abort_all();
+
+        let mut cancelled = 0;
+        while let Some(res) = set.join_next().await {
+            assert!(res.unwrap_err().is_cancelled());
+            cancelled += 1;
+        }
+        assert_eq!(cancelled, 3);
+    }
+
+    async fn join_set_shutdown() {
+        let mut set = JoinSet::new();
+        set.spawn(std::future::pending::<()>());
+        set.spawn(async {});
+        set.shutdown().await;
+        assert!(set.is_empty());
+    }
+
+    async fn join_set_join_all() {
+        let mut set = JoinSet::new();
+        for i in 0..5 {
+            set.spawn(async move {
+                time::sleep(Duration::from_millis(5 - i)).await;
+                i
+            });
+        }
+        let mut out = set.join_all().await;
+        out.sort();
+        assert_eq!(out, [0, 1, 2, 3, 4]);
+    }
+
+    #[cfg(panic = "unwind")]
+    async fn join_set_join_all_panic() {
+        use n0_future::FutureExt;
+
+        let dropped = Arc::new(AtomicBool::new(false));
+        let mut set = JoinSet::new();
+        set.spawn({
+            let guard = SetOnDrop(dropped.clone());
+            async move {
+                let _guard = guard;
+                std::future::pending::<()>().await;
+            }
+        });
+        set.spawn(async {
+            panic!("boom");
+        });
+
+        // The original panic is resumed, and the remaining tasks are aborted
+        let payload = std::panic::AssertUnwindSafe(set.join_all())
+            .catch_unwind()
+            .await
+            .unwrap_err();
+        assert_eq!(*payload.downcast::<&str>().unwrap(), "boom");
+        wait_until(|| dropped.load(Ordering::SeqCst)).await;
+    }
+
+    async fn join_set_drop_aborts() {
+        let dropped = Arc::new(AtomicBool::new(false));
+        let mut set = JoinSet::new();
+        set.spawn({
+            let guard = SetOnDrop(dropped.clone());
+            async move {
+                let _guard = guard;
+                std::future::pending::<()>().await;
+            }
+        });
+        drop(set);
+        wait_until(|| dropped.load(Ordering::SeqCst)).await;
+    }
+
+    // time
+
+    async fn sleep_elapses() {
+        let start = Instant::now();
+        let sleep = time::sleep(Duration::from_millis(10));
+        assert!(sleep.deadline() >= start + Duration::from_millis(10));
+        assert!(!sleep.is_elapsed());
+
+        let mut sleep = std::pin::pin!(sleep);
+        sleep.as_mut().await;
+        assert!(sleep.is_elapsed());
+        assert!(start.elapsed() >= Duration::from_millis(10));
+    }
+
+    async fn sleep_reset() {
+        let start = Instant::now();
+        let mut sleep = std::pin::pin!(time::sleep(Duration::from_secs(60)));
+        sleep.as_mut().reset(start + Duration::from_millis(5));
+        assert_eq!(sleep.deadline(), start + Duration::from_millis(5));
+        time::timeout(Duration::from_secs(1), sleep)
+            .await
+            .expect("reset sleep didn't elapse");
+    }
+
+    async fn sleep_until_past_deadline() {
+        let deadline = Instant::now();
+        time::sleep(Duration::from_millis(1)).await;
+        time::timeout(Duration::from_secs(1), time::sleep_until(deadline))
+            .await
+            .expect("sleep with a past deadline didn't elapse");
+    }
+
+    async fn timeout_completes() {
+        let res = time::timeout(Duration::from_secs(1), async { 42 }).await;
+        assert_eq!(res.unwrap(), 42);
+
+        // A ready future completes even with a zero timeout
+        let res = time::timeout(Duration::ZERO, async { 42 }).await;
+        assert_eq!(res.unwrap(), 42);
+    }
+
+    async fn timeout_elapses() {
+        let err = time::timeout(Duration::from_millis(1), std::future::pending::<()>())
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "deadline has elapsed");
+
+        let err = std::io::Error::from(err);
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+    }
+
+    async fn timeout_into_inner() {
+        let timeout = time::timeout(Duration::from_millis(1), async { 42 });
+        assert_eq!(timeout.into_inner().await, 42);
+    }
+
+    async fn timeout_at_reset() {
+        let deadline = Instant::now() + Duration::from_millis(5);
+        let timeout = time::timeout_at(deadline, std::future::pending::<()>());
+        assert_eq!(timeout.deadline(), deadline);
+
+        // Extends the deadline, like an idle timeout does on activity
+        let mut timeout = std::pin::pin!(timeout);
+        assert!(n0_future::future::poll_once(timeout.as_mut()).await.is_none());
+        let extended = Instant::now() + Duration::from_millis(20);
+        timeout.as_mut().reset(extended);
+        assert_eq!(timeout.deadline(), extended);
+        assert!(timeout.await.is_err());
+        assert!(Instant::now() >= extended);
+    }
+
+    async fn interval_ticks() {
+        let start = Instant::now();
+        let period = Duration::from_millis(5);
+        let mut interval = time::interval(period);
+        assert_eq!(interval.period(), period);
+        assert_eq!(interval.missed_tick_behavior(), MissedTickBehavior::Burst);
+
+        // The first tick completes immediately
+        let first = interval.tick().await;
+        assert!(first >= start && first - start < period);
+        let second = interval.tick().await;
+        assert_eq!(second, first + period);
+        assert!(Instant::now() >= second);
+    }
+
+    async fn interval_at_and_reset() {
+        let start = Instant::now() + Duration::from_millis(5);
+        let period = Duration::from_millis(5);
+        let mut interval = time::interval_at(start, period);
+        assert_eq!(interval.tick().await, start);
+
+        let reset_at = Instant::now();
+        interval.reset();
+        let tick = interval.tick().await;
+        assert!(tick >= reset_at + period);
+    }
+
+    async fn interval_missed_tick_delay() {
+        let period = Duration::from_millis(5);
+        let mut interval = time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        interval.tick().await;
+
+        // Miss a couple of ticks
+        time::sleep(period * 3).await;
+        let now = Instant::now();
+        let missed = interval.tick().await;
+        // The missed tick fires immediately, and the next one a period afterwards
+        let next = interval.tick().await;
+        assert!(next >= now + period);
+        assert!(next > missed);
+    }
+
+    async fn pause_and_advance() {
+        time::pause();
+        let mut sleep = std::pin::pin!(time::sleep(Duration::from_secs(60)));
+        let timeout = time::timeout(Duration::from_secs(30), std::future::pending::<()>());
+        let mut interval = time::interval(Duration::from_secs(10));
+        let first = interval.tick().await;
+
+        time::advance(Duration::from_secs(30)).await;
+        assert!(timeout.await.is_err());
+        assert!(n0_future::future::poll_once(sleep.as_mut()).await.is_none());
+        assert!(!sleep.is_elapsed());
+        // The interval missed two ticks, and catches up immediately
+        assert_eq!(interval.tick().await, first + Duration::from_secs(10));
+
+        time::advance(Duration::from_secs(30)).await;
+        assert!(sleep.is_elapsed());
+        sleep.await;
+        time::resume();
+    }
+}
+
+// AbortOnDropHandle
+
+#[test]
+async fn abort_on_drop_handle() {
+    let handle = AbortOnDropHandle::new(task::spawn(async { 42 }));
+    assert_eq!(handle.await.unwrap(), 42);
+
+    let dropped = Arc::new(AtomicBool::new(false));
+    let handle = AbortOnDropHandle::new(task::spawn({
+        let guard = SetOnDrop(dropped.clone());
+        async move {
+            let _guard = guard;
+            std::future::pending::<()>().await;
+        }
+    }));
+    let abort = handle.abort_handle();
+    drop(handle);
+    native::wait_until(|| dropped.load(Ordering::SeqCst)).await;
+    assert!(abort.is_finished());
+}
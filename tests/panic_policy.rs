@@ -0,0 +1,98 @@
+//! Tests for the utilities built on top of `JoinSet`s, with the global [`PanicPolicy`] set
+//! to [`PanicPolicy::Resume`].
+//!
+//! These live in their own test binary, since the global policy applies to the whole
+//! process.
+//!
+//! [`PanicPolicy`]: n0_future::task::PanicPolicy
+//! [`PanicPolicy::Resume`]: n0_future::task::PanicPolicy::Resume
+
+#![cfg(panic = "unwind")]
+
+use std::time::Duration;
+
+use n0_future::task::{
+    self, BoundedJoinSet, JoinMap, PanicPolicy, RestartPolicy, ScopeError, Supervisor,
+    SupervisorEvent,
+};
+#[cfg(not(wasm_browser))]
+use tokio::test;
+#[cfg(wasm_browser)]
+use wasm_bindgen_test::wasm_bindgen_test as test;
+
+#[test]
+async fn scope_returns_panic_with_resume_policy() {
+    task::set_panic_policy(PanicPolicy::Resume);
+    let res = task::scope(|scope| async move {
+        scope.spawn(async { panic!("boom") });
+        Ok::<_, ()>(())
+    })
+    .await;
+    match res {
+        Err(ScopeError::Panic(err)) => assert_eq!(err.panic_message(), Some("boom")),
+        res => panic!("unexpected result: {res:?}"),
+    }
+}
+
+#[test]
+async fn supervisor_restarts_panic_with_resume_policy() {
+    task::set_panic_policy(PanicPolicy::Resume);
+    let policy = RestartPolicy::new()
+        .max_restarts(1, Duration::from_secs(60))
+        .backoff(Duration::from_millis(1), Duration::from_millis(1));
+    let mut supervisor = Supervisor::<()>::new(policy);
+    supervisor.spawn("child", || async { panic!("boom") });
+
+    let mut panics = 0;
+    while let Some(event) = supervisor.next_event().await {
+        if let SupervisorEvent::Panicked { .. } = event {
+            panics += 1;
+        }
+    }
+    assert_eq!(panics, 2);
+    assert!(supervisor.is_empty());
+}
+
+#[test]
+async fn join_map_removes_panicked_task_before_resuming() {
+    use std::panic::AssertUnwindSafe;
+
+    use n0_future::FutureExt;
+
+    task::set_panic_policy(PanicPolicy::Resume);
+    let mut map = JoinMap::new();
+    map.spawn("panics", async { panic!("boom") });
+    let payload = AssertUnwindSafe(map.join_next())
+        .catch_unwind()
+        .await
+        .unwrap_err();
+    assert_eq!(payload.downcast_ref::<&str>(), Some(&"boom"));
+    assert!(map.is_empty());
+}
+
+#[test]
+async fn bounded_join_set_shutdown_ignores_resume_policy() {
+    task::set_panic_policy(PanicPolicy::Resume);
+    let mut set = BoundedJoinSet::new(2);
+    set.spawn(async { panic!("boom") }).await;
+    set.spawn(std::future::pending()).await;
+    n0_future::time::sleep(Duration::from_millis(1)).await;
+    set.shutdown().await;
+    assert!(set.is_empty());
+}
+
+#[cfg(all(feature = "shim", not(wasm_browser)))]
+#[test]
+async fn shim_join_set_shutdown_ignores_resume_policy() {
+    use n0_future::shim;
+
+    task::set_panic_policy(PanicPolicy::Resume);
+    shim::block_on(async {
+        let mut set = shim::task::JoinSet::new();
+        set.spawn(async { panic!("boom") });
+        set.spawn(std::future::pending::<()>());
+        shim::time::sleep(Duration::from_millis(1)).await;
+        set.shutdown().await;
+        assert!(set.is_empty());
+    });
+}
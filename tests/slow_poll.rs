@@ -0,0 +1,54 @@
+//! Tests for the [`SlowPollDetector`] installed globally for all spawned tasks.
+//!
+//! These live in their own test binary, since the installed detector can't be
+//! uninstalled, and applies to the whole process.
+//!
+//! [`SlowPollDetector`]: n0_future::task::SlowPollDetector
+
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use n0_future::{
+    task::{self, SlowPollDetector},
+    time::Instant,
+};
+#[cfg(not(wasm_browser))]
+use tokio::test;
+#[cfg(wasm_browser)]
+use wasm_bindgen_test::wasm_bindgen_test as test;
+
+/// Blocks the thread, to have the future spend time in a poll.
+fn busy(duration: Duration) {
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        std::hint::spin_loop();
+    }
+}
+
+#[test]
+async fn detects_slow_polls_of_tasks() {
+    let polls = Arc::new(Mutex::new(Vec::new()));
+    let detector = SlowPollDetector::new(Duration::from_millis(5)).on_slow_poll({
+        let polls = polls.clone();
+        move |poll| polls.lock().unwrap().push(poll.clone())
+    });
+    detector.install().unwrap();
+    let err = SlowPollDetector::new(Duration::ZERO).install().unwrap_err();
+    assert_eq!(err.to_string(), "a slow poll detector is installed already");
+
+    task::Builder::new()
+        .name("blocking")
+        .spawn(async { busy(Duration::from_millis(10)) })
+        .await
+        .unwrap();
+
+    let polls = polls.lock().unwrap();
+    let poll = polls
+        .iter()
+        .find(|poll| poll.name() == Some("blocking"))
+        .expect("slow poll is reported");
+    assert_eq!(poll.location().file(), file!());
+    assert!(poll.to_string().contains("task \"blocking\""));
+}